@@ -0,0 +1,338 @@
+// Copyright 2018 Michael Lamparski
+// Part of the vasp-poscar crate.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Bond and coordination analysis.
+
+use crate::Poscar;
+
+/// Decides which pairs of sites are bonded, for [`Poscar::bonds`].
+///
+/// [`Poscar::bonds`]: struct.Poscar.html#method.bonds
+#[derive(Debug, Clone, PartialEq)]
+pub enum BondCriterion {
+    /// Explicit cutoffs for unordered pairs of species labels.
+    ///
+    /// Two sites are bonded if they are within the cutoff for their species.
+    /// Pairs of species that are not listed never bond.
+    PairCutoffs(Vec<(String, String, f64)>),
+
+    /// Sites are bonded if their distance is at most `scale * (r1 + r2)`,
+    /// where `r1` and `r2` are the covalent radii of the two elements.
+    ///
+    /// Radii are taken from B. Cordero et al., *Dalton Trans.* 2832-2838 (2008),
+    /// using the low-spin values for Mn, Fe, and Co.  A `scale` of around `1.1`
+    /// to `1.3` is typical.
+    ///
+    /// This requires symbols.  To support symbols such as `Fe_pv` or `Fe1`,
+    /// anything after the leading alphabetic part of a symbol is ignored.
+    CovalentRadii { scale: f64 },
+}
+
+/// A bond between two sites.
+///
+/// Each bond is listed once.  `image` is the lattice translation applied to
+/// site `j` (relative to its position as stored in the Poscar) to obtain the
+/// bonded image, and `i <= j`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bond {
+    pub i: usize,
+    pub j: usize,
+    pub image: [i32; 3],
+    pub length: f64,
+}
+
+/// Bond length statistics for a single (unordered) pair of species.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PairStats {
+    /// The species labels, in sorted order.
+    pub species: (String, String),
+    pub count: usize,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    /// Population standard deviation.
+    pub std_dev: f64,
+}
+
+/// The output of [`Poscar::bonds`].
+///
+/// [`Poscar::bonds`]: struct.Poscar.html#method.bonds
+#[derive(Debug, Clone, PartialEq)]
+pub struct BondAnalysis {
+    /// Every bond, sorted by `i`, then `j`, then `image`.
+    pub bonds: Vec<Bond>,
+    /// The number of bonds to each site.
+    pub coordination: Vec<usize>,
+    /// Statistics for each pair of species that has at least one bond,
+    /// sorted by species.
+    pub pair_stats: Vec<PairStats>,
+}
+
+/// Covalent radius of an element in angstroms, if known.
+///
+/// See [`BondCriterion::CovalentRadii`] for details.
+///
+/// [`BondCriterion::CovalentRadii`]: enum.BondCriterion.html#variant.CovalentRadii
+pub fn covalent_radius(symbol: &str) -> Option<f64>
+{
+    let element = {
+        let end = symbol.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(symbol.len());
+        &symbol[..end]
+    };
+    COVALENT_RADII.iter()
+        .find(|&&(sym, _)| sym == element)
+        .map(|&(_, r)| r)
+}
+
+const COVALENT_RADII: &[(&str, f64)] = &[
+    ("H", 0.31), ("He", 0.28), ("Li", 1.28), ("Be", 0.96), ("B", 0.84),
+    ("C", 0.76), ("N", 0.71), ("O", 0.66), ("F", 0.57), ("Ne", 0.58),
+    ("Na", 1.66), ("Mg", 1.41), ("Al", 1.21), ("Si", 1.11), ("P", 1.07),
+    ("S", 1.05), ("Cl", 1.02), ("Ar", 1.06), ("K", 2.03), ("Ca", 1.76),
+    ("Sc", 1.70), ("Ti", 1.60), ("V", 1.53), ("Cr", 1.39), ("Mn", 1.39),
+    ("Fe", 1.32), ("Co", 1.26), ("Ni", 1.24), ("Cu", 1.32), ("Zn", 1.22),
+    ("Ga", 1.22), ("Ge", 1.20), ("As", 1.19), ("Se", 1.20), ("Br", 1.20),
+    ("Kr", 1.16), ("Rb", 2.20), ("Sr", 1.95), ("Y", 1.90), ("Zr", 1.75),
+    ("Nb", 1.64), ("Mo", 1.54), ("Tc", 1.47), ("Ru", 1.46), ("Rh", 1.42),
+    ("Pd", 1.39), ("Ag", 1.45), ("Cd", 1.44), ("In", 1.42), ("Sn", 1.39),
+    ("Sb", 1.39), ("Te", 1.38), ("I", 1.39), ("Xe", 1.40), ("Cs", 2.44),
+    ("Ba", 2.15), ("La", 2.07), ("Ce", 2.04), ("Pr", 2.03), ("Nd", 2.01),
+    ("Pm", 1.99), ("Sm", 1.98), ("Eu", 1.98), ("Gd", 1.96), ("Tb", 1.94),
+    ("Dy", 1.92), ("Ho", 1.92), ("Er", 1.89), ("Tm", 1.90), ("Yb", 1.87),
+    ("Lu", 1.87), ("Hf", 1.75), ("Ta", 1.70), ("W", 1.62), ("Re", 1.51),
+    ("Os", 1.44), ("Ir", 1.41), ("Pt", 1.36), ("Au", 1.36), ("Hg", 1.32),
+    ("Tl", 1.45), ("Pb", 1.46), ("Bi", 1.48), ("Po", 1.40), ("At", 1.50),
+    ("Rn", 1.50), ("Fr", 2.60), ("Ra", 2.21), ("Ac", 2.15), ("Th", 2.06),
+    ("Pa", 2.00), ("U", 1.96), ("Np", 1.90), ("Pu", 1.87), ("Am", 1.80),
+    ("Cm", 1.69),
+];
+
+/// # Bond analysis
+impl Poscar {
+    /// Find bonds and coordination numbers under periodic boundary conditions.
+    ///
+    /// The scale line is taken into account.  Bonds to a site's own periodic
+    /// images are included, and count towards its coordination.
+    ///
+    /// ```rust
+    /// # fn main() -> Result<(), failure::Error> {Ok({
+    /// use vasp_poscar::{Poscar, BondCriterion};
+    ///
+    /// let poscar = Poscar::from_reader("\
+    /// cubic diamond
+    ///   5.43
+    ///     0.0 0.5 0.5
+    ///     0.5 0.0 0.5
+    ///     0.5 0.5 0.0
+    ///    Si
+    ///    2
+    /// Direct
+    ///   0.0 0.0 0.0
+    ///   0.25 0.25 0.25
+    /// ".as_bytes())?;
+    ///
+    /// let analysis = poscar.bonds(&BondCriterion::CovalentRadii { scale: 1.2 })?;
+    /// assert_eq!(analysis.coordination, vec![4, 4]);
+    /// assert_eq!(analysis.bonds.len(), 4);
+    /// assert_eq!(analysis.pair_stats[0].count, 4);
+    /// # })}
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// With [`BondCriterion::CovalentRadii`], fails if the Poscar has no symbols
+    /// or if a symbol is not a recognized element.
+    ///
+    /// # Species labels
+    ///
+    /// Species are identified by their symbol.  For a Poscar with no symbols line,
+    /// the species of a site is instead labeled by the zero-based index of its group,
+    /// written in decimal (`"0"`, `"1"`, ...).
+    ///
+    /// [`BondCriterion::CovalentRadii`]: enum.BondCriterion.html#variant.CovalentRadii
+    pub fn bonds(&self, criterion: &BondCriterion) -> Result<BondAnalysis, failure::Error>
+    {
+        let labels = self.site_species_labels();
+        let mut species: Vec<&str> = labels.iter().map(|s| &s[..]).collect();
+        species.sort();
+        species.dedup();
+        let site_species: Vec<usize> = {
+            labels.iter()
+                .map(|l| species.binary_search(&&l[..]).expect("(BUG) missing species"))
+                .collect()
+        };
+
+        // cutoff for each pair of species
+        let n_species = species.len();
+        let mut cutoffs = vec![vec![None; n_species]; n_species];
+        match *criterion {
+            BondCriterion::PairCutoffs(ref pairs) => {
+                for &(ref a, ref b, cutoff) in pairs {
+                    let a = species.binary_search(&&a[..]);
+                    let b = species.binary_search(&&b[..]);
+                    if let (Ok(a), Ok(b)) = (a, b) {
+                        cutoffs[a][b] = Some(cutoff);
+                        cutoffs[b][a] = Some(cutoff);
+                    }
+                }
+            },
+            BondCriterion::CovalentRadii { scale } => {
+                ensure!(self.0.group_symbols.is_some(), "covalent radii require symbols");
+                let radii = {
+                    species.iter()
+                        .map(|&sym| covalent_radius(sym).ok_or_else(|| {
+                            format_err!("no covalent radius for symbol {:?}", sym)
+                        }))
+                        .collect::<Result<Vec<_>, _>>()?
+                };
+                for a in 0..n_species {
+                    for b in 0..n_species {
+                        cutoffs[a][b] = Some(scale * (radii[a] + radii[b]));
+                    }
+                }
+            },
+        }
+
+        let max_cutoff = {
+            cutoffs.iter().flat_map(|row| row.iter())
+                .filter_map(|&c| c)
+                .fold(0.0, f64::max)
+        };
+
+        let mut bonds = vec![];
+        let mut coordination = vec![0; self.num_sites()];
+        for neighbor in self.neighbors(max_cutoff) {
+            let (si, sj) = (site_species[neighbor.i], site_species[neighbor.j]);
+            match cutoffs[si][sj] {
+                Some(cutoff) if neighbor.distance <= cutoff => {},
+                _ => continue,
+            }
+
+            coordination[neighbor.i] += 1;
+            // each bond appears twice in the full neighbor list
+            if (neighbor.i, [0; 3]) < (neighbor.j, neighbor.image) {
+                bonds.push(Bond {
+                    i: neighbor.i,
+                    j: neighbor.j,
+                    image: neighbor.image,
+                    length: neighbor.distance,
+                });
+            }
+        }
+
+        let mut lengths = vec![vec![vec![]; n_species]; n_species];
+        for bond in &bonds {
+            let (a, b) = (site_species[bond.i], site_species[bond.j]);
+            let (a, b) = (a.min(b), a.max(b));
+            lengths[a][b].push(bond.length);
+        }
+
+        let mut pair_stats = vec![];
+        for a in 0..n_species {
+            for b in a..n_species {
+                let lengths = &lengths[a][b];
+                if lengths.is_empty() {
+                    continue;
+                }
+                let count = lengths.len();
+                let mean = lengths.iter().sum::<f64>() / count as f64;
+                let variance = lengths.iter().map(|x| (x - mean) * (x - mean)).sum::<f64>() / count as f64;
+                pair_stats.push(PairStats {
+                    species: (species[a].to_string(), species[b].to_string()),
+                    count,
+                    min: lengths.iter().cloned().fold(f64::INFINITY, f64::min),
+                    max: lengths.iter().cloned().fold(f64::NEG_INFINITY, f64::max),
+                    mean,
+                    std_dev: variance.sqrt(),
+                });
+            }
+        }
+
+        Ok(BondAnalysis { bonds, coordination, pair_stats })
+    }
+}
+
+#[cfg(test)]
+#[deny(unused)]
+mod tests {
+    use super::*;
+    use crate::{Builder, Coords, ScaleLine};
+
+    fn rocksalt() -> Poscar {
+        Builder::new()
+            .scale(ScaleLine::Factor(5.64))
+            .dummy_lattice_vectors()
+            .group_symbols(vec!["Na", "Cl"])
+            .group_counts(vec![4, 4])
+            .positions(Coords::Frac(vec![
+                [0.0, 0.0, 0.0], [0.0, 0.5, 0.5], [0.5, 0.0, 0.5], [0.5, 0.5, 0.0],
+                [0.5, 0.5, 0.5], [0.5, 0.0, 0.0], [0.0, 0.5, 0.0], [0.0, 0.0, 0.5],
+            ]))
+            .build().unwrap()
+    }
+
+    #[test]
+    fn pair_cutoffs() {
+        let poscar = rocksalt();
+
+        let criterion = BondCriterion::PairCutoffs(vec![("Cl".into(), "Na".into(), 3.0)]);
+        let analysis = poscar.bonds(&criterion).unwrap();
+        assert_eq!(analysis.coordination, vec![6; 8]);
+        assert_eq!(analysis.bonds.len(), 24);
+        assert_eq!(analysis.pair_stats.len(), 1);
+
+        let stats = &analysis.pair_stats[0];
+        assert_eq!(stats.species, ("Cl".to_string(), "Na".to_string()));
+        assert_eq!(stats.count, 24);
+        assert!((stats.mean - 2.82).abs() < 1e-12);
+        assert!((stats.min - 2.82).abs() < 1e-12);
+        assert!((stats.max - 2.82).abs() < 1e-12);
+        assert!(stats.std_dev < 1e-12);
+
+        // like-species bonds at the second shell
+        let criterion = BondCriterion::PairCutoffs(vec![("Na".into(), "Na".into(), 4.0)]);
+        let analysis = poscar.bonds(&criterion).unwrap();
+        assert_eq!(analysis.coordination, vec![12, 12, 12, 12, 0, 0, 0, 0]);
+        assert_eq!(analysis.pair_stats[0].species, ("Na".to_string(), "Na".to_string()));
+    }
+
+    #[test]
+    fn without_symbols() {
+        let mut poscar = rocksalt().into_raw();
+        poscar.group_symbols = None;
+        let poscar = poscar.validate().unwrap();
+
+        let criterion = BondCriterion::PairCutoffs(vec![("0".into(), "1".into(), 3.0)]);
+        assert_eq!(poscar.bonds(&criterion).unwrap().coordination, vec![6; 8]);
+        assert!(poscar.bonds(&BondCriterion::CovalentRadii { scale: 1.2 }).is_err());
+    }
+
+    #[test]
+    fn self_image_bonds() {
+        // a chain of atoms with a single atom per cell
+        let poscar =
+            Builder::new()
+            .lattice_vectors(&[[1.5, 0.0, 0.0], [0.0, 10.0, 0.0], [0.0, 0.0, 10.0]])
+            .site_symbols(vec!["C"])
+            .positions(Coords::Frac(vec![[0.0; 3]]))
+            .build().unwrap();
+
+        let analysis = poscar.bonds(&BondCriterion::CovalentRadii { scale: 1.1 }).unwrap();
+        assert_eq!(analysis.coordination, vec![2]);
+        assert_eq!(analysis.bonds, vec![Bond { i: 0, j: 0, image: [1, 0, 0], length: 1.5 }]);
+    }
+
+    #[test]
+    fn radius_lookup() {
+        assert_eq!(covalent_radius("C"), Some(0.76));
+        assert_eq!(covalent_radius("Fe_pv"), Some(1.32));
+        assert_eq!(covalent_radius("Si1"), Some(1.11));
+        assert_eq!(covalent_radius("Xx"), None);
+    }
+}
@@ -15,6 +15,7 @@
 //! [`Builder`]: struct.Builder.html
 
 use crate::{ScaleLine, Coords, RawPoscar, Poscar, ValidationError};
+use crate::{LatticeParams, LatticeOrientation};
 use crate::types::{CoordsTag};
 use crate::{ToN3};
 
@@ -95,6 +96,10 @@ use crate::{ToN3};
 /// If positions is set to [`Zeroed`], then **[`group_counts`]
 /// also becomes required.**
 ///
+/// **Lattice parameters must describe a valid cell:** [`build_raw`] panics if
+/// the parameters given to [`lattice_parameters`] are invalid.
+/// ([`build`] returns a [`ValidationError`] instead)
+///
 /// ## Poisoning
 ///
 /// Calling [`build_raw`] or [`build`] "consumes" the `Builder` in a manner
@@ -109,6 +114,7 @@ use crate::{ToN3};
 /// [`positions`]: #method.positions
 /// [`comment`]: #method.comment
 /// [`lattice_vectors`]: #method.lattice_vectors
+/// [`lattice_parameters`]: #method.lattice_parameters
 /// [`group_counts`]: #method.group_counts
 /// [`build_raw`]: #method.build_raw
 /// [`build`]: #method.build
@@ -165,6 +171,7 @@ pub struct Zeroed;
 enum Lattice {
     Missing,
     This(Box<[[f64; 3]; 3]>),
+    Params(LatticeParams, LatticeOrientation),
 }
 
 #[derive(Debug, Clone)]
@@ -392,6 +399,10 @@ impl Builder {
     /// to the builder will ultimately be discarded.
     pub fn dummy_lattice_vectors(&mut self) -> &mut Self
     { self.as_mut().lattice_vectors = Lattice::This(Box::new(EYE)); self }
+
+    // backend of `lattice_parameters`, which lives with the rest of the lattice parameter code
+    pub(crate) fn _lattice_parameters(&mut self, params: LatticeParams, orientation: LatticeOrientation) -> &mut Self
+    { self.as_mut().lattice_vectors = Lattice::Params(params, orientation); self }
 }
 
 /// # Setting coordinate data
@@ -524,7 +535,15 @@ impl Builder {
     /// [Errors: See `ValidationError`]: ../enum.ValidationError.html
     /// [Panics: See toplevel documentation]: #panics
     pub fn build(&mut self) -> Result<Poscar, ValidationError>
-    { self.build_raw().validate() }
+    {
+        if let Lattice::Params(params, orientation) = self.as_mut().lattice_vectors {
+            if params.checked_lattice_vectors(orientation).is_err() {
+                let _ = self.take();
+                g_bail!(ValidationError::BadLatticeParams(params));
+            }
+        }
+        self.build_raw().validate()
+    }
 
     /// Creates a [`RawPoscar`].
     ///
@@ -543,6 +562,7 @@ impl Builder {
         let lattice_vectors = match lattice_vectors {
             Lattice::Missing => panic!("missing required field 'lattice_vectors'"),
             Lattice::This(x) => *x,
+            Lattice::Params(params, orientation) => params.lattice_vectors(orientation),
         };
 
         let (positions, group_counts) = match (positions, group_counts) {
@@ -597,7 +617,7 @@ impl Builder {
     }
 }
 
-fn get_run_length_encoding<X: PartialEq>(xs: impl IntoIterator<Item=X>) -> (Vec<usize>, Vec<X>) {
+pub(crate) fn get_run_length_encoding<X: PartialEq>(xs: impl IntoIterator<Item=X>) -> (Vec<usize>, Vec<X>) {
     let mut iter = xs.into_iter();
     let mut last = match iter.next() {
         None => return (vec![], vec![]),
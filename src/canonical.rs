@@ -0,0 +1,296 @@
+// Copyright 2018 Michael Lamparski
+// Part of the vasp-poscar crate.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use crate::{Poscar, ScaleLine};
+
+/// Options for [`Poscar::canonicalize`].
+///
+/// [`Poscar::canonicalize`]: struct.Poscar.html#method.canonicalize
+#[derive(Debug, Clone, PartialEq)]
+pub struct CanonicalizeOptions {
+    /// Fractional coordinates within this distance below `1.0` are mapped to `0.0`.
+    ///
+    /// Defaults to `1e-8`.
+    pub wrap_tol: f64,
+
+    /// Sort the sites by symbol, merging groups that share a symbol.
+    ///
+    /// This has no effect on a Poscar without symbols.
+    /// Defaults to `false`.
+    pub sort_species: bool,
+
+    /// Within each group, sort the sites lexicographically by their
+    /// (wrapped) fractional coordinates.
+    ///
+    /// Defaults to `false`.
+    pub sort_sites: bool,
+}
+
+impl Default for CanonicalizeOptions {
+    fn default() -> Self
+    { CanonicalizeOptions {
+        wrap_tol: 1e-8,
+        sort_species: false,
+        sort_sites: false,
+    }}
+}
+
+impl CanonicalizeOptions {
+    /// Alias for [`Default`]`::default`.
+    ///
+    /// [`Default`]: https://doc.rust-lang.org/std/default/trait.Default.html
+    pub fn new() -> Self
+    { Default::default() }
+}
+
+/// # Canonicalization
+impl Poscar {
+    /// Rewrite the Poscar into a standard form, so that the written file
+    /// is identical for equivalent representations of the same structure.
+    ///
+    /// The following steps are performed:
+    ///
+    /// * The scale is absorbed into the lattice, leaving `ScaleLine::Factor(1.0)`.
+    /// * Positions and velocities are converted to direct coordinates.
+    /// * Positions are wrapped into `[0, 1)`, with values close to `1`
+    ///   (per `wrap_tol`) being sent to `0`.
+    /// * Negative zeros in the lattice, positions and velocities become positive.
+    /// * Optionally, sites are sorted by symbol and/or position.
+    ///
+    /// The comment is left untouched.  Calling this twice with the same options
+    /// produces the same Poscar as calling it once.
+    ///
+    /// ```rust
+    /// # fn main() -> Result<(), failure::Error> {Ok({
+    /// use vasp_poscar::{Builder, Coords, ScaleLine, CanonicalizeOptions};
+    ///
+    /// let mut poscar =
+    ///     Builder::new()
+    ///     .scale(ScaleLine::Volume(8.0))
+    ///     .lattice_vectors(&[[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]])
+    ///     .site_symbols(vec!["O", "C"])
+    ///     .positions(Coords::Cart(vec![[1.0, -0.0, 0.5], [-0.5, 0.25, 0.0]]))
+    ///     .build()?;
+    ///
+    /// poscar.canonicalize(&CanonicalizeOptions { sort_species: true, ..Default::default() });
+    ///
+    /// assert_eq!(format!("{}", poscar), "\
+    /// POSCAR File
+    ///   1.0
+    ///     2.0 0.0 0.0
+    ///     0.0 2.0 0.0
+    ///     0.0 0.0 2.0
+    ///    C  O
+    ///    1  1
+    /// Direct
+    ///   0.5 0.25 0.0
+    ///   0.0 0.0 0.5
+    /// ");
+    /// # })}
+    /// ```
+    pub fn canonicalize(&mut self, options: &CanonicalizeOptions)
+    {
+        let CanonicalizeOptions { wrap_tol, sort_species, sort_sites } = *options;
+
+        // bake the scale into the lattice.  Cartesian velocities are not affected
+        // by the scale line, so they are taken before the lattice changes and
+        // converted to fractional against the new one.
+        let cart_velocities = self.cart_velocities().map(|v| v.into_owned());
+        *self = self.to_frac_storage();
+        self.0.lattice_vectors = self.scaled_lattice();
+        self.0.scale = ScaleLine::Factor(1.0);
+        self.0.velocities = cart_velocities.map(|v| {
+            crate::Coords::Cart(v).into_tag(&self.0.lattice_vectors, crate::types::FRAC)
+        });
+
+        if let crate::Coords::Frac(ref mut fracs) = self.0.positions {
+            for x in fracs.iter_mut().flat_map(|v| v.iter_mut()) {
+                *x = wrap_unit(*x, wrap_tol);
+            }
+        }
+
+        if sort_species || sort_sites {
+            let types = self.site_type_indices();
+            let symbols = self.type_symbols();
+            let fracs = self.frac_positions().into_owned();
+            let groups: Vec<usize> = {
+                zip!(0.., &self.0.group_counts)
+                    .flat_map(|(g, &count)| (0..count).map(move |_| g))
+                    .collect()
+            };
+
+            let mut perm: Vec<usize> = (0..self.num_sites()).collect();
+            perm.sort_by(|&a, &b| {
+                let by_species = match (sort_species, symbols.as_ref()) {
+                    (true, Some(syms)) => syms[types[a]].cmp(&syms[types[b]]),
+                    _ => groups[a].cmp(&groups[b]),
+                };
+                let by_position = match sort_sites {
+                    true => cmp_v3(&fracs[a], &fracs[b]),
+                    false => std::cmp::Ordering::Equal,
+                };
+                by_species.then(by_position)
+            });
+
+            // Sorting by symbols merges groups, but otherwise the original
+            // grouping must be kept even when two adjacent groups are
+            // indistinguishable.
+            match (sort_species, symbols.is_some()) {
+                (true, true) => self.permute_sites(&perm),
+                _ => {
+                    let counts = self.0.group_counts.clone();
+                    let symbols = self.0.group_symbols.clone();
+                    self.permute_sites(&perm);
+                    self.0.group_counts = counts;
+                    self.0.group_symbols = symbols;
+                },
+            }
+        }
+
+        let raw = &mut self.0;
+        let velocities = raw.velocities.as_mut().map(|v| v.as_mut().raw());
+        let data = {
+            raw.lattice_vectors.iter_mut()
+                .chain(raw.positions.as_mut().raw().iter_mut())
+                .chain(velocities.into_iter().flat_map(|v| v.iter_mut()))
+        };
+        for x in data.flat_map(|v| v.iter_mut()) {
+            // adding positive zero turns -0.0 into 0.0 and leaves all else unchanged
+            *x += 0.0;
+        }
+    }
+}
+
+/// Reduce a fractional coordinate into `[0, 1)`, sending values that
+/// are within `tol` of `1` to zero.
+pub(crate) fn wrap_unit(x: f64, tol: f64) -> f64
+{
+    let y = x - x.floor();
+    match y >= 1.0 - tol {
+        true => 0.0,
+        false => y,
+    }
+}
+
+fn cmp_v3(a: &[f64; 3], b: &[f64; 3]) -> std::cmp::Ordering
+{
+    zip!(a, b)
+        .map(|(a, b)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
+        .find(|&o| o != std::cmp::Ordering::Equal)
+        .unwrap_or(std::cmp::Ordering::Equal)
+}
+
+#[cfg(test)]
+#[deny(unused)]
+mod tests {
+    use super::*;
+    use crate::{Builder, Coords};
+
+    #[test]
+    fn equivalent_inputs_agree() {
+        let lattice = [[2.0, 0.0, 0.0], [1.0, 3.0, 0.0], [0.0, 0.0, 4.0]];
+        let fracs = vec![[0.0, 0.5, 0.25], [0.5, 0.75, 0.5], [0.25, 0.0, 0.0]];
+        let base =
+            Builder::new()
+            .lattice_vectors(&lattice)
+            .group_symbols(vec!["Si", "O"])
+            .group_counts(vec![1, 2])
+            .positions(Coords::Frac(fracs))
+            .build().unwrap();
+
+        // a version with different scale line, Cartesian storage, shifted
+        // periodic images, and swapped groups.
+        let other = {
+            let half = crate::math::scale_33(&lattice, 0.5).0;
+            let shifted: Vec<_> = vec![
+                [0.5, 0.75, 0.5 - 1.0],
+                [0.25 + 1.0, -0.0, 0.0],
+                [-1.0, 0.5, 0.25],
+            ];
+            Builder::new()
+                .scale(ScaleLine::Volume(24.0))
+                .lattice_vectors(&half)
+                .group_symbols(vec!["O", "Si"])
+                .group_counts(vec![2, 1])
+                .positions(Coords::Frac(shifted))
+                .build().unwrap()
+                .to_cart_storage()
+        };
+
+        let options = CanonicalizeOptions {
+            sort_species: true,
+            sort_sites: true,
+            ..Default::default()
+        };
+        let mut a = base.clone();
+        let mut b = other.clone();
+        a.canonicalize(&options);
+        b.canonicalize(&options);
+        assert_eq!(format!("{}", a), format!("{}", b));
+
+        // idempotent
+        let mut c = a.clone();
+        c.canonicalize(&options);
+        assert_eq!(format!("{}", a), format!("{}", c));
+    }
+
+    #[test]
+    fn velocities_survive_scale() {
+        for &scale in &[ScaleLine::Factor(2.0), ScaleLine::Volume(3.0)] {
+            let mut poscar =
+                Builder::new()
+                .scale(scale)
+                .lattice_vectors(&[[2.0, 0.0, 0.0], [1.0, 3.0, 0.0], [0.0, 0.5, 4.0]])
+                .positions(Coords::Frac(vec![[0.0; 3], [0.5; 3]]))
+                .velocities(Coords::Cart(vec![[1.0, 0.0, 0.0], [0.25, -0.5, 2.0]]))
+                .build().unwrap();
+            let expected = poscar.cart_velocities().unwrap().into_owned();
+
+            poscar.canonicalize(&Default::default());
+            assert_eq!(poscar.0.scale, ScaleLine::Factor(1.0));
+            assert_eq!(poscar.0.velocities.as_ref().unwrap().tag(), crate::types::FRAC);
+            for (a, b) in zip!(poscar.cart_velocities().unwrap().iter(), expected.iter()) {
+                for k in 0..3 {
+                    assert!((a[k] - b[k]).abs() < 1e-12, "{:?} {:?}", a, b);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn wrapping() {
+        assert_eq!(wrap_unit(0.25, 1e-8), 0.25);
+        assert_eq!(wrap_unit(-0.75, 1e-8), 0.25);
+        assert_eq!(wrap_unit(2.0, 1e-8), 0.0);
+        assert_eq!(wrap_unit(1.0 - 1e-10, 1e-8), 0.0);
+        assert_eq!(wrap_unit(-1e-10, 1e-8), 0.0);
+        assert!(wrap_unit(-1e-10, 0.0) < 1.0);
+    }
+
+    #[test]
+    fn groups_kept_without_sort_species() {
+        let mut poscar =
+            Builder::new()
+            .dummy_lattice_vectors()
+            .group_symbols(vec!["C", "C"])
+            .group_counts(vec![1, 1])
+            .positions(Coords::Frac(vec![[0.5, 0.0, 0.0], [0.25, 0.0, 0.0]]))
+            .dynamics(vec![[true, false, false], [false, true, false]])
+            .build().unwrap();
+
+        poscar.canonicalize(&CanonicalizeOptions { sort_sites: true, ..Default::default() });
+        assert_eq!(poscar.group_counts().collect::<Vec<_>>(), vec![1, 1]);
+        assert_eq!(poscar.frac_positions()[0], [0.5, 0.0, 0.0]);
+
+        poscar.canonicalize(&CanonicalizeOptions { sort_species: true, sort_sites: true, ..Default::default() });
+        assert_eq!(poscar.group_counts().collect::<Vec<_>>(), vec![2]);
+        assert_eq!(poscar.frac_positions()[0], [0.25, 0.0, 0.0]);
+        assert_eq!(poscar.0.dynamics, Some(vec![[false, true, false], [true, false, false]]));
+    }
+}
@@ -0,0 +1,306 @@
+// Copyright 2018 Michael Lamparski
+// Part of the vasp-poscar crate.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use crate::{Poscar, ScaleLine};
+use crate::geometry::MinImage;
+use crate::math::{norm_3, sub_3};
+
+/// The output of [`Poscar::diff`].
+///
+/// Each `bool` field is `true` when the corresponding section of the files differs.
+///
+/// [`Poscar::diff`]: struct.Poscar.html#method.diff
+#[derive(Debug, Clone, PartialEq)]
+pub struct PoscarDiff {
+    /// The comments are not identical.
+    pub comment: bool,
+    /// The scale lines are of different kinds, or their values differ by more
+    /// than the tolerance.
+    pub scale: bool,
+    /// Some component of the scaled lattice vectors differs by more than the tolerance.
+    pub lattice: bool,
+    /// The group symbols are not identical (including when only one file has them).
+    pub symbols: bool,
+    /// The group counts are not identical.
+    pub counts: bool,
+    /// The files have a different number of sites, or some site moved by more than
+    /// the tolerance.
+    pub positions: bool,
+    /// Only one file has velocities, or some Cartesian velocity component differs
+    /// by more than the tolerance.
+    pub velocities: bool,
+    /// The selective dynamics flags are not identical (including when only one file
+    /// has them).
+    pub dynamics: bool,
+
+    /// The largest difference between any two components of the scaled lattice vectors.
+    pub max_lattice_deviation: f64,
+    /// The distance that each site moved, in scaled units.
+    ///
+    /// This is the length of the shortest periodic image of the change in direct
+    /// coordinates, under the lattice of the first file.  Thus, sites that merely
+    /// wrapped around the cell do not count as moving, and neither do sites that
+    /// only followed a change in the lattice.  `None` if the files have a different
+    /// number of sites.
+    pub site_deviations: Option<Vec<f64>>,
+    /// The largest difference between any two Cartesian velocity components.
+    ///
+    /// `None` unless both files have velocities for the same number of sites.
+    pub max_velocity_deviation: Option<f64>,
+}
+
+impl PoscarDiff {
+    /// Test whether no section differs.
+    pub fn is_empty(&self) -> bool
+    { self.sections().is_empty() }
+
+    /// Get the names of the sections that differ, in file order.
+    ///
+    /// The names are `"comment"`, `"scale"`, `"lattice"`, `"symbols"`, `"counts"`,
+    /// `"positions"`, `"velocities"`, and `"dynamics"`.
+    pub fn sections(&self) -> Vec<&'static str>
+    {
+        vec![
+            ("comment", self.comment),
+            ("scale", self.scale),
+            ("lattice", self.lattice),
+            ("symbols", self.symbols),
+            ("counts", self.counts),
+            ("positions", self.positions),
+            ("velocities", self.velocities),
+            ("dynamics", self.dynamics),
+        ].into_iter().filter(|&(_, differs)| differs).map(|(name, _)| name).collect()
+    }
+
+    /// The largest distance moved by any site, in scaled units.
+    ///
+    /// `None` if the files have a different number of sites.  Zero if they have no sites.
+    pub fn max_site_deviation(&self) -> Option<f64>
+    { self.site_deviations.as_ref().map(|d| d.iter().cloned().fold(0.0, f64::max)) }
+
+    /// Get up to `n` sites that moved the most, as `(site, distance)` pairs.
+    ///
+    /// These are sorted by decreasing distance, with ties broken by index.
+    /// Empty if the files have a different number of sites.
+    pub fn most_moved_sites(&self, n: usize) -> Vec<(usize, f64)>
+    {
+        let mut sites: Vec<(usize, f64)> = match self.site_deviations {
+            Some(ref deviations) => deviations.iter().cloned().enumerate().collect(),
+            None => return vec![],
+        };
+        sites.sort_by(|a, b| b.1.partial_cmp(&a.1).expect("NaN deviation").then(a.0.cmp(&b.0)));
+        sites.truncate(n);
+        sites
+    }
+}
+
+/// # Comparison
+impl Poscar {
+    /// Compare two files section by section, for use in regression tests.
+    ///
+    /// All floating point data is compared with the absolute tolerance `tol`.
+    /// Positions are compared in direct coordinates, using the nearest periodic
+    /// images, so wrapping a site into the cell does not count as a difference;
+    /// see [`PoscarDiff::site_deviations`].  Whether the coordinates are stored as
+    /// Cartesian or direct is not considered.
+    ///
+    /// ```rust
+    /// # fn main() -> Result<(), failure::Error> {Ok({
+    /// use vasp_poscar::{Builder, Coords};
+    ///
+    /// let before =
+    ///     Builder::new()
+    ///     .dummy_lattice_vectors()
+    ///     .positions(Coords::Frac(vec![[0.0; 3], [0.5; 3], [0.25; 3]]))
+    ///     .build()?;
+    ///
+    /// let after =
+    ///     Builder::new()
+    ///     .dummy_lattice_vectors()
+    ///     .positions(Coords::Frac(vec![[1.0, 0.0, 0.0], [0.5, 0.5, 0.4], [0.25, 0.25, 0.3]]))
+    ///     .build()?;
+    ///
+    /// let diff = before.diff(&after, 1e-3)?;
+    /// assert_eq!(diff.sections(), vec!["positions"]);
+    /// assert_eq!(diff.most_moved_sites(2)[0].0, 1);
+    /// assert_eq!(diff.site_deviations.as_ref().unwrap()[0], 0.0);
+    /// # })}
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Fails if `tol` is negative or NaN.
+    ///
+    /// [`PoscarDiff::site_deviations`]: struct.PoscarDiff.html#structfield.site_deviations
+    pub fn diff(&self, other: &Poscar, tol: f64) -> Result<PoscarDiff, failure::Error>
+    {
+        ensure!(tol >= 0.0, "tolerance must be non-negative: {}", tol);
+
+        let comment = self.0.comment != other.0.comment;
+        let scale = match (self.0.scale, other.0.scale) {
+            (ScaleLine::Factor(a), ScaleLine::Factor(b)) |
+            (ScaleLine::Volume(a), ScaleLine::Volume(b)) => (a - b).abs() > tol,
+            _ => true,
+        };
+
+        let lattice_1 = self.scaled_lattice();
+        let lattice_2 = other.scaled_lattice();
+        let max_lattice_deviation = max_component_deviation(&lattice_1, &lattice_2);
+        let lattice = max_lattice_deviation > tol;
+
+        let symbols = self.0.group_symbols != other.0.group_symbols;
+        let counts = self.0.group_counts != other.0.group_counts;
+        let dynamics = self.0.dynamics != other.0.dynamics;
+
+        let site_deviations: Option<Vec<f64>> = match self.num_sites() == other.num_sites() {
+            false => None,
+            true => {
+                let min_image = MinImage::new(&lattice_1);
+                let (fracs_1, fracs_2) = (self.frac_positions(), other.frac_positions());
+                Some(zip!(fracs_1.iter(), fracs_2.iter()).map(|(a, b)| {
+                    norm_3(&min_image.cart_displacement(a, b))
+                }).collect())
+            },
+        };
+        let positions = match site_deviations {
+            None => true,
+            Some(ref deviations) => deviations.iter().any(|&d| d > tol),
+        };
+
+        let (velocities, max_velocity_deviation) = match (self.cart_velocities(), other.cart_velocities()) {
+            (None, None) => (false, None),
+            (Some(a), Some(b)) => match a.len() == b.len() {
+                true => {
+                    let deviation = max_component_deviation(&a, &b);
+                    (deviation > tol, Some(deviation))
+                },
+                false => (true, None),
+            },
+            _ => (true, None),
+        };
+
+        Ok(PoscarDiff {
+            comment, scale, lattice, symbols, counts, positions, velocities, dynamics,
+            max_lattice_deviation, site_deviations, max_velocity_deviation,
+        })
+    }
+
+    /// Test whether two files are the same up to the absolute tolerance `tol`.
+    ///
+    /// This is true when no section differs according to [`diff`], which
+    /// describes the comparison in detail.
+    ///
+    /// # Errors
+    ///
+    /// Fails if `tol` is negative or NaN.
+    ///
+    /// [`diff`]: #method.diff
+    pub fn approx_eq(&self, other: &Poscar, tol: f64) -> Result<bool, failure::Error>
+    { Ok(self.diff(other, tol)?.is_empty()) }
+}
+
+fn max_component_deviation(a: &[[f64; 3]], b: &[[f64; 3]]) -> f64
+{
+    zip!(a, b)
+        .flat_map(|(a, b)| sub_3(a, b).to_vec())
+        .map(f64::abs)
+        .fold(0.0, f64::max)
+}
+
+#[cfg(test)]
+#[deny(unused)]
+mod tests {
+    use super::*;
+    use crate::util::test_structures::skewed_builder;
+    use crate::Coords;
+
+    fn example() -> Poscar {
+        skewed_builder()
+            .comment("example")
+            .site_symbols(vec!["Ga", "As", "As"])
+            .positions(Coords::Frac(vec![[0.0; 3], [0.25, 0.5, 0.75], [0.5, 0.5, 0.99]]))
+            .velocities(Coords::Cart(vec![[0.1, 0.0, 0.0], [0.0; 3], [0.0; 3]]))
+            .build().unwrap()
+    }
+
+    #[test]
+    fn identical() {
+        let poscar = example();
+        let diff = poscar.diff(&poscar, 0.0).unwrap();
+        assert!(diff.is_empty());
+        assert_eq!(diff.max_site_deviation(), Some(0.0));
+        assert_eq!(diff.max_velocity_deviation, Some(0.0));
+        assert!(poscar.approx_eq(&poscar.to_cart_storage(), 1e-12).unwrap());
+
+        assert!(poscar.diff(&poscar, -1e-6).is_err());
+        assert!(poscar.approx_eq(&poscar, f64::NAN).is_err());
+    }
+
+    #[test]
+    fn wrapped_and_noisy() {
+        let poscar = example();
+        let mut other = example().to_cart_storage();
+        other.wrap_positions(0.0);
+        let fracs: Vec<[f64; 3]> = other.frac_positions().iter().map(|x| [x[0] + 1e-8, x[1] - 1.0, x[2]]).collect();
+        other.set_frac_positions(fracs);
+
+        assert!(poscar.approx_eq(&other, 1e-6).unwrap());
+        assert!(!poscar.approx_eq(&other, 1e-9).unwrap());
+        assert_eq!(poscar.diff(&other, 1e-9).unwrap().sections(), vec!["positions"]);
+    }
+
+    #[test]
+    fn sections() {
+        let poscar = example();
+
+        let mut raw = example().into_raw();
+        raw.comment = "other".into();
+        raw.scale = ScaleLine::Factor(2.1);
+        raw.group_symbols = Some(vec!["Ga".into(), "P".into()]);
+        raw.dynamics = Some(vec![[true; 3]; 3]);
+        raw.velocities = None;
+        let diff = poscar.diff(&raw.validate().unwrap(), 1e-6).unwrap();
+        assert_eq!(diff.sections(), vec!["comment", "scale", "lattice", "symbols", "velocities", "dynamics"]);
+        assert!((diff.max_lattice_deviation - 0.3).abs() < 1e-12);
+        assert_eq!(diff.max_velocity_deviation, None);
+        assert_eq!(diff.max_site_deviation(), Some(0.0));
+
+        // the same scaled lattice, written differently
+        let mut raw = example().into_raw();
+        raw.scale = ScaleLine::Factor(1.0);
+        raw.lattice_vectors = [[4.0, 0.0, 0.0], [2.0, 4.0, 0.0], [0.0, 1.0, 6.0]];
+        assert_eq!(poscar.diff(&raw.validate().unwrap(), 1e-6).unwrap().sections(), vec!["scale"]);
+
+        let (supercell, _) = poscar.supercell(&[[1, 0, 0], [0, 1, 0], [0, 0, 2]]).unwrap();
+        let diff = poscar.diff(&supercell, 1e-6).unwrap();
+        assert_eq!(diff.sections(), vec!["lattice", "counts", "positions", "velocities"]);
+        assert_eq!(diff.site_deviations, None);
+        assert_eq!(diff.max_site_deviation(), None);
+        assert_eq!(diff.most_moved_sites(3), vec![]);
+    }
+
+    #[test]
+    fn most_moved() {
+        let poscar = example();
+        let mut other = example();
+        let mut fracs = other.frac_positions().into_owned();
+        fracs[0][0] += 0.1;
+        fracs[2][2] -= 0.1;
+        other.set_frac_positions(fracs);
+
+        let diff = poscar.diff(&other, 1e-6).unwrap();
+        assert_eq!(diff.sections(), vec!["positions"]);
+        let moved = diff.most_moved_sites(5);
+        assert_eq!(moved.iter().map(|&(i, _)| i).collect::<Vec<_>>(), vec![2, 0, 1]);
+        assert!((moved[0].1 - 0.37f64.sqrt()).abs() < 1e-12);
+        assert!((moved[1].1 - 0.4).abs() < 1e-12);
+        assert_eq!(moved[2].1, 0.0);
+        assert_eq!(diff.max_site_deviation(), Some(moved[0].1));
+    }
+}
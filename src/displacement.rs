@@ -0,0 +1,287 @@
+// Copyright 2018 Michael Lamparski
+// Part of the vasp-poscar crate.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use crate::Poscar;
+use crate::math::{inv_f64, mul_3_33, norm_3};
+
+/// A displaced structure produced by [`Poscar::finite_displacements`].
+///
+/// [`Poscar::finite_displacements`]: struct.Poscar.html#method.finite_displacements
+#[derive(Debug, Clone)]
+pub struct FiniteDisplacement {
+    /// The position of this structure in the output, numbered from 1.
+    ///
+    /// This is the number that appears in the comment.
+    pub number: usize,
+    /// Index of the displaced site.
+    pub site: usize,
+    /// The Cartesian axis of the displacement (`0`, `1` or `2` for `x`, `y`, `z`).
+    pub axis: usize,
+    /// The Cartesian displacement of the site (in scaled units), which is
+    /// `±amplitude` along `axis`.
+    pub displacement: [f64; 3],
+    /// The displaced structure.
+    pub poscar: Poscar,
+}
+
+/// # Finite displacements
+impl Poscar {
+    /// Generate the displaced structures for computing force constants by finite differences.
+    ///
+    /// Each site is displaced by `+amplitude` and then `-amplitude` (in scaled units)
+    /// along each of the Cartesian axes `x`, `y` and `z`, one at a time.  The output is
+    /// ordered by site, then by axis, then by sign.
+    ///
+    /// When `equivalent_atoms` is supplied (such as the one in a [`SymmetryDataset`]),
+    /// only the sites `i` with `equivalent_atoms[i] == i` are displaced, since the force
+    /// constants of the others follow by symmetry.
+    ///
+    /// Displacements that would change a coordinate fixed by selective dynamics are
+    /// skipped.  The flags apply to the stored coordinates, so for Cartesian storage this
+    /// skips the frozen axes.  For direct storage, a Cartesian displacement generally
+    /// changes all three direct coordinates, so it is only generated when it leaves the
+    /// frozen ones unchanged (e.g. along `z` for a site with flags `F F T` in a cell whose
+    /// first two lattice vectors lie in the `xy` plane).
+    ///
+    /// Each output has the same storage (Cartesian or direct) as `self`, and a comment
+    /// of the form `"<comment> disp-001 site 4 +x"`.  In the comment, both the
+    /// displacements and the sites are numbered from 1; thus, this example is the
+    /// first displacement, of the site with index `3`.  Only the stored coordinates of
+    /// the displaced site are modified (never those fixed by selective dynamics), and
+    /// other data is unchanged.
+    ///
+    /// ```rust
+    /// # fn main() -> Result<(), failure::Error> {Ok({
+    /// use vasp_poscar::{Builder, Coords};
+    ///
+    /// let poscar =
+    ///     Builder::new()
+    ///     .comment("NaCl")
+    ///     .lattice_vectors(&[[4.0, 0.0, 0.0], [0.0, 4.0, 0.0], [0.0, 0.0, 4.0]])
+    ///     .site_symbols(vec!["Na", "Cl", "Cl"])
+    ///     .positions(Coords::Cart(vec![[0.0; 3], [2.0, 0.0, 0.0], [0.0, 2.0, 0.0]]))
+    ///     .build()?;
+    ///
+    /// // pretend that the two Cl sites are equivalent
+    /// let displaced = poscar.finite_displacements(0.01, Some(&[0, 1, 1]))?;
+    /// assert_eq!(displaced.len(), 12);
+    /// assert_eq!(displaced[9].site, 1);
+    /// assert_eq!(displaced[9].poscar.comment(), "NaCl disp-010 site 2 -y");
+    /// assert_eq!(displaced[9].poscar.unscaled_cart_positions()[1], [2.0, -0.01, 0.0]);
+    /// # })}
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Fails if `amplitude` is not positive and finite.  Also fails if `equivalent_atoms`
+    /// has the wrong length, or does not map each site to a site of the same species
+    /// that maps to itself.
+    ///
+    /// [`SymmetryDataset`]: struct.SymmetryDataset.html
+    pub fn finite_displacements(
+        &self,
+        amplitude: f64,
+        equivalent_atoms: Option<&[usize]>,
+    ) -> Result<Vec<FiniteDisplacement>, failure::Error>
+    {
+        ensure!(amplitude.is_finite() && amplitude > 0.0, "displacement amplitude must be positive: {}", amplitude);
+
+        let num_sites = self.num_sites();
+        let displaced_sites: Vec<usize> = match equivalent_atoms {
+            None => (0..num_sites).collect(),
+            Some(equivalent_atoms) => {
+                ensure!(
+                    equivalent_atoms.len() == num_sites,
+                    "expected {} equivalent atoms, got {}", num_sites, equivalent_atoms.len(),
+                );
+                let types = self.site_type_indices();
+                for (i, &rep) in equivalent_atoms.iter().enumerate() {
+                    ensure!(
+                        rep < num_sites && equivalent_atoms[rep] == rep && types[rep] == types[i],
+                        "site {} cannot be equivalent to site {}", i, rep,
+                    );
+                }
+                (0..num_sites).filter(|&i| equivalent_atoms[i] == i).collect()
+            },
+        };
+        let inverse = inv_f64(&self.scaled_lattice());
+        let scale = self.effective_scale_factor();
+        let stored_is_cart = self.0.positions.tag() == crate::types::CART;
+
+        // Whether a displacement leaves the frozen components of the stored coordinates alone.
+        let is_allowed = |site: usize, axis: usize, frac_shift: &[f64; 3]| {
+            let flags = match self.0.dynamics {
+                Some(ref dynamics) => dynamics[site],
+                None => return true,
+            };
+            match stored_is_cart {
+                true => flags[axis],
+                // (the tolerance absorbs roundoff in the inverse of the lattice)
+                false => (0..3).all(|k| flags[k] || frac_shift[k].abs() <= 1e-12 * norm_3(frac_shift)),
+            }
+        };
+
+        let mut out = vec![];
+        for &site in &displaced_sites {
+            for axis in 0..3 {
+                for &sign in &[1.0, -1.0] {
+                    let mut displacement = [0.0; 3];
+                    displacement[axis] = sign * amplitude;
+
+                    let shift = mul_3_33(&displacement, &inverse);
+                    if !is_allowed(site, axis, &shift) {
+                        continue;
+                    }
+                    let number = out.len() + 1;
+
+                    // Only the stored coordinates of the displaced site are touched, and
+                    // its frozen components are left exactly as they are.
+                    let flags = self.0.dynamics.as_ref().map_or([true; 3], |dynamics| dynamics[site]);
+                    let delta = match stored_is_cart {
+                        true => arr_3![k => displacement[k] / scale],
+                        false => arr_3![k => if flags[k] { shift[k] } else { 0.0 }],
+                    };
+                    let mut poscar = self.clone();
+                    let stored = &mut poscar.0.positions.as_mut().raw()[site];
+                    for (x, &d) in zip!(stored.iter_mut(), &delta) {
+                        if d != 0.0 {
+                            *x += d;
+                        }
+                    }
+                    poscar.0.comment = {
+                        let sign_char = if sign > 0.0 { '+' } else { '-' };
+                        let description = format!("disp-{:03} site {} {}{}", number, site + 1, sign_char, ["x", "y", "z"][axis]);
+                        match self.0.comment.is_empty() {
+                            true => description,
+                            false => format!("{} {}", self.0.comment, description),
+                        }
+                    };
+                    out.push(FiniteDisplacement { number, site, axis, displacement, poscar });
+                }
+            }
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+#[deny(unused)]
+mod tests {
+    use super::*;
+    use crate::util::test_structures::skewed_builder;
+    use crate::Coords;
+
+    fn example() -> Poscar {
+        skewed_builder()
+            .comment("")
+            .site_symbols(vec!["Ga", "As"])
+            .positions(Coords::Frac(vec![[0.0; 3], [0.25, 0.5, 0.75]]))
+            .build().unwrap()
+    }
+
+    #[test]
+    fn displacements() {
+        for poscar in [example(), example().to_cart_storage()] {
+            let displaced = poscar.finite_displacements(0.02, None).unwrap();
+            assert_eq!(displaced.len(), 12);
+
+            let original = poscar.scaled_cart_positions();
+            for (i, disp) in displaced.iter().enumerate() {
+                assert_eq!(disp.number, i + 1);
+                assert_eq!(disp.site, i / 6);
+                assert_eq!(disp.axis, (i / 2) % 3);
+                assert_eq!(disp.poscar.0.positions.tag(), poscar.0.positions.tag());
+
+                let carts = disp.poscar.scaled_cart_positions();
+                for k in 0..3 {
+                    let expected = original[disp.site][k] + disp.displacement[k];
+                    assert!((carts[disp.site][k] - expected).abs() < 1e-12);
+                }
+
+                // nothing else changes, not even by roundoff
+                let old = poscar.0.positions.as_ref().raw();
+                let new = disp.poscar.0.positions.as_ref().raw();
+                for site in 0..2 {
+                    for k in 0..3 {
+                        let is_cart = poscar.0.positions.tag() == Coords::Cart(());
+                        let untouched = site != disp.site || (is_cart && k != disp.axis);
+                        assert!(!untouched || old[site][k].to_bits() == new[site][k].to_bits());
+                    }
+                }
+                assert_eq!(disp.poscar.0.velocities, poscar.0.velocities);
+                assert_eq!(disp.poscar.scaled_lattice(), poscar.scaled_lattice());
+                let expected_sign = if i % 2 == 0 { 0.02 } else { -0.02 };
+                assert_eq!(disp.displacement[disp.axis], expected_sign);
+            }
+            assert_eq!(displaced[0].poscar.comment(), "disp-001 site 1 +x");
+            assert_eq!(displaced[11].poscar.comment(), "disp-012 site 2 -z");
+        }
+    }
+
+    #[test]
+    fn symmetry_and_dynamics() {
+        let (supercell, _) = example().supercell(&[[2, 0, 0], [0, 1, 0], [0, 0, 1]]).unwrap();
+        let equivalent_atoms = [0, 0, 2, 2];
+        let displaced = supercell.finite_displacements(0.01, Some(&equivalent_atoms)).unwrap();
+        let sites: Vec<usize> = displaced.iter().map(|d| d.site).collect();
+        assert_eq!(sites, vec![0, 0, 0, 0, 0, 0, 2, 2, 2, 2, 2, 2]);
+
+        // representatives must map to themselves, within a species
+        assert!(supercell.finite_displacements(0.01, Some(&[1, 0, 2, 2])).is_err());
+        assert!(supercell.finite_displacements(0.01, Some(&[0, 0, 0, 0])).is_err());
+        assert!(supercell.finite_displacements(0.01, Some(&[0, 0, 2])).is_err());
+        assert!(supercell.finite_displacements(0.0, None).is_err());
+
+        // Cartesian flags freeze Cartesian axes
+        let mut raw = supercell.to_cart_storage().into_raw();
+        raw.dynamics = Some(vec![[false; 3], [true; 3], [false, false, true], [false; 3]]);
+        let poscar = raw.validate().unwrap();
+        let displaced = poscar.finite_displacements(0.01, None).unwrap();
+        for disp in &displaced {
+            let old = poscar.0.positions.as_ref().raw();
+            let new = disp.poscar.0.positions.as_ref().raw();
+            for site in 0..4 {
+                for k in 0..3 {
+                    let moved = site == disp.site && k == disp.axis;
+                    assert_eq!(old[site][k].to_bits() == new[site][k].to_bits(), !moved);
+                }
+            }
+        }
+        let moves: Vec<(usize, usize)> = displaced.iter().map(|d| (d.site, d.axis)).collect();
+        assert_eq!(moves, vec![(1, 0), (1, 0), (1, 1), (1, 1), (1, 2), (1, 2), (2, 2), (2, 2)]);
+        assert_eq!(displaced[7].number, 8);
+        assert_eq!(displaced[7].poscar.comment(), "disp-008 site 3 -z");
+
+        // Direct flags freeze lattice directions.  Here, `x` is parallel to the first
+        // lattice vector and `y` lies in the plane of the first two, but `z` does not
+        // lie in any lattice plane.
+        for &(flags, expected_axes) in &[
+            ([true, false, false], &[0][..]),
+            ([true, true, false], &[0, 1][..]),
+            ([false, true, true], &[][..]),
+        ] {
+            let mut raw = supercell.clone().into_raw();
+            raw.dynamics = Some(vec![[false; 3], [false; 3], flags, [false; 3]]);
+            let poscar = raw.validate().unwrap();
+            let displaced = poscar.finite_displacements(0.01, None).unwrap();
+            let axes: Vec<usize> = displaced.iter().map(|d| d.axis).step_by(2).collect();
+            assert_eq!(axes, expected_axes.to_vec());
+            for disp in &displaced {
+                let old = poscar.0.positions.as_ref().raw();
+                let new = disp.poscar.0.positions.as_ref().raw();
+                for site in 0..4 {
+                    for k in 0..3 {
+                        let frozen = site != 2 || !flags[k];
+                        assert!(!frozen || old[site][k].to_bits() == new[site][k].to_bits());
+                    }
+                }
+            }
+        }
+    }
+}
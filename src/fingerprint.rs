@@ -0,0 +1,402 @@
+// Copyright 2018 Michael Lamparski
+// Part of the vasp-poscar crate.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Structural fingerprints.
+//
+// The fingerprint is meant to be stored, so the algorithm below is part of the
+// public API and will not change.  Any future variant will use a new version tag
+// in the key.
+//
+// 1. Species are the distinct labels of `Poscar::site_species_labels`, sorted
+//    by their UTF-8 bytes.
+// 2. The composition is the fraction of sites of each species, `count / num_sites`.
+// 3. The volume per atom is `Poscar::scaled_volume / num_sites`.
+// 4. With `n = round(cutoff / bin_width)` (at least 1), there are `n` bins centered
+//    at `k * bin_width` for `k` in `0..n`.  For every ordered pair of sites `(i, j)`
+//    (including periodic images of `i` itself) at a distance `r < n * bin_width`,
+//    let `x = r / bin_width`, `k = floor(x)` and `f = x - k`.  Then `(1 - f) / num_sites`
+//    is added to bin `k` and `f / num_sites` to bin `k + 1` (if `k + 1 < n`) of the
+//    histogram for the species pair `(a, b)` with `a <= b`.  Histograms are ordered
+//    by `a`, then `b`, and include pairs with no sites in range.
+// 5. The key is the text below, with fields separated by single spaces:
+//
+//        fp1 <bin_width> <n> <volume_resolution> <count_resolution>
+//        <symbol>=<round(1e6 * fraction)>   (for each species)
+//        v=<round(volume_per_atom / volume_resolution)>
+//        <a>-<b>:<comma-separated round(count / count_resolution)>   (for each pair)
+//
+//    Floats are written with Rust's `Display` (the shortest string that parses back
+//    to the same value), `round` rounds half away from zero, and trailing zeros of
+//    each histogram are removed (leaving `<a>-<b>:` if all are zero).
+// 6. The hash is the 64-bit FNV-1a hash of the UTF-8 bytes of the key.
+
+use crate::Poscar;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// Options for [`Poscar::fingerprint`].
+///
+/// [`Poscar::fingerprint`]: struct.Poscar.html#method.fingerprint
+#[derive(Debug, Clone, PartialEq)]
+pub struct FingerprintOptions {
+    /// Distances up to this are included in the histograms, in scaled units.
+    ///
+    /// This is rounded to a whole number of bins.  Defaults to `6.0`.
+    pub cutoff: f64,
+
+    /// Spacing between the centers of the histogram bins, in scaled units.
+    ///
+    /// Defaults to `0.2`.
+    pub bin_width: f64,
+
+    /// Resolution of the volume per atom in the [`key`].
+    ///
+    /// Defaults to `0.5`.
+    ///
+    /// [`key`]: struct.Fingerprint.html#method.key
+    pub volume_resolution: f64,
+
+    /// Resolution of the histogram values in the [`key`].
+    ///
+    /// Defaults to `0.25`.
+    ///
+    /// [`key`]: struct.Fingerprint.html#method.key
+    pub count_resolution: f64,
+}
+
+impl Default for FingerprintOptions {
+    fn default() -> Self
+    { FingerprintOptions {
+        cutoff: 6.0,
+        bin_width: 0.2,
+        volume_resolution: 0.5,
+        count_resolution: 0.25,
+    }}
+}
+
+impl FingerprintOptions {
+    /// Alias for [`Default`]`::default`.
+    ///
+    /// [`Default`]: https://doc.rust-lang.org/std/default/trait.Default.html
+    pub fn new() -> Self
+    { Default::default() }
+}
+
+/// A structural fingerprint produced by [`Poscar::fingerprint`].
+///
+/// [`Poscar::fingerprint`]: struct.Poscar.html#method.fingerprint
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fingerprint {
+    /// The options used to compute it.
+    pub options: FingerprintOptions,
+    /// The distinct species, sorted.
+    pub species: Vec<String>,
+    /// The fraction of sites belonging to each species.
+    pub composition: Vec<f64>,
+    /// Volume of the cell divided by the number of sites, in scaled units.
+    pub volume_per_atom: f64,
+    /// A distance histogram for each pair of species, ordered by the first
+    /// species and then the second.
+    pub histograms: Vec<PairHistogram>,
+}
+
+/// A species-pair distance histogram in a [`Fingerprint`].
+///
+/// [`Fingerprint`]: struct.Fingerprint.html
+#[derive(Debug, Clone, PartialEq)]
+pub struct PairHistogram {
+    /// Indices into [`Fingerprint::species`], with `species.0 <= species.1`.
+    ///
+    /// [`Fingerprint::species`]: struct.Fingerprint.html#structfield.species
+    pub species: (usize, usize),
+    /// Number of pairs at each distance, per site.
+    ///
+    /// Bin `k` is centered at `k * bin_width`, and each pair is split between the
+    /// two nearest bins in proportion to its proximity, so that the values change
+    /// continuously as sites move.
+    pub counts: Vec<f64>,
+}
+
+/// # Fingerprints
+impl Poscar {
+    /// Compute a fingerprint of the structure, for fast deduplication.
+    ///
+    /// The fingerprint consists of the composition, the volume per atom, and a
+    /// histogram of the interatomic distances for each pair of species.  It does not
+    /// depend on the order of the sites, the choice of unit cell, the orientation, or
+    /// the origin, so it is the same for a structure and its supercells.  Because
+    /// distances are binned smoothly, similar structures have nearby fingerprints
+    /// (see [`Fingerprint::distance`]), and most often the same [`key`] and [`hash`].
+    ///
+    /// Different structures can have the same fingerprint, so structures that agree
+    /// should be confirmed with [`structure_match`].
+    ///
+    /// The algorithm is fixed (it is described in full in the source of this module),
+    /// so that fingerprints remain valid across versions of this crate.
+    ///
+    /// ```rust
+    /// # fn main() -> Result<(), failure::Error> {Ok({
+    /// use vasp_poscar::{Builder, Coords, FingerprintOptions};
+    ///
+    /// let poscar =
+    ///     Builder::new()
+    ///     .lattice_vectors(&[[4.0, 0.0, 0.0], [0.0, 4.0, 0.0], [0.0, 0.0, 4.0]])
+    ///     .site_symbols(vec!["Na", "Cl"])
+    ///     .positions(Coords::Frac(vec![[0.0; 3], [0.5; 3]]))
+    ///     .build()?;
+    ///
+    /// let (supercell, _) = poscar.supercell(&[[1, 1, 0], [0, 1, 0], [0, 0, 2]])?;
+    ///
+    /// let options = FingerprintOptions::new();
+    /// let a = poscar.fingerprint(&options)?;
+    /// let b = supercell.fingerprint(&options)?;
+    /// assert_eq!(a.species, vec!["Cl", "Na"]);
+    /// assert_eq!(a.volume_per_atom, 32.0);
+    /// assert_eq!(a.key(), b.key());
+    /// assert!(a.distance(&b) < 1e-10);
+    /// # })}
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Fails if any option is not positive and finite.
+    ///
+    /// [`Fingerprint::distance`]: struct.Fingerprint.html#method.distance
+    /// [`key`]: struct.Fingerprint.html#method.key
+    /// [`hash`]: struct.Fingerprint.html#method.hash
+    /// [`structure_match`]: #method.structure_match
+    pub fn fingerprint(&self, options: &FingerprintOptions) -> Result<Fingerprint, failure::Error>
+    {
+        let FingerprintOptions { cutoff, bin_width, volume_resolution, count_resolution } = *options;
+        for &(name, value) in &[
+            ("cutoff", cutoff), ("bin_width", bin_width),
+            ("volume_resolution", volume_resolution), ("count_resolution", count_resolution),
+        ] {
+            ensure!(value.is_finite() && value > 0.0, "{} must be positive: {}", name, value);
+        }
+
+        let labels = self.site_species_labels();
+        let mut species = labels.clone();
+        species.sort();
+        species.dedup();
+        let site_species: Vec<usize> = {
+            labels.iter()
+                .map(|l| species.binary_search(l).expect("(BUG) missing species"))
+                .collect()
+        };
+
+        let num_sites = labels.len() as f64;
+        let composition = {
+            (0..species.len())
+                .map(|s| site_species.iter().filter(|&&x| x == s).count() as f64 / num_sites)
+                .collect()
+        };
+        let volume_per_atom = self.scaled_volume() / num_sites;
+
+        let num_species = species.len();
+        let num_bins = usize::max(1, (cutoff / bin_width).round() as usize);
+        let mut histograms: Vec<PairHistogram> = {
+            (0..num_species)
+                .flat_map(|a| (a..num_species).map(move |b| (a, b)))
+                .map(|species| PairHistogram { species, counts: vec![0.0; num_bins] })
+                .collect()
+        };
+
+        for neighbor in self.neighbors(num_bins as f64 * bin_width) {
+            let (a, b) = (site_species[neighbor.i], site_species[neighbor.j]);
+            let index = histograms.binary_search_by_key(&(a.min(b), a.max(b)), |h| h.species).expect("(BUG) missing pair");
+            let counts = &mut histograms[index].counts;
+            let x = neighbor.distance / bin_width;
+            let k = x.floor() as usize;
+            let f = x - k as f64;
+            if k < num_bins {
+                counts[k] += (1.0 - f) / num_sites;
+            }
+            if k + 1 < num_bins {
+                counts[k + 1] += f / num_sites;
+            }
+        }
+
+        Ok(Fingerprint { options: options.clone(), species, composition, volume_per_atom, histograms })
+    }
+}
+
+impl Fingerprint {
+    /// A measure of how different two fingerprints are.
+    ///
+    /// This is the sum of:
+    ///
+    /// * the total absolute difference in the composition,
+    /// * the absolute difference in the volume per atom, relative to their mean, and
+    /// * the total absolute difference in the histograms, relative to the mean of
+    ///   their totals (i.e. roughly the fraction of pairs that moved by one bin width).
+    ///
+    /// It is zero for identical fingerprints, and infinite if the species or the
+    /// `cutoff` and `bin_width` options differ.
+    pub fn distance(&self, other: &Fingerprint) -> f64
+    {
+        if self.species != other.species
+            || self.options.cutoff != other.options.cutoff
+            || self.options.bin_width != other.options.bin_width
+        {
+            return f64::INFINITY;
+        }
+
+        let composition = zip!(&self.composition, &other.composition).map(|(a, b)| (a - b).abs()).sum::<f64>();
+        let volume = {
+            let (a, b) = (self.volume_per_atom, other.volume_per_atom);
+            if a == b { 0.0 } else { (a - b).abs() / (0.5 * (a + b)) }
+        };
+        let histograms = {
+            let pairs = || zip!(&self.histograms, &other.histograms).flat_map(|(a, b)| zip!(&a.counts, &b.counts));
+            let difference = pairs().map(|(a, b)| (a - b).abs()).sum::<f64>();
+            let mean = pairs().map(|(a, b)| 0.5 * (a + b)).sum::<f64>();
+            if difference == 0.0 { 0.0 } else { difference / mean }
+        };
+        composition + volume + histograms
+    }
+
+    /// A canonical text representation with quantized values.
+    ///
+    /// Similar structures usually have the same key, but not always, since two
+    /// values can be arbitrarily close and still round differently.  Use
+    /// [`distance`] for a robust comparison.
+    ///
+    /// The format is stable; see the source of this module for its definition.
+    ///
+    /// [`distance`]: #method.distance
+    pub fn key(&self) -> String
+    {
+        let FingerprintOptions { bin_width, volume_resolution, count_resolution, .. } = self.options;
+        let num_bins = self.histograms.first().map_or(0, |h| h.counts.len());
+
+        let mut words = vec![format!("fp1 {} {} {} {}", bin_width, num_bins, volume_resolution, count_resolution)];
+        for (symbol, fraction) in zip!(&self.species, &self.composition) {
+            words.push(format!("{}={}", symbol, quantize(*fraction, 1e-6)));
+        }
+        words.push(format!("v={}", quantize(self.volume_per_atom, volume_resolution)));
+        for histogram in &self.histograms {
+            let mut counts: Vec<i64> = histogram.counts.iter().map(|&c| quantize(c, count_resolution)).collect();
+            while counts.last() == Some(&0) {
+                counts.pop();
+            }
+            let counts: Vec<String> = counts.iter().map(|c| c.to_string()).collect();
+            let (a, b) = histogram.species;
+            words.push(format!("{}-{}:{}", self.species[a], self.species[b], counts.join(",")));
+        }
+        words.join(" ")
+    }
+
+    /// The 64-bit FNV-1a hash of the [`key`].
+    ///
+    /// [`key`]: #method.key
+    pub fn hash(&self) -> u64
+    {
+        self.key().bytes().fold(FNV_OFFSET_BASIS, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME)
+        })
+    }
+}
+
+fn quantize(value: f64, resolution: f64) -> i64
+{ (value / resolution).round() as i64 }
+
+#[cfg(test)]
+#[deny(unused)]
+mod tests {
+    use super::*;
+    use crate::util::test_structures::low_symmetry;
+    use crate::{Builder, Coords};
+
+    #[test]
+    fn layout() {
+        let fingerprint = low_symmetry().fingerprint(&FingerprintOptions::new()).unwrap();
+        assert_eq!(fingerprint.species, vec!["O", "Sr", "Ti"]);
+        assert_eq!(fingerprint.composition, vec![0.6, 0.2, 0.2]);
+        let pairs: Vec<_> = fingerprint.histograms.iter().map(|h| h.species).collect();
+        assert_eq!(pairs, vec![(0, 0), (0, 1), (0, 2), (1, 1), (1, 2), (2, 2)]);
+        assert!(fingerprint.histograms.iter().all(|h| h.counts.len() == 30));
+
+        // every ordered pair adds 1 / num_sites in total, except near the cutoff
+        let total: f64 = fingerprint.histograms.iter().flat_map(|h| h.counts.iter()).sum();
+        let neighbors = low_symmetry().neighbors(6.0);
+        let lower = neighbors.iter().filter(|n| n.distance <= 5.8).count() as f64 / 5.0;
+        let upper = neighbors.len() as f64 / 5.0;
+        assert!(lower - 1e-9 <= total && total <= upper + 1e-9, "{} {} {}", lower, total, upper);
+    }
+
+    #[test]
+    fn invariance() {
+        let options = FingerprintOptions::new();
+        let original = low_symmetry().fingerprint(&options).unwrap();
+
+        let mut other = low_symmetry();
+        other.change_basis(&[[1, 1, 0], [0, 1, 0], [-1, 0, -1]]).unwrap();
+        other.permute_sites(&[4, 3, 0, 2, 1]);
+        let (other, _) = other.supercell(&[[2, 0, 0], [0, 1, 0], [0, 1, 1]]).unwrap();
+        let mut other = other.to_cart_storage();
+        let (s, c) = (0.6, 0.8);
+        other.rotate(&[[c, s, 0.0], [-s, c, 0.0], [0.0, 0.0, 1.0]]).unwrap();
+        let fingerprint = other.fingerprint(&options).unwrap();
+
+        assert!(original.distance(&fingerprint) < 1e-10);
+        assert_eq!(original.key(), fingerprint.key());
+        assert_eq!(original.hash(), fingerprint.hash());
+    }
+
+    #[test]
+    fn similarity() {
+        let options = FingerprintOptions::new();
+        let original = low_symmetry().fingerprint(&options).unwrap();
+
+        let mut rattled = low_symmetry();
+        rattled.rattle(0.01, 3, true).unwrap();
+        let rattled = rattled.fingerprint(&options).unwrap();
+        assert!(original.distance(&rattled) > 0.0);
+
+        let mut distorted = low_symmetry();
+        distorted.rattle(0.3, 3, true).unwrap();
+        let distorted = distorted.fingerprint(&options).unwrap();
+        assert!(original.distance(&rattled) < 0.1);
+        assert!(original.distance(&distorted) > 5.0 * original.distance(&rattled));
+        assert_ne!(original.key(), distorted.key());
+
+        let mut raw = low_symmetry().into_raw();
+        raw.group_symbols.as_mut().unwrap()[2] = "Ba".into();
+        let substituted = raw.validate().unwrap().fingerprint(&options).unwrap();
+        assert_eq!(original.distance(&substituted), f64::INFINITY);
+
+        let coarse = low_symmetry().fingerprint(&FingerprintOptions { bin_width: 0.5, ..options }).unwrap();
+        assert_eq!(original.distance(&coarse), f64::INFINITY);
+
+        for bad in &[
+            FingerprintOptions { cutoff: 0.0, ..options.clone() },
+            FingerprintOptions { bin_width: f64::NAN, ..options.clone() },
+            FingerprintOptions { count_resolution: -1.0, ..options.clone() },
+        ] {
+            assert!(low_symmetry().fingerprint(bad).is_err());
+        }
+    }
+
+    #[test]
+    fn stable_key() {
+        let fingerprint = {
+            Builder::new()
+                .lattice_vectors(&[[4.0, 0.0, 0.0], [0.0, 4.0, 0.0], [0.0, 0.0, 4.0]])
+                .site_symbols(vec!["Na", "Cl"])
+                .positions(Coords::Frac(vec![[0.0; 3], [0.5; 3]]))
+                .build().unwrap()
+                .fingerprint(&FingerprintOptions { cutoff: 4.0, bin_width: 1.0, ..FingerprintOptions::new() }).unwrap()
+        };
+        // Cl-Cl and Na-Na: 6 neighbors at 4.0 (just outside).  Cl-Na: 8 at 3.4641.
+        assert_eq!(fingerprint.key(), "fp1 1 4 0.5 0.25 Cl=500000 Na=500000 v=64 Cl-Cl: Cl-Na:0,0,0,17 Na-Na:");
+        assert_eq!(fingerprint.hash(), 0xae00_b6f6_9907_cf0d);
+    }
+}
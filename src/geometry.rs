@@ -0,0 +1,357 @@
+// Copyright 2018 Michael Lamparski
+// Part of the vasp-poscar crate.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use crate::Poscar;
+use crate::math::{inv_f64, mul_3_33, norm_3, sub_3, add_3};
+
+/// # Periodic images
+impl Poscar {
+    /// Move every site into the unit cell by adding lattice vectors, so that
+    /// all fractional coordinates lie in `[0, 1)`.
+    ///
+    /// Fractional coordinates within `tol` below `1.0` are sent to `0.0`,
+    /// which helps avoid printing things like `0.99999999999`.
+    ///
+    /// The positions are written back in the same form as they were stored
+    /// (Cartesian or direct).  Velocities are not modified.
+    ///
+    /// ```rust
+    /// # fn main() -> Result<(), failure::Error> {Ok({
+    /// use vasp_poscar::{Builder, Coords};
+    ///
+    /// let mut poscar =
+    ///     Builder::new()
+    ///     .dummy_lattice_vectors()
+    ///     .positions(Coords::Frac(vec![[1.25, -0.25, 0.9999999999]]))
+    ///     .build()?;
+    ///
+    /// poscar.wrap_positions(1e-8);
+    /// assert_eq!(&poscar.frac_positions()[..], &[[0.25, 0.75, 0.0]]);
+    /// # })}
+    /// ```
+    pub fn wrap_positions(&mut self, tol: f64)
+    {
+        let mut fracs = self.frac_positions().into_owned();
+        for x in fracs.iter_mut().flat_map(|v| v.iter_mut()) {
+            *x = crate::canonical::wrap_unit(*x, tol);
+        }
+        self.set_frac_positions(fracs);
+    }
+
+    /// Replace each site with the periodic image of itself that lies closest
+    /// to the corresponding site in `reference`.
+    ///
+    /// This undoes the effect of wrapping, and is useful for comparing e.g. a
+    /// relaxed structure to its initial structure.  Sites are paired up by index,
+    /// and the displacement of each is measured as a fractional difference
+    /// (so that the reference may have a slightly different lattice), with the
+    /// "closest" image determined using this Poscar's lattice.
+    ///
+    /// The positions are written back in the same form as they were stored
+    /// (Cartesian or direct).  Velocities are not modified.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the number of sites differs between the two structures.
+    ///
+    /// ```rust
+    /// # fn main() -> Result<(), failure::Error> {Ok({
+    /// use vasp_poscar::{Builder, Coords};
+    ///
+    /// let reference =
+    ///     Builder::new()
+    ///     .dummy_lattice_vectors()
+    ///     .positions(Coords::Frac(vec![[0.0, 0.5, 0.99]]))
+    ///     .build()?;
+    ///
+    /// let mut poscar =
+    ///     Builder::new()
+    ///     .dummy_lattice_vectors()
+    ///     .positions(Coords::Frac(vec![[0.75, 0.5, 0.0]]))
+    ///     .build()?;
+    ///
+    /// poscar.unwrap_relative_to(&reference)?;
+    /// assert_eq!(&poscar.frac_positions()[..], &[[-0.25, 0.5, 1.0]]);
+    /// # })}
+    /// ```
+    pub fn unwrap_relative_to(&mut self, reference: &Poscar) -> Result<(), failure::Error>
+    {
+        ensure!(
+            self.num_sites() == reference.num_sites(),
+            "cannot unwrap relative to a structure with a different number of sites ({} vs {})",
+            self.num_sites(), reference.num_sites(),
+        );
+
+        let min_image = MinImage::new(&self.scaled_lattice());
+        let fracs = {
+            zip!(&self.frac_positions()[..], &reference.frac_positions()[..])
+                .map(|(x, x_ref)| add_3(x_ref, &min_image.reduce_frac(&sub_3(x, x_ref))))
+                .collect()
+        };
+        self.set_frac_positions(fracs);
+        Ok(())
+    }
+}
+
+/// # Distances
+///
+/// These use the minimum image convention, and take the scale line into account.
+/// The shortest image is found by a search that is exact even for highly
+/// skewed cells.
+impl Poscar {
+    /// Get the shortest Cartesian vector from site `i` to any periodic image of site `j`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if either index is out of bounds.
+    pub fn displacement(&self, i: usize, j: usize) -> [f64; 3]
+    {
+        let fracs = self.frac_positions();
+        let min_image = MinImage::new(&self.scaled_lattice());
+
+        // (always compute in the same direction so that the result is exactly antisymmetric)
+        match i <= j {
+            true => min_image.cart_displacement(&fracs[i], &fracs[j]),
+            false => {
+                let v = min_image.cart_displacement(&fracs[j], &fracs[i]);
+                [-v[0], -v[1], -v[2]]
+            },
+        }
+    }
+
+    /// Get the distance between site `i` and the nearest periodic image of site `j`.
+    ///
+    /// When `i == j`, this is zero.
+    ///
+    /// ```rust
+    /// # fn main() -> Result<(), failure::Error> {Ok({
+    /// use vasp_poscar::{Builder, Coords, ScaleLine};
+    ///
+    /// let poscar =
+    ///     Builder::new()
+    ///     .scale(ScaleLine::Factor(4.0))
+    ///     .dummy_lattice_vectors()
+    ///     .positions(Coords::Frac(vec![[0.125, 0.0, 0.0], [0.875, 0.0, 0.0]]))
+    ///     .build()?;
+    ///
+    /// assert_eq!(poscar.displacement(0, 1), [-1.0, 0.0, 0.0]);
+    /// assert_eq!(poscar.distance(0, 1), 1.0);
+    /// # })}
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if either index is out of bounds.
+    pub fn distance(&self, i: usize, j: usize) -> f64
+    { norm_3(&self.displacement(i, j)) }
+
+    /// Compute the minimum image distance between all pairs of sites.
+    ///
+    /// `matrix[i][j]` is equal to `self.distance(i, j)`.
+    pub fn distance_matrix(&self) -> Vec<Vec<f64>>
+    {
+        let fracs = self.frac_positions();
+        let min_image = MinImage::new(&self.scaled_lattice());
+
+        let n = fracs.len();
+        let mut out = vec![vec![0.0; n]; n];
+        for i in 0..n {
+            for j in i + 1..n {
+                let d = norm_3(&min_image.cart_displacement(&fracs[i], &fracs[j]));
+                out[i][j] = d;
+                out[j][i] = d;
+            }
+        }
+        out
+    }
+}
+
+/// Helper for finding the shortest periodic image of a displacement.
+///
+/// Unlike the simple strategy of rounding the fractional coordinates (which
+/// can give wrong answers for skewed cells), this searches over all lattice
+/// points that could possibly produce a shorter vector.
+#[derive(Debug, Clone)]
+pub(crate) struct MinImage {
+    lattice: [[f64; 3]; 3],
+    // norms of the columns of the inverse lattice matrix.  The i-th fractional
+    // component of a Cartesian vector `v` is bounded by `|v| * inv_norms[i]`.
+    inv_norms: [f64; 3],
+}
+
+impl MinImage {
+    pub(crate) fn new(lattice: &[[f64; 3]; 3]) -> Self
+    {
+        let inv = inv_f64(lattice);
+        let inv_norms = arr_3![i => norm_3(&arr_3![k => inv[k][i]])];
+        MinImage { lattice: *lattice, inv_norms }
+    }
+
+    /// Get the lattice point `n` minimizing the Cartesian norm of `(frac + n)`.
+    ///
+    /// Ties are broken deterministically.
+    pub(crate) fn image_shift(&self, frac: &[f64; 3]) -> [f64; 3]
+    {
+        let rounded = arr_3![k => -frac[k].round()];
+        let start = add_3(frac, &rounded);
+        let radius = norm_3(&mul_3_33(&start, &self.lattice));
+
+        // Any shorter vector has every fractional component bounded by this.
+        // (a little slack is added so that ties on the boundary are considered)
+        let bounds = arr_3![k => radius * self.inv_norms[k] * (1.0 + 1e-10) + 1e-10];
+        let ranges = arr_3![k => {
+            let lo = (-bounds[k] - start[k]).ceil() as i64;
+            let hi = (bounds[k] - start[k]).floor() as i64;
+            lo..=hi
+        }];
+
+        let mut best = ([0.0; 3], radius);
+        for i in ranges[0].clone() {
+            for j in ranges[1].clone() {
+                for k in ranges[2].clone() {
+                    let shift = [i as f64, j as f64, k as f64];
+                    let cart = mul_3_33(&add_3(&start, &shift), &self.lattice);
+                    let norm = norm_3(&cart);
+                    if norm < best.1 * (1.0 - 1e-12) {
+                        best = (shift, norm);
+                    }
+                }
+            }
+        }
+        add_3(&rounded, &best.0)
+    }
+
+    /// Get the shortest periodic image of a fractional displacement,
+    /// in fractional coordinates.
+    pub(crate) fn reduce_frac(&self, frac: &[f64; 3]) -> [f64; 3]
+    { add_3(frac, &self.image_shift(frac)) }
+
+    /// Get the shortest Cartesian vector from the point at fractional
+    /// coordinates `from` to any image of `to`.
+    pub(crate) fn cart_displacement(&self, from: &[f64; 3], to: &[f64; 3]) -> [f64; 3]
+    { mul_3_33(&self.reduce_frac(&sub_3(to, from)), &self.lattice) }
+}
+
+#[cfg(test)]
+#[deny(unused)]
+mod tests {
+    use super::*;
+    use crate::{Builder, Coords, ScaleLine};
+
+    #[test]
+    fn wrap_keeps_storage() {
+        let poscar =
+            Builder::new()
+            .scale(ScaleLine::Factor(2.0))
+            .lattice_vectors(&[[1.0, 0.0, 0.0], [0.0, 2.0, 0.0], [0.0, 0.0, 4.0]])
+            .positions(Coords::Cart(vec![[1.5, -1.0, 4.0]]))
+            .build().unwrap();
+
+        let mut wrapped = poscar.clone();
+        wrapped.wrap_positions(0.0);
+        assert_eq!(wrapped.0.positions, Coords::Cart(vec![[0.5, 1.0, 0.0]]));
+
+        let mut unwrapped = wrapped.clone();
+        unwrapped.unwrap_relative_to(&poscar).unwrap();
+        assert_eq!(unwrapped.0.positions, poscar.0.positions);
+    }
+
+    #[test]
+    fn unwrap_wrong_length() {
+        let one =
+            Builder::new()
+            .dummy_lattice_vectors()
+            .positions(Coords::Frac(vec![[0.0; 3]]))
+            .build().unwrap();
+        let two =
+            Builder::new()
+            .dummy_lattice_vectors()
+            .positions(Coords::Frac(vec![[0.0; 3]; 2]))
+            .build().unwrap();
+        assert!(one.clone().unwrap_relative_to(&two).is_err());
+    }
+
+    #[test]
+    fn distances() {
+        // A skewed lattice with a nontrivial scale
+        let lattice = [[1.0, 0.0, 0.0], [4.0, 1.0, 0.0], [0.5, 0.5, 2.0]];
+        let fracs = vec![[0.0, 0.0, 0.0], [0.1, 0.5, 0.9], [0.6, 0.2, 0.3]];
+        for &scale in &[ScaleLine::Factor(1.5), ScaleLine::Volume(5.0)] {
+            let poscar =
+                Builder::new()
+                .scale(scale)
+                .lattice_vectors(&lattice)
+                .positions(Coords::Frac(fracs.clone()))
+                .build().unwrap();
+            let scaled = poscar.scaled_lattice();
+
+            let matrix = poscar.distance_matrix();
+            for i in 0..3 {
+                assert_eq!(matrix[i][i], 0.0);
+                for j in 0..3 {
+                    assert_eq!(matrix[i][j], matrix[j][i]);
+                    assert_eq!(matrix[i][j], poscar.distance(i, j));
+
+                    // brute force
+                    let mut best = f64::INFINITY;
+                    for a in -6..=6 {
+                        for b in -6..=6 {
+                            for c in -6..=6 {
+                                let d = add_3(&sub_3(&fracs[j], &fracs[i]), &[a as f64, b as f64, c as f64]);
+                                best = f64::min(best, norm_3(&mul_3_33(&d, &scaled)));
+                            }
+                        }
+                    }
+                    assert!((matrix[i][j] - best).abs() < 1e-12);
+
+                    // displacement is a lattice translation away from the naive one
+                    let naive = mul_3_33(&sub_3(&fracs[j], &fracs[i]), &scaled);
+                    let diff = sub_3(&poscar.displacement(i, j), &naive);
+                    let diff_frac = mul_3_33(&diff, &inv_f64(&scaled));
+                    for x in &diff_frac {
+                        assert!((x - x.round()).abs() < 1e-10);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn min_image_skewed() {
+        // A very skewed 2D-like cell where rounding the fractional
+        // coordinates does not give the shortest vector.
+        let lattice = [[1.0, 0.0, 0.0], [10.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+        let min_image = MinImage::new(&lattice);
+
+        let frac = [0.0, 0.5, 0.0];
+        let reduced = min_image.reduce_frac(&frac);
+        let cart = mul_3_33(&reduced, &lattice);
+        // naive rounding gives length ~5.02; the true minimum is [0, 0.5] - 5 a,
+        // which is of length 0.5
+        assert!((norm_3(&cart) - 0.5).abs() < 1e-12, "{:?}", cart);
+
+        // brute force comparison on a few arbitrary vectors
+        for &frac in &[[0.3, -0.7, 0.2], [0.49, 0.49, 0.49], [2.6, -3.1, 0.9]] {
+            let best = {
+                let mut best = f64::INFINITY;
+                for i in -20..=20 {
+                    for j in -20..=20 {
+                        for k in -20..=20 {
+                            let v = add_3(&frac, &[i as f64, j as f64, k as f64]);
+                            best = f64::min(best, norm_3(&mul_3_33(&v, &lattice)));
+                        }
+                    }
+                }
+                best
+            };
+            let cart = mul_3_33(&min_image.reduce_frac(&frac), &lattice);
+            assert!((norm_3(&cart) - best).abs() < 1e-12);
+        }
+    }
+}
@@ -0,0 +1,372 @@
+// Copyright 2018 Michael Lamparski
+// Part of the vasp-poscar crate.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use crate::{Poscar, Builder};
+use crate::math::{dot_f64, norm_3};
+
+/// The lengths and angles of a lattice.
+///
+/// Lengths are in the same units as the lattice vectors, and
+/// angles are in **degrees**.  Following the usual crystallographic
+/// convention, `alpha` is the angle between `b` and `c`, `beta` is
+/// the angle between `a` and `c`, and `gamma` is the angle between `a` and `b`.
+///
+/// Lattice parameters do not describe the orientation or handedness of
+/// a lattice.  [`lattice_vectors`] will always produce a right-handed lattice
+/// in one of the orientations described by [`LatticeOrientation`].
+///
+/// [`lattice_vectors`]: #method.lattice_vectors
+/// [`LatticeOrientation`]: enum.LatticeOrientation.html
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LatticeParams {
+    pub a: f64,
+    pub b: f64,
+    pub c: f64,
+    pub alpha: f64,
+    pub beta: f64,
+    pub gamma: f64,
+}
+
+/// A standard orientation for lattice vectors constructed from [`LatticeParams`].
+///
+/// [`LatticeParams`]: struct.LatticeParams.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LatticeOrientation {
+    /// `a` points along `+x`, and `b` lies in the xy plane with a positive y component.
+    ///
+    /// The matrix whose rows are the lattice vectors is lower triangular.
+    /// This is the orientation used by e.g. LAMMPS.
+    ///
+    /// ```text
+    /// a = [ax,  0,  0]
+    /// b = [bx, by,  0]
+    /// c = [cx, cy, cz]
+    /// ```
+    LowerTriangular,
+
+    /// `c` points along `+z`, and `b` lies in the yz plane with a positive y component.
+    ///
+    /// The matrix whose rows are the lattice vectors is upper triangular.
+    ///
+    /// ```text
+    /// a = [ax, ay, az]
+    /// b = [ 0, by, bz]
+    /// c = [ 0,  0, cz]
+    /// ```
+    UpperTriangular,
+}
+
+/// # Constructors for each crystal family
+///
+/// These fill in the parameters that are fixed by the lattice system.
+impl LatticeParams {
+    /// Construct from all six parameters. (angles in degrees)
+    pub fn new(a: f64, b: f64, c: f64, alpha: f64, beta: f64, gamma: f64) -> Self
+    { LatticeParams { a, b, c, alpha, beta, gamma } }
+
+    /// `a = b = c`, `alpha = beta = gamma = 90`.
+    pub fn cubic(a: f64) -> Self
+    { Self::new(a, a, a, 90.0, 90.0, 90.0) }
+
+    /// `a = b`, `alpha = beta = gamma = 90`.
+    pub fn tetragonal(a: f64, c: f64) -> Self
+    { Self::new(a, a, c, 90.0, 90.0, 90.0) }
+
+    /// `alpha = beta = gamma = 90`.
+    pub fn orthorhombic(a: f64, b: f64, c: f64) -> Self
+    { Self::new(a, b, c, 90.0, 90.0, 90.0) }
+
+    /// `a = b`, `alpha = beta = 90`, `gamma = 120`.
+    pub fn hexagonal(a: f64, c: f64) -> Self
+    { Self::new(a, a, c, 90.0, 90.0, 120.0) }
+
+    /// `a = b = c`, `alpha = beta = gamma` (rhombohedral axes).
+    pub fn rhombohedral(a: f64, alpha: f64) -> Self
+    { Self::new(a, a, a, alpha, alpha, alpha) }
+
+    /// `alpha = gamma = 90` (unique axis `b`).
+    pub fn monoclinic(a: f64, b: f64, c: f64, beta: f64) -> Self
+    { Self::new(a, b, c, 90.0, beta, 90.0) }
+
+    /// No constraints.  Equivalent to `new`.
+    pub fn triclinic(a: f64, b: f64, c: f64, alpha: f64, beta: f64, gamma: f64) -> Self
+    { Self::new(a, b, c, alpha, beta, gamma) }
+}
+
+impl LatticeParams {
+    /// Compute the parameters of a lattice, given as three row vectors.
+    pub fn from_lattice_vectors(vectors: &[[f64; 3]; 3]) -> Self
+    {
+        let lengths = arr_3![i => norm_3(&vectors[i])];
+        let angle = |i: usize, j: usize| {
+            let cos = dot_f64(&vectors[i], &vectors[j]) / (lengths[i] * lengths[j]);
+            cos.clamp(-1.0, 1.0).acos().to_degrees()
+        };
+        LatticeParams {
+            a: lengths[0],
+            b: lengths[1],
+            c: lengths[2],
+            alpha: angle(1, 2),
+            beta: angle(0, 2),
+            gamma: angle(0, 1),
+        }
+    }
+
+    /// Construct right-handed lattice vectors with these parameters.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the lengths are not positive, or if the angles do not describe
+    /// a non-degenerate parallelepiped (e.g. `alpha > beta + gamma`).
+    pub fn lattice_vectors(&self, orientation: LatticeOrientation) -> [[f64; 3]; 3]
+    {
+        match self.checked_lattice_vectors(orientation) {
+            Ok(vectors) => vectors,
+            Err(msg) => panic!("{}: {:?}", msg, self),
+        }
+    }
+
+    // Fallible form of `lattice_vectors`, for `Builder::build`.
+    pub(crate) fn checked_lattice_vectors(&self, orientation: LatticeOrientation) -> Result<[[f64; 3]; 3], &'static str>
+    {
+        let LatticeParams { a, b, c, alpha, beta, gamma } = *self;
+        if !(a > 0.0 && b > 0.0 && c > 0.0) {
+            return Err("lattice lengths must be positive");
+        }
+
+        let (cos_a, cos_b, cos_g) = (cos_deg(alpha), cos_deg(beta), cos_deg(gamma));
+        Ok(match orientation {
+            LatticeOrientation::LowerTriangular => {
+                let sin_g = sin_deg(gamma);
+                let cy = (cos_a - cos_b * cos_g) / sin_g;
+                let cz = checked_sqrt(1.0 - cos_b * cos_b - cy * cy)?;
+                [
+                    [a, 0.0, 0.0],
+                    [b * cos_g, b * sin_g, 0.0],
+                    [c * cos_b, c * cy, c * cz],
+                ]
+            },
+            LatticeOrientation::UpperTriangular => {
+                let sin_a = sin_deg(alpha);
+                let ay = (cos_g - cos_a * cos_b) / sin_a;
+                let ax = checked_sqrt(1.0 - cos_b * cos_b - ay * ay)?;
+                [
+                    [a * ax, a * ay, a * cos_b],
+                    [0.0, b * sin_a, b * cos_a],
+                    [0.0, 0.0, c],
+                ]
+            },
+        })
+    }
+
+    /// Volume of a cell with these parameters.
+    pub fn volume(&self) -> f64
+    {
+        let (cos_a, cos_b, cos_g) = (cos_deg(self.alpha), cos_deg(self.beta), cos_deg(self.gamma));
+        let factor = 1.0 - cos_a * cos_a - cos_b * cos_b - cos_g * cos_g + 2.0 * cos_a * cos_b * cos_g;
+        self.a * self.b * self.c * factor.max(0.0).sqrt()
+    }
+}
+
+// Exact values for the most common angles, so that e.g. cubic lattices
+// come out without any 6.1e-17 garbage.
+fn cos_deg(x: f64) -> f64
+{
+    if x == 90.0 { 0.0 }
+    else if x == 60.0 { 0.5 }
+    else if x == 120.0 { -0.5 }
+    else { x.to_radians().cos() }
+}
+
+fn sin_deg(x: f64) -> f64
+{
+    if x == 90.0 { 1.0 }
+    else { x.to_radians().sin() }
+}
+
+fn checked_sqrt(x: f64) -> Result<f64, &'static str>
+{
+    if x > 0.0 { Ok(x.sqrt()) }
+    else { Err("lattice angles do not describe a valid cell") }
+}
+
+/// # Accessing lattice parameters
+impl Poscar {
+    /// Compute the lengths and angles of the lattice, taking the scale line into account.
+    pub fn scaled_lattice_parameters(&self) -> LatticeParams
+    { LatticeParams::from_lattice_vectors(&self.scaled_lattice()) }
+
+    /// Compute the lengths and angles of the lattice as it is written in the file.
+    pub fn unscaled_lattice_parameters(&self) -> LatticeParams
+    { LatticeParams::from_lattice_vectors(&self.unscaled_lattice()) }
+}
+
+/// # Setting the lattice from parameters
+impl Builder {
+    /// Set the unscaled lattice vectors from lengths and angles (in degrees).
+    ///
+    /// The vectors are constructed in a standard orientation described by
+    /// [`LatticeOrientation`].  See [`LatticeParams::lattice_vectors`] for details.
+    ///
+    /// ```rust
+    /// use vasp_poscar::{Builder, Coords, LatticeOrientation};
+    ///
+    /// let raw =
+    ///     Builder::new()
+    ///     .lattice_parameters(2.0, 2.0, 5.0, 90.0, 90.0, 120.0, LatticeOrientation::LowerTriangular)
+    ///     .positions(Coords::Frac(vec![[0.0; 3]]))
+    ///     .build_raw();
+    ///
+    /// assert_eq!(raw.lattice_vectors[0], [2.0, 0.0, 0.0]);
+    /// assert_eq!(raw.lattice_vectors[2], [0.0, 0.0, 5.0]);
+    /// ```
+    ///
+    /// The parameters are checked when building.  If they do not describe a valid
+    /// cell, [`build`] returns a [`ValidationError`] and [`build_raw`] panics.
+    ///
+    /// [`build`]: #method.build
+    /// [`build_raw`]: #method.build_raw
+    /// [`ValidationError`]: ../enum.ValidationError.html
+    /// [`LatticeOrientation`]: ../enum.LatticeOrientation.html
+    /// [`LatticeParams::lattice_vectors`]: ../struct.LatticeParams.html#method.lattice_vectors
+    #[allow(clippy::too_many_arguments)]
+    pub fn lattice_parameters(
+        &mut self,
+        a: f64, b: f64, c: f64,
+        alpha: f64, beta: f64, gamma: f64,
+        orientation: LatticeOrientation,
+    ) -> &mut Self {
+        self._lattice_parameters(LatticeParams::new(a, b, c, alpha, beta, gamma), orientation)
+    }
+}
+
+#[cfg(test)]
+#[deny(unused)]
+mod tests {
+    use super::*;
+    use crate::{Coords, ScaleLine};
+    use crate::math::{det_f64, inv_f64, mul_3_33};
+
+    fn assert_params_close(a: &LatticeParams, b: &LatticeParams) {
+        let a_arr = [a.a, a.b, a.c, a.alpha, a.beta, a.gamma];
+        let b_arr = [b.a, b.b, b.c, b.alpha, b.beta, b.gamma];
+        for (x, y) in zip!(&a_arr, &b_arr) {
+            assert!((x - y).abs() < 1e-10, "{:?} vs {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let cases = vec![
+            LatticeParams::cubic(3.5),
+            LatticeParams::tetragonal(3.0, 5.0),
+            LatticeParams::orthorhombic(3.0, 4.0, 5.0),
+            LatticeParams::hexagonal(2.46, 6.7),
+            LatticeParams::rhombohedral(5.0, 55.0),
+            LatticeParams::monoclinic(3.0, 4.0, 5.0, 100.0),
+            LatticeParams::triclinic(3.0, 4.0, 5.0, 70.0, 80.0, 110.0),
+        ];
+        for params in cases {
+            for &orientation in &[LatticeOrientation::LowerTriangular, LatticeOrientation::UpperTriangular] {
+                let vectors = params.lattice_vectors(orientation);
+                assert_params_close(&params, &LatticeParams::from_lattice_vectors(&vectors));
+
+                // right-handed, with the expected volume
+                let det = det_f64(&vectors);
+                assert!(det > 0.0);
+                assert!((det - params.volume()).abs() < 1e-10 * det);
+
+                // the inverse is consistent with the vectors
+                let inv = inv_f64(&vectors);
+                for (i, row) in vectors.iter().enumerate() {
+                    let frac = mul_3_33(row, &inv);
+                    for (k, x) in frac.iter().enumerate() {
+                        let expected = if i == k { 1.0 } else { 0.0 };
+                        assert!((x - expected).abs() < 1e-12);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn orientations() {
+        let params = LatticeParams::triclinic(3.0, 4.0, 5.0, 70.0, 80.0, 110.0);
+
+        let m = params.lattice_vectors(LatticeOrientation::LowerTriangular);
+        assert_eq!([m[0][1], m[0][2], m[1][2]], [0.0; 3]);
+        assert!(m[0][0] > 0.0 && m[1][1] > 0.0);
+
+        let m = params.lattice_vectors(LatticeOrientation::UpperTriangular);
+        assert_eq!([m[1][0], m[2][0], m[2][1]], [0.0; 3]);
+        assert!(m[2][2] > 0.0 && m[1][1] > 0.0);
+
+        // exact values for the common angles
+        assert_eq!(
+            LatticeParams::cubic(2.0).lattice_vectors(LatticeOrientation::LowerTriangular),
+            [[2.0, 0.0, 0.0], [0.0, 2.0, 0.0], [0.0, 0.0, 2.0]],
+        );
+    }
+
+    #[test]
+    fn poscar_accessors() {
+        let poscar =
+            Builder::new()
+            .scale(ScaleLine::Factor(2.0))
+            .lattice_parameters(1.0, 2.0, 3.0, 90.0, 90.0, 120.0, LatticeOrientation::LowerTriangular)
+            .positions(Coords::Frac(vec![[0.0; 3]]))
+            .build().unwrap();
+
+        assert_params_close(
+            &poscar.unscaled_lattice_parameters(),
+            &LatticeParams::new(1.0, 2.0, 3.0, 90.0, 90.0, 120.0),
+        );
+        assert_params_close(
+            &poscar.scaled_lattice_parameters(),
+            &LatticeParams::new(2.0, 4.0, 6.0, 90.0, 90.0, 120.0),
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "valid cell")]
+    fn impossible_angles() {
+        let _ = LatticeParams::new(1.0, 1.0, 1.0, 170.0, 10.0, 10.0)
+            .lattice_vectors(LatticeOrientation::LowerTriangular);
+    }
+
+    #[test]
+    fn builder_invalid_parameters() {
+        let bad_params = [
+            LatticeParams::new(1.0, 1.0, 1.0, 170.0, 10.0, 10.0),
+            LatticeParams::new(0.0, 1.0, 1.0, 90.0, 90.0, 90.0),
+            LatticeParams::new(1.0, f64::NAN, 1.0, 90.0, 90.0, 90.0),
+        ];
+        for &LatticeParams { a, b, c, alpha, beta, gamma } in &bad_params {
+            // the setter itself does not panic
+            let mut builder = Builder::new();
+            builder
+                .lattice_parameters(a, b, c, alpha, beta, gamma, LatticeOrientation::UpperTriangular)
+                .positions(Coords::Frac(vec![[0.0; 3]]));
+
+            match builder.clone().build() {
+                Err(crate::ValidationError::BadLatticeParams(_)) => {},
+                r => panic!("expected BadLatticeParams, got {:?}", r),
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "valid cell")]
+    fn builder_invalid_parameters_raw() {
+        let _ = Builder::new()
+            .lattice_parameters(1.0, 1.0, 1.0, 170.0, 10.0, 10.0, LatticeOrientation::LowerTriangular)
+            .positions(Coords::Frac(vec![[0.0; 3]]))
+            .build_raw();
+    }
+}
@@ -73,11 +73,46 @@ mod parse;
 mod types;
 mod write;
 mod math;
+mod canonical;
+mod geometry;
+mod lattice;
+mod reciprocal;
+mod supercell;
+mod reduction;
+mod orientation;
+mod slab;
+mod vacuum;
+mod strain;
+mod displacement;
+mod random;
+mod matcher;
+mod diff;
+mod fingerprint;
+mod neighbors;
+mod bonds;
+mod rdf;
+mod symmetry;
+mod neb;
 pub mod builder;
 
 pub use crate::types::{Coords, ScaleLine, RawPoscar, Poscar};
 pub use crate::types::ValidationError;
 pub use crate::builder::{Builder, Zeroed};
+pub use crate::canonical::CanonicalizeOptions;
+pub use crate::neighbors::Neighbor;
+pub use crate::bonds::{BondCriterion, Bond, PairStats, BondAnalysis, covalent_radius};
+pub use crate::rdf::{Rdf, PartialRdf, radial_distribution};
+pub use crate::symmetry::{SymmetryOperation, SymmetryDataset};
+pub use crate::neb::{NebInterpolationMethod, NebInterpolationOptions, NebInterpolation, CloseContact};
+pub use crate::neb::{interpolate_neb_images, write_neb_images, read_neb_images};
+pub use crate::supercell::{SupercellSearchOptions, SupercellCriterion, SupercellCandidate};
+pub use crate::strain::StrainedPoscar;
+pub use crate::displacement::FiniteDisplacement;
+pub use crate::matcher::{StructureMatchOptions, StructureMatch};
+pub use crate::diff::PoscarDiff;
+pub use crate::fingerprint::{Fingerprint, FingerprintOptions, PairHistogram};
+pub use crate::lattice::{LatticeParams, LatticeOrientation};
+pub use crate::reciprocal::ReciprocalConvention;
 
 /// Types convertable into `Vec<[X; 3]>`.
 ///
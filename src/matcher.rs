@@ -0,0 +1,362 @@
+// Copyright 2018 Michael Lamparski
+// Part of the vasp-poscar crate.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use crate::Poscar;
+use crate::geometry::MinImage;
+use crate::math::{det_f64, dot_f64, inv_f64, mul_33_33, mul_n3_33, norm_3, sub_3};
+use crate::reduction::niggli_matrix;
+use crate::symmetry::alternative_settings;
+
+// Tolerance used for the Niggli reduction of each cell.  Ambiguities in the reduced
+// cell are covered by also trying the alternative settings, so this can be tight.
+const REDUCTION_TOL: f64 = 1e-5;
+
+/// Options for [`Poscar::structure_match`].
+///
+/// [`Poscar::structure_match`]: struct.Poscar.html#method.structure_match
+#[derive(Debug, Clone, PartialEq)]
+pub struct StructureMatchOptions {
+    /// Largest distance (in scaled units) allowed between a site and the site it
+    /// is matched with.
+    ///
+    /// Defaults to `0.2`.
+    pub distance_tol: f64,
+
+    /// Relative tolerance for comparing the lattices.
+    ///
+    /// Once expressed in a common basis, the lengths of the lattice vectors may differ
+    /// by at most this fraction, and the cosines of the angles between them by at most
+    /// this much.  Defaults to `0.05`.
+    pub lattice_tol: f64,
+}
+
+impl Default for StructureMatchOptions {
+    fn default() -> Self
+    { StructureMatchOptions {
+        distance_tol: 0.2,
+        lattice_tol: 0.05,
+    }}
+}
+
+impl StructureMatchOptions {
+    /// Alias for [`Default`]`::default`.
+    ///
+    /// [`Default`]: https://doc.rust-lang.org/std/default/trait.Default.html
+    pub fn new() -> Self
+    { Default::default() }
+}
+
+/// The result of a successful [`Poscar::structure_match`].
+///
+/// [`Poscar::structure_match`]: struct.Poscar.html#method.structure_match
+#[derive(Debug, Clone, PartialEq)]
+pub struct StructureMatch {
+    /// For each site `i` of the first structure, the index of the site of the
+    /// second structure that it corresponds to.
+    pub mapping: Vec<usize>,
+    /// Root-mean-square distance between matched sites, in scaled units.
+    ///
+    /// This is computed after removing the mean displacement, so it does not
+    /// depend on the choice of origin.
+    pub rms: f64,
+    /// Largest distance between matched sites, in scaled units.
+    ///
+    /// Like [`rms`], this is computed after removing the mean displacement.
+    ///
+    /// [`rms`]: #structfield.rms
+    pub max_distance: f64,
+}
+
+/// # Structure matching
+impl Poscar {
+    /// Decide whether two structures describe the same crystal, within tolerance.
+    ///
+    /// The structures may differ by a rotation, an origin shift, a permutation of the
+    /// sites within each species, and the choice of lattice vectors.  When they match,
+    /// the returned [`StructureMatch`] gives the correspondence between the sites and
+    /// how far apart they are; otherwise, this returns `None`.
+    ///
+    /// Species are identified by their symbols, or by the group index if there are
+    /// no symbols.  Only the positions are compared; velocities, selective dynamics,
+    /// and the comments are ignored.
+    ///
+    /// ```rust
+    /// # fn main() -> Result<(), failure::Error> {Ok({
+    /// use vasp_poscar::{Builder, Coords, StructureMatchOptions};
+    ///
+    /// let a =
+    ///     Builder::new()
+    ///     .lattice_vectors(&[[3.0, 0.0, 0.0], [0.0, 4.0, 0.0], [0.0, 0.0, 5.0]])
+    ///     .site_symbols(vec!["Na", "Cl", "Cl"])
+    ///     .positions(Coords::Frac(vec![[0.0, 0.0, 0.0], [0.5, 0.1, 0.0], [0.5, 0.6, 0.3]]))
+    ///     .build()?;
+    ///
+    /// // the same crystal, with different lattice vectors, origin and site order
+    /// let b =
+    ///     Builder::new()
+    ///     .lattice_vectors(&[[3.0, 4.0, 0.0], [0.0, 4.0, 0.0], [0.0, 0.0, -5.0]])
+    ///     .site_symbols(vec!["Cl", "Na", "Cl"])
+    ///     .positions(Coords::Cart(vec![[1.5, 2.9, 4.0], [0.0, 0.5, 2.5], [1.5, 0.9, 2.5]]))
+    ///     .build()?;
+    ///
+    /// let matched = a.structure_match(&b, &StructureMatchOptions::new())?.unwrap();
+    /// assert_eq!(matched.mapping, vec![1, 2, 0]);
+    /// assert!(matched.rms < 1e-10);
+    /// # })}
+    /// ```
+    ///
+    /// # Limitations
+    ///
+    /// Both structures must contain the same number of sites; a structure is never
+    /// matched to a supercell of itself.  Mirror images are only matched if the
+    /// structure has an improper symmetry operation.  Sites are paired greedily with
+    /// their nearest unclaimed partner, so `distance_tol` should be kept well below
+    /// the distance between neighboring sites.
+    ///
+    /// The fractional coordinates of `other` are compared in the lattice of `self`.
+    /// Thus, distances do not include any difference in the lattices.
+    ///
+    /// # Errors
+    ///
+    /// Fails if either tolerance is not positive and finite.  (structures that do not
+    /// match are not an error)
+    ///
+    /// [`StructureMatch`]: struct.StructureMatch.html
+    pub fn structure_match(
+        &self,
+        other: &Poscar,
+        options: &StructureMatchOptions,
+    ) -> Result<Option<StructureMatch>, failure::Error>
+    {
+        let StructureMatchOptions { distance_tol, lattice_tol } = *options;
+        ensure!(distance_tol.is_finite() && distance_tol > 0.0, "distance_tol must be positive: {}", distance_tol);
+        ensure!(lattice_tol.is_finite() && lattice_tol > 0.0, "lattice_tol must be positive: {}", lattice_tol);
+        Ok(self._structure_match(other, distance_tol, lattice_tol))
+    }
+
+    fn _structure_match(&self, other: &Poscar, distance_tol: f64, lattice_tol: f64) -> Option<StructureMatch>
+    {
+        let num_sites = self.num_sites();
+        if other.num_sites() != num_sites {
+            return None;
+        }
+
+        // Species, as indices into the species of `self`.
+        let labels = self.site_species_labels();
+        let mut species_labels = labels.clone();
+        species_labels.sort();
+        species_labels.dedup();
+        let species_of = |label: &String| species_labels.binary_search(label).ok();
+        let species_1: Vec<usize> = labels.iter().map(|l| species_of(l).expect("(BUG) missing label")).collect();
+        let species_2: Vec<usize> = other.site_species_labels().iter().map(species_of).collect::<Option<_>>()?;
+        let mut counts_1 = vec![0; species_labels.len()];
+        let mut counts_2 = vec![0; species_labels.len()];
+        species_1.iter().for_each(|&s| counts_1[s] += 1);
+        species_2.iter().for_each(|&s| counts_2[s] += 1);
+        if counts_1 != counts_2 {
+            return None;
+        }
+        if num_sites == 0 {
+            // (nothing to compare but the lattices)
+            let (lattice_1, _) = reduced_cell(self);
+            let (lattice_2, _) = reduced_cell(other);
+            return common_settings(&lattice_1, &lattice_2, lattice_tol).next().map(|_| {
+                StructureMatch { mapping: vec![], rms: 0.0, max_distance: 0.0 }
+            });
+        }
+
+        let (lattice_1, fracs_1) = reduced_cell(self);
+        let (mut lattice_2, mut fracs_2) = reduced_cell(other);
+        if det_f64(&lattice_1) * det_f64(&lattice_2) < 0.0 {
+            // change to a basis of the same handedness as `self`
+            lattice_2 = mat_3!((r, c) => -lattice_2[r][c]);
+            for x in &mut fracs_2 {
+                *x = arr_3![k => -x[k]];
+            }
+        }
+
+        // Anchor the search on a site of the rarest species.
+        let anchor_species = (0..counts_1.len()).filter(|&s| counts_1[s] > 0).min_by_key(|&s| counts_1[s]).expect("(BUG) no species");
+        let anchor = species_1.iter().position(|&s| s == anchor_species).expect("(BUG) no anchor");
+
+        let min_image = MinImage::new(&lattice_1);
+        let mut best: Option<StructureMatch> = None;
+        for m in common_settings(&lattice_1, &lattice_2, lattice_tol) {
+            let inv_m = inv_f64(&mat_3!((r, c) => f64::from(m[r][c])));
+            let fracs_2 = mul_n3_33(&fracs_2, &inv_m);
+
+            for (candidate, _) in species_2.iter().enumerate().filter(|&(_, &s)| s == anchor_species) {
+                let shift = sub_3(&fracs_1[anchor], &fracs_2[candidate]);
+                let shifted: Vec<[f64; 3]> = fracs_2.iter().map(|x| arr_3![k => x[k] + shift[k]]).collect();
+
+                let found = match_sites(&min_image, &fracs_1, &species_1, &shifted, &species_2, distance_tol);
+                if let Some(found) = found {
+                    if best.as_ref().is_none_or(|best| found.rms < best.rms) {
+                        best = Some(found);
+                    }
+                }
+            }
+        }
+        best
+    }
+}
+
+// The Niggli-reduced lattice and the fractional positions in that basis.
+fn reduced_cell(poscar: &Poscar) -> ([[f64; 3]; 3], Vec<[f64; 3]>)
+{
+    let lattice = poscar.scaled_lattice();
+    let m = niggli_matrix(&lattice, REDUCTION_TOL);
+    let m = mat_3!((r, c) => f64::from(m[r][c]));
+    let fracs = mul_n3_33(&poscar.frac_positions(), &inv_f64(&m));
+    (mul_33_33(&m, &lattice), fracs)
+}
+
+// Changes of basis `m` for which `m * lattice_2` has the same metric as `lattice_1`.
+fn common_settings<'a>(
+    lattice_1: &[[f64; 3]; 3],
+    lattice_2: &'a [[f64; 3]; 3],
+    lattice_tol: f64,
+) -> impl Iterator<Item=&'static [[i32; 3]; 3]> + 'a
+{
+    let lengths_1 = arr_3![k => norm_3(&lattice_1[k])];
+    let cosines_1 = mat_3!((r, c) => dot_f64(&lattice_1[r], &lattice_1[c]) / (lengths_1[r] * lengths_1[c]));
+
+    alternative_settings().iter().filter(move |m| {
+        let lattice = mul_33_33(&mat_3!((r, c) => f64::from(m[r][c])), lattice_2);
+        let lengths = arr_3![k => norm_3(&lattice[k])];
+        (0..3).all(|r| (lengths[r] - lengths_1[r]).abs() <= lattice_tol * lengths_1[r])
+            && (0..3).all(|r| (0..3).all(|c| {
+                let cosine = dot_f64(&lattice[r], &lattice[c]) / (lengths[r] * lengths[c]);
+                (cosine - cosines_1[r][c]).abs() <= lattice_tol
+            }))
+    })
+}
+
+// Pair each site of the first structure with the nearest unclaimed site of the same
+// species in the second, failing if any is too far away.
+fn match_sites(
+    min_image: &MinImage,
+    fracs_1: &[[f64; 3]],
+    species_1: &[usize],
+    fracs_2: &[[f64; 3]],
+    species_2: &[usize],
+    distance_tol: f64,
+) -> Option<StructureMatch>
+{
+    let mut claimed = vec![false; fracs_2.len()];
+    let mut mapping = Vec::with_capacity(fracs_1.len());
+    let mut displacements = Vec::with_capacity(fracs_1.len());
+    for (frac_1, &species) in zip!(fracs_1, species_1) {
+        let mut nearest: Option<(usize, [f64; 3], f64)> = None;
+        for (j, frac_2) in fracs_2.iter().enumerate() {
+            if claimed[j] || species_2[j] != species {
+                continue;
+            }
+            let displacement = min_image.cart_displacement(frac_1, frac_2);
+            let distance = norm_3(&displacement);
+            if distance <= distance_tol && nearest.is_none_or(|(_, _, d)| distance < d) {
+                nearest = Some((j, displacement, distance));
+            }
+        }
+        let (j, displacement, _) = nearest?;
+        claimed[j] = true;
+        mapping.push(j);
+        displacements.push(displacement);
+    }
+
+    let n = displacements.len() as f64;
+    let mean = arr_3![k => displacements.iter().map(|d| d[k]).sum::<f64>() / n];
+    let distances: Vec<f64> = displacements.iter().map(|d| norm_3(&sub_3(d, &mean))).collect();
+    let rms = (distances.iter().map(|d| d * d).sum::<f64>() / n).sqrt();
+    let max_distance = distances.iter().cloned().fold(0.0, f64::max);
+    Some(StructureMatch { mapping, rms, max_distance })
+}
+
+#[cfg(test)]
+#[deny(unused)]
+mod tests {
+    use super::*;
+    use crate::util::test_structures::low_symmetry;
+
+    fn options() -> StructureMatchOptions
+    { StructureMatchOptions::new() }
+
+    #[test]
+    fn identical() {
+        let poscar = low_symmetry();
+        let matched = poscar.structure_match(&poscar, &options()).unwrap().unwrap();
+        assert_eq!(matched.mapping, vec![0, 1, 2, 3, 4]);
+        assert!(matched.rms < 1e-10);
+        assert!(matched.max_distance < 1e-10);
+    }
+
+    #[test]
+    fn equivalent_descriptions() {
+        let mut other = low_symmetry();
+        other.change_basis(&[[1, 1, 0], [0, 1, 0], [-1, 0, -1]]).unwrap();
+
+        // other site k is original site perm[k]
+        let perm = [4, 3, 0, 2, 1];
+        other.permute_sites(&perm);
+
+        let fracs: Vec<[f64; 3]> = other.frac_positions().iter().map(|x| [x[0] + 0.3, x[1] - 0.7, x[2] + 2.1]).collect();
+        other.set_frac_positions(fracs);
+        other.rattle(0.01, 7, true).unwrap();
+
+        let rotation = {
+            let (s, c) = (0.6, 0.8);
+            [[c, s, 0.0], [-s, c, 0.0], [0.0, 0.0, 1.0]]
+        };
+        other.rotate(&rotation).unwrap();
+        other.0.comment = "something else".into();
+        let other = other.to_cart_storage();
+
+        let matched = low_symmetry().structure_match(&other, &options()).unwrap().unwrap();
+        let expected: Vec<usize> = (0..5).map(|i| perm.iter().position(|&p| p == i).unwrap()).collect();
+        assert_eq!(matched.mapping, expected);
+        assert!(matched.rms > 1e-4 && matched.rms < 0.05, "{}", matched.rms);
+        assert!(matched.max_distance >= matched.rms);
+
+        // the mapping goes the other way when the arguments are swapped
+        let back = other.structure_match(&low_symmetry(), &options()).unwrap().unwrap();
+        assert_eq!(back.mapping, perm.to_vec());
+    }
+
+    #[test]
+    fn mismatches() {
+        let poscar = low_symmetry();
+
+        let mut raw = poscar.clone().into_raw();
+        raw.group_symbols.as_mut().unwrap()[2] = "Ba".into();
+        assert!(poscar.structure_match(&raw.validate().unwrap(), &options()).unwrap().is_none());
+
+        let (supercell, _) = poscar.supercell(&[[2, 0, 0], [0, 1, 0], [0, 0, 1]]).unwrap();
+        assert!(poscar.structure_match(&supercell, &options()).unwrap().is_none());
+
+        let mut strained = poscar.clone();
+        strained.apply_strain(&[[0.1, 0.0, 0.0], [0.0, 0.0, 0.0], [0.0, 0.0, 0.0]]).unwrap();
+        assert!(poscar.structure_match(&strained, &options()).unwrap().is_none());
+        let loose = StructureMatchOptions { lattice_tol: 0.2, distance_tol: 0.5 };
+        assert!(poscar.structure_match(&strained, &loose).unwrap().is_some());
+
+        let mut moved = poscar.clone();
+        let mut fracs = moved.frac_positions().into_owned();
+        fracs[2][2] += 0.1;
+        moved.set_frac_positions(fracs);
+        assert!(poscar.structure_match(&moved, &options()).unwrap().is_none());
+    }
+
+    #[test]
+    fn bad_tolerance() {
+        let bad = StructureMatchOptions { distance_tol: 0.0, ..options() };
+        assert!(low_symmetry().structure_match(&low_symmetry(), &bad).is_err());
+        let bad = StructureMatchOptions { lattice_tol: f64::NAN, ..options() };
+        assert!(low_symmetry().structure_match(&low_symmetry(), &bad).is_err());
+    }
+}
@@ -25,6 +25,15 @@ pub(crate) fn dot_f64(a: &[f64; 3], b: &[f64; 3]) -> f64
 pub(crate) fn det_f64(m: &[[f64; 3]; 3]) -> f64
 { dot_f64(&cross_f64(&m[0], &m[1]), &m[2]) }
 
+pub(crate) fn add_3(a: &[f64; 3], b: &[f64; 3]) -> [f64; 3]
+{ arr_3![k => a[k] + b[k]] }
+
+pub(crate) fn sub_3(a: &[f64; 3], b: &[f64; 3]) -> [f64; 3]
+{ arr_3![k => a[k] - b[k]] }
+
+pub(crate) fn norm_3(a: &[f64; 3]) -> f64
+{ dot_f64(a, a).sqrt() }
+
 pub(crate) fn inv_f64(m: &[[f64; 3]; 3]) -> [[f64; 3]; 3]
 {
     let cofactors = mat_3!((r, c) => {
@@ -36,6 +45,87 @@ pub(crate) fn inv_f64(m: &[[f64; 3]; 3]) -> [[f64; 3]; 3]
     mat_3!((r, c) => det.recip() * cofactors[c][r])
 }
 
+pub(crate) fn transpose_33(m: &[[f64; 3]; 3]) -> [[f64; 3]; 3]
+{ mat_3!((r, c) => m[c][r]) }
+
+pub(crate) fn det_i32(m: &[[i32; 3]; 3]) -> i64
+{
+    let m = mat_3!((r, c) => i64::from(m[r][c]));
+    m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+    - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+    + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+}
+
+/// The adjugate matrix, which is equal to `det(m) * inv(m)`.
+pub(crate) fn adj_i32(m: &[[i32; 3]; 3]) -> [[i64; 3]; 3]
+{
+    let m = mat_3!((r, c) => i64::from(m[r][c]));
+    let cofactors = mat_3!((r, c) => {
+        m[(r+1) % 3][(c+1) % 3] * m[(r+2) % 3][(c+2) % 3]
+        - m[(r+1) % 3][(c+2) % 3] * m[(r+2) % 3][(c+1) % 3]
+    });
+    mat_3!((r, c) => cofactors[c][r])
+}
+
+pub(crate) fn mul_i32_33(a: &[[i32; 3]; 3], b: &[[i32; 3]; 3]) -> [[i32; 3]; 3]
+{ mat_3!((r, c) => (0..3).map(|k| a[r][k] * b[k][c]).sum()) }
+
+pub(crate) fn transpose_i32_33(m: &[[i32; 3]; 3]) -> [[i32; 3]; 3]
+{ mat_3!((r, c) => m[c][r]) }
+
+/// Row-style Hermite normal form of the lattice generated by some integer vectors.
+///
+/// This is the unique upper triangular basis of the lattice with positive diagonal
+/// and with each entry above the diagonal reduced into `[0, diagonal)`.
+///
+/// # Panics
+///
+/// Panics if the vectors do not span three dimensions.
+pub(crate) fn hermite_normal_form(rows: &[[i64; 3]]) -> [[i64; 3]; 3]
+{
+    let mut h = rows.to_vec();
+    assert!(h.len() >= 3, "(BUG) too few rows for hermite_normal_form");
+    for col in 0..3 {
+        // Euclid's algorithm on the rows at and below `col`
+        loop {
+            let pivot = (col..h.len()).filter(|&r| h[r][col] != 0).min_by_key(|&r| h[r][col].abs());
+            let pivot = match pivot {
+                Some(pivot) => pivot,
+                None => panic!("(BUG) singular matrix in hermite_normal_form"),
+            };
+            h.swap(col, pivot);
+            let mut done = true;
+            for r in col + 1..h.len() {
+                let q = h[r][col].div_euclid(h[col][col]);
+                let pivot_row = h[col];
+                for (x, p) in zip!(&mut h[r], &pivot_row) {
+                    *x -= q * p;
+                }
+                done &= h[r][col] == 0;
+            }
+            if done {
+                break;
+            }
+        }
+        if h[col][col] < 0 {
+            for x in &mut h[col] {
+                *x = -*x;
+            }
+        }
+        for r in 0..col {
+            let q = h[r][col].div_euclid(h[col][col]);
+            let pivot_row = h[col];
+            for (x, p) in zip!(&mut h[r], &pivot_row) {
+                *x -= q * p;
+            }
+        }
+    }
+    [h[0], h[1], h[2]]
+}
+
+pub(crate) fn mul_33_33(a: &[[f64; 3]; 3], b: &[[f64; 3]; 3]) -> [[f64; 3]; 3]
+{ arr_3![r => mul_3_33(&a[r], b)] }
+
 pub(crate) fn mul_3_33(v: &[f64; 3], m: &[[f64; 3]; 3]) -> [f64; 3]
 {
     // I suspect this is *vaguely* more amenable to vector instructions
@@ -126,6 +216,22 @@ mod tests {
         assert_eq!(mul_n3_33(&vs, &m), prod);
     }
 
+    #[test]
+    fn test_integer_inverse() {
+        let m = [[2, -1, 2], [-1, 3, -3], [1, 1, 0]];
+        assert_eq!(det_i32(&m), 1);
+        let adj = adj_i32(&m);
+        let adj = mat_3!((r, c) => adj[r][c] as f64);
+        assert_eq!(adj, EXAMPLE_UNIMODULAR_INV);
+
+        let m = [[2, 0, 0], [1, 3, 0], [0, 0, -1]];
+        assert_eq!(det_i32(&m), -6);
+        let adj = adj_i32(&m);
+        let m_f = mat_3!((r, c) => m[r][c] as f64);
+        let adj_f = mat_3!((r, c) => adj[r][c] as f64);
+        assert_eq!(mul_33_33(&m_f, &adj_f), scale_33(&[[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]], -6.0).0);
+    }
+
     #[test]
     fn test_inv_f64() {
         // test an inverse that can be computed exactly.
@@ -0,0 +1,576 @@
+// Copyright 2018 Michael Lamparski
+// Part of the vasp-poscar crate.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Utilities for nudged elastic band (NEB) calculations.
+
+use crate::{Poscar, ScaleLine};
+use crate::geometry::MinImage;
+use crate::math::{det_f64, inv_f64, mul_n3_33, mul_3_33, norm_3, scale_33, sub_3};
+
+use std::path::{Path, PathBuf};
+
+/// How [`interpolate_neb_images`] places the intermediate images.
+///
+/// [`interpolate_neb_images`]: fn.interpolate_neb_images.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NebInterpolationMethod {
+    /// Interpolate the fractional coordinates linearly.
+    Linear,
+    /// The image dependent pair potential method of Smidstrup et al. (2014).
+    ///
+    /// Starting from the linear interpolation, the sites of each intermediate image
+    /// are moved to minimize `S = sum_{i<j} (d'_ij - d_ij)^2 / d_ij^4`, where `d_ij`
+    /// is the (minimum image) distance between sites `i` and `j` and `d'_ij` is the
+    /// linear interpolation of the same distance between the two endpoints.
+    /// The lattice of each image is held fixed.
+    Idpp,
+}
+
+/// Options for [`interpolate_neb_images`].
+///
+/// [`interpolate_neb_images`]: fn.interpolate_neb_images.html
+#[derive(Debug, Clone, PartialEq)]
+pub struct NebInterpolationOptions {
+    /// Defaults to [`NebInterpolationMethod::Linear`].
+    ///
+    /// [`NebInterpolationMethod::Linear`]: enum.NebInterpolationMethod.html#variant.Linear
+    pub method: NebInterpolationMethod,
+
+    /// Pairs of sites closer than this in an intermediate image are reported in
+    /// [`NebInterpolation::close_contacts`].  Defaults to `0.5` (in scaled units).
+    ///
+    /// [`NebInterpolation::close_contacts`]: struct.NebInterpolation.html#structfield.close_contacts
+    pub min_distance: f64,
+
+    /// IDPP stops when the gradient of `S` for every site is smaller than this.
+    /// Defaults to `1e-3`.
+    pub idpp_tolerance: f64,
+
+    /// IDPP gives up on an image after this many steps.  Defaults to `1000`.
+    pub idpp_max_steps: usize,
+}
+
+impl Default for NebInterpolationOptions {
+    fn default() -> Self
+    { NebInterpolationOptions {
+        method: NebInterpolationMethod::Linear,
+        min_distance: 0.5,
+        idpp_tolerance: 1e-3,
+        idpp_max_steps: 1000,
+    }}
+}
+
+impl NebInterpolationOptions {
+    /// Alias for [`Default`]`::default`.
+    ///
+    /// [`Default`]: https://doc.rust-lang.org/std/default/trait.Default.html
+    pub fn new() -> Self
+    { Default::default() }
+}
+
+/// The output of [`interpolate_neb_images`].
+///
+/// [`interpolate_neb_images`]: fn.interpolate_neb_images.html
+#[derive(Debug, Clone)]
+pub struct NebInterpolation {
+    /// All of the images, including the two endpoints.
+    pub images: Vec<Poscar>,
+    /// Pairs of sites that are too close in an intermediate image.
+    pub close_contacts: Vec<CloseContact>,
+    /// `false` if IDPP did not converge for some image.  Always `true` for
+    /// linear interpolation.
+    ///
+    /// IDPP also fails (leaving the image as linearly interpolated) if two sites
+    /// coincide in the initial guess, where its objective is undefined.
+    pub converged: bool,
+}
+
+/// A pair of sites that are closer than [`NebInterpolationOptions::min_distance`].
+///
+/// [`NebInterpolationOptions::min_distance`]: struct.NebInterpolationOptions.html#structfield.min_distance
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CloseContact {
+    /// Index of the image in [`NebInterpolation::images`].
+    ///
+    /// [`NebInterpolation::images`]: struct.NebInterpolation.html#structfield.images
+    pub image: usize,
+    /// The two sites, with `i < j`.
+    pub i: usize,
+    pub j: usize,
+    /// The minimum image distance between them.
+    pub distance: f64,
+}
+
+/// Generate `num_images` intermediate images between two structures.
+///
+/// The two structures must have the same group counts and symbols, and are assumed
+/// to list corresponding sites in the same order.  Each site of `final_` is first
+/// replaced by the periodic image closest to its position in `initial` (as in
+/// [`Poscar::unwrap_relative_to`]), so that no site travels across the cell.
+///
+/// For image `k` (from `1` to `num_images`), with `t = k / (num_images + 1)`,
+/// the scaled lattice vectors are interpolated linearly between those of the two
+/// endpoints.  The positions are then placed according to `options.method`.
+///
+/// The returned list of images begins with `initial` and ends with the unwrapped
+/// `final_`.  The intermediate images are copies of `initial` with new lattice vectors
+/// and positions, keeping the comment, selective dynamics, the kind of scale line and
+/// the choice of Cartesian or direct coordinates.  Their velocities are removed.
+///
+/// ```rust
+/// # fn main() -> Result<(), failure::Error> {Ok({
+/// use vasp_poscar::{Builder, Coords};
+/// use vasp_poscar::{interpolate_neb_images, NebInterpolationOptions};
+///
+/// let structure = |x| {
+///     Builder::new()
+///     .lattice_vectors(&[[4.0, 0.0, 0.0], [0.0, 4.0, 0.0], [0.0, 0.0, 4.0]])
+///     .positions(Coords::Frac(vec![[0.0; 3], [x, 0.5, 0.5]]))
+///     .build()
+/// };
+///
+/// // the second site hops across the boundary
+/// let out = interpolate_neb_images(&structure(0.8)?, &structure(0.2)?, 3, &NebInterpolationOptions::new())?;
+/// assert_eq!(out.images.len(), 5);
+/// assert!((out.images[2].frac_positions()[1][0] - 1.0).abs() < 1e-12);
+/// assert!(out.close_contacts.is_empty());
+/// # })}
+/// ```
+///
+/// # Errors
+///
+/// Fails if the group counts or symbols differ, or if `options.min_distance` is negative.
+///
+/// [`Poscar::unwrap_relative_to`]: struct.Poscar.html#method.unwrap_relative_to
+pub fn interpolate_neb_images(
+    initial: &Poscar,
+    final_: &Poscar,
+    num_images: usize,
+    options: &NebInterpolationOptions,
+) -> Result<NebInterpolation, failure::Error>
+{
+    ensure!(
+        initial.0.group_counts == final_.0.group_counts,
+        "group counts differ: {:?} vs {:?}", initial.0.group_counts, final_.0.group_counts,
+    );
+    ensure!(
+        initial.0.group_symbols == final_.0.group_symbols,
+        "group symbols differ: {:?} vs {:?}", initial.0.group_symbols, final_.0.group_symbols,
+    );
+    ensure!(options.min_distance >= 0.0, "min_distance must be non-negative");
+
+    let mut final_ = final_.clone();
+    final_.unwrap_relative_to(initial)?;
+
+    let (lattice_0, lattice_1) = (initial.scaled_lattice(), final_.scaled_lattice());
+    let (fracs_0, fracs_1) = (initial.frac_positions(), final_.frac_positions());
+    let (distances_0, distances_1) = match options.method {
+        NebInterpolationMethod::Linear => (vec![], vec![]),
+        NebInterpolationMethod::Idpp => (initial.distance_matrix(), final_.distance_matrix()),
+    };
+    let scale = initial.effective_scale_factor();
+
+    let mut images = vec![initial.clone()];
+    let mut close_contacts = vec![];
+    let mut converged = true;
+    for k in 1..=num_images {
+        let t = k as f64 / (num_images + 1) as f64;
+        let lerp = |a: f64, b: f64| a + t * (b - a);
+        let lattice = mat_3!((r, c) => lerp(lattice_0[r][c], lattice_1[r][c]));
+        let mut fracs: Vec<[f64; 3]> = {
+            zip!(fracs_0.iter(), fracs_1.iter())
+                .map(|(a, b)| arr_3![i => lerp(a[i], b[i])])
+                .collect()
+        };
+
+        if options.method == NebInterpolationMethod::Idpp {
+            let targets: Vec<Vec<f64>> = {
+                zip!(&distances_0, &distances_1)
+                    .map(|(row_0, row_1)| zip!(row_0, row_1).map(|(&a, &b)| lerp(a, b)).collect())
+                    .collect()
+            };
+            let mut carts = mul_n3_33(&fracs, &lattice);
+            converged &= idpp_relax(&lattice, &mut carts, &targets, options);
+            fracs = mul_n3_33(&carts, &inv_f64(&lattice));
+        }
+
+        let mut image = initial.clone();
+        image.0.lattice_vectors = scale_33(&lattice, 1.0 / scale).0;
+        if let ScaleLine::Volume(_) = image.0.scale {
+            image.0.scale = ScaleLine::Volume(det_f64(&lattice).abs());
+        }
+        image.0.velocities = None;
+        image.set_frac_positions(fracs);
+
+        let distances = image.distance_matrix();
+        for (i, row) in distances.iter().enumerate() {
+            for (j, &distance) in row.iter().enumerate().skip(i + 1) {
+                if distance < options.min_distance {
+                    close_contacts.push(CloseContact { image: k, i, j, distance });
+                }
+            }
+        }
+        images.push(image);
+    }
+    images.push(final_);
+    Ok(NebInterpolation { images, close_contacts, converged })
+}
+
+/// Write images into the numbered subdirectories `00`, `01`, ... of `dir`, as
+/// expected by VASP for an NEB calculation.
+///
+/// Each image is written to a file named `POSCAR`.  The directories are created
+/// (along with `dir` itself, if needed).  Directory names have two digits, or more
+/// if there are over 100 images.
+///
+/// This refuses to write into a `dir` that already has numbered subdirectories, as
+/// files left over from another calculation (such as a `CONTCAR`, or an extra image)
+/// would be picked up by [`read_neb_images`].  Remove them first to start over.
+///
+/// For consistency, every image is written with the same kind of coordinates
+/// (Cartesian or direct) as the first image.
+///
+/// # Errors
+///
+/// Fails if there are fewer than two images, if the images do not all have the
+/// same group counts and symbols, if `dir` already contains image directories,
+/// or on IO errors.
+///
+/// [`read_neb_images`]: fn.read_neb_images.html
+pub fn write_neb_images<P: AsRef<Path>>(dir: P, images: &[Poscar]) -> Result<(), failure::Error>
+{
+    ensure!(images.len() >= 2, "an NEB calculation needs at least two images");
+    check_ordering(images)?;
+    if dir.as_ref().exists() {
+        if let Some((_, path)) = image_dirs(dir.as_ref())?.first() {
+            bail!("refusing to overwrite existing image directory {}", path.display());
+        }
+    }
+
+    let width = usize::max(2, (images.len() - 1).to_string().len());
+    let tag = images[0].0.positions.tag();
+    for (index, image) in images.iter().enumerate() {
+        let image_dir = dir.as_ref().join(format!("{:0width$}", index, width = width));
+        std::fs::create_dir_all(&image_dir)?;
+
+        let image = match tag {
+            crate::Coords::Frac(()) => image.to_frac_storage(),
+            crate::Coords::Cart(()) => image.to_cart_storage(),
+        };
+        std::fs::write(image_dir.join("POSCAR"), image.to_string())?;
+    }
+    Ok(())
+}
+
+/// Read the latest structure of each image of an NEB calculation.
+///
+/// The images are read from the subdirectories of `dir` whose names consist only of
+/// digits, in numerical order.  From each, this reads `CONTCAR` if it exists and is
+/// not blank (VASP leaves it empty for the fixed endpoints), and `POSCAR` otherwise.
+///
+/// # Errors
+///
+/// Fails if there are fewer than two image directories, if they are not numbered
+/// consecutively from zero, if a file fails to parse, or if the images do not all
+/// have the same group counts and symbols.  A `CONTCAR` that exists but cannot be
+/// read is an error; `POSCAR` is only used in place of a missing or blank `CONTCAR`.
+pub fn read_neb_images<P: AsRef<Path>>(dir: P) -> Result<Vec<Poscar>, failure::Error>
+{
+    let numbered = image_dirs(dir.as_ref())?;
+    ensure!(numbered.len() >= 2, "found {} image directories in {}", numbered.len(), dir.as_ref().display());
+    for (expected, (number, path)) in numbered.iter().enumerate() {
+        ensure!(
+            *number == expected,
+            "image directories are not numbered consecutively from zero: {}", path.display(),
+        );
+    }
+
+    let images = {
+        numbered.iter().map(|(_, image_dir)| {
+            let contcar = image_dir.join("CONTCAR");
+            let path = match std::fs::read(&contcar) {
+                Ok(ref bytes) if bytes.iter().all(u8::is_ascii_whitespace) => image_dir.join("POSCAR"),
+                Ok(_) => contcar,
+                Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => image_dir.join("POSCAR"),
+                Err(e) => bail!("could not read {}: {}", contcar.display(), e),
+            };
+            Poscar::from_path(&path)
+        }).collect::<Result<Vec<_>, _>>()?
+    };
+    check_ordering(&images)?;
+    Ok(images)
+}
+
+// Subdirectories whose names consist only of digits, sorted by number.
+fn image_dirs(dir: &Path) -> Result<Vec<(usize, PathBuf)>, failure::Error>
+{
+    let mut numbered: Vec<(usize, PathBuf)> = vec![];
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name = match name.to_str() {
+            Some(name) => name,
+            None => continue,
+        };
+        if entry.file_type()?.is_dir() && !name.is_empty() && name.bytes().all(|b| b.is_ascii_digit()) {
+            let number = name.parse().map_err(|_| format_err!("image number too large: {}", name))?;
+            numbered.push((number, entry.path()));
+        }
+    }
+    numbered.sort();
+    Ok(numbered)
+}
+
+fn check_ordering(images: &[Poscar]) -> Result<(), failure::Error>
+{
+    for (index, image) in images.iter().enumerate().skip(1) {
+        ensure!(
+            image.0.group_counts == images[0].0.group_counts
+                && image.0.group_symbols == images[0].0.group_symbols,
+            "image {} does not have the same atoms in the same order as image 0", index,
+        );
+    }
+    Ok(())
+}
+
+// Minimize the IDPP objective by steepest descent with an adaptive step.
+// Returns whether it converged.  The sites are not moved if the objective cannot
+// be evaluated at the starting point (e.g. two sites coincide).
+fn idpp_relax(
+    lattice: &[[f64; 3]; 3],
+    carts: &mut Vec<[f64; 3]>,
+    targets: &[Vec<f64>],
+    options: &NebInterpolationOptions,
+) -> bool
+{
+    let min_image = MinImage::new(lattice);
+    let inverse = inv_f64(lattice);
+
+    // objective and gradient
+    let evaluate = |carts: &[[f64; 3]]| -> (f64, Vec<[f64; 3]>) {
+        let fracs = mul_n3_33(carts, &inverse);
+        let mut value = 0.0;
+        let mut gradient = vec![[0.0; 3]; carts.len()];
+        for i in 0..carts.len() {
+            for j in i + 1..carts.len() {
+                let r = mul_3_33(&min_image.reduce_frac(&sub_3(&fracs[j], &fracs[i])), lattice);
+                let d = norm_3(&r);
+                let error = targets[i][j] - d;
+                let d4 = d * d * d * d;
+                value += error * error / d4;
+                // derivative of the pair's term with respect to d, divided by d
+                let slope = (-4.0 * error * error / (d4 * d) - 2.0 * error / d4) / d;
+                for k in 0..3 {
+                    gradient[j][k] += slope * r[k];
+                    gradient[i][k] -= slope * r[k];
+                }
+            }
+        }
+        (value, gradient)
+    };
+    let largest = |gradient: &[[f64; 3]]| gradient.iter().map(norm_3).fold(0.0, f64::max);
+
+    let (mut value, mut gradient) = evaluate(carts);
+    if !value.is_finite() || gradient.iter().flatten().any(|g| !g.is_finite()) {
+        return false;
+    }
+    // initial step size moves the site with the largest gradient by 0.05
+    let mut step = 0.05 / largest(&gradient).max(1e-300);
+    for _ in 0..options.idpp_max_steps {
+        if largest(&gradient) < options.idpp_tolerance {
+            return true;
+        }
+        let trial: Vec<[f64; 3]> = {
+            zip!(carts.iter(), &gradient)
+                .map(|(x, g)| arr_3![k => x[k] - step * g[k]])
+                .collect()
+        };
+        let (trial_value, trial_gradient) = evaluate(&trial);
+        if trial_value < value {
+            *carts = trial;
+            value = trial_value;
+            gradient = trial_gradient;
+            step *= 1.2;
+        } else {
+            step *= 0.5;
+        }
+    }
+    largest(&gradient) < options.idpp_tolerance
+}
+
+#[cfg(test)]
+#[deny(unused)]
+mod tests {
+    use super::*;
+    use crate::{Builder, Coords};
+
+    fn structure(lattice: f64, positions: Vec<[f64; 3]>) -> Poscar {
+        Builder::new()
+            .lattice_vectors(&[[lattice, 0.0, 0.0], [0.0, lattice, 0.0], [0.0, 0.0, lattice]])
+            .site_symbols(vec!["H"; positions.len()])
+            .positions(Coords::Cart(positions))
+            .velocities(Coords::Cart(vec![[0.0; 3]; 2]))
+            .build().unwrap()
+    }
+
+    #[test]
+    fn linear() {
+        let initial = structure(10.0, vec![[0.5, 5.0, 5.0], [5.0, 5.0, 5.0]]);
+        let final_ = structure(12.0, vec![[9.5, 5.0, 5.0], [7.0, 5.0, 5.0]]);
+        let out = interpolate_neb_images(&initial, &final_, 1, &NebInterpolationOptions::new()).unwrap();
+        assert_eq!(out.images.len(), 3);
+        assert!(out.converged);
+        assert!(out.close_contacts.is_empty());
+
+        let middle = &out.images[1];
+        assert_eq!(middle.scaled_lattice()[0][0], 11.0);
+        assert_eq!(middle.0.velocities, None);
+        assert_eq!(middle.0.positions.tag(), Coords::Cart(()));
+
+        // the first site crosses the boundary
+        let fracs = middle.frac_positions();
+        assert!((fracs[0][0] - (0.05 + 9.5 / 12.0 - 1.0) / 2.0).abs() < 1e-12);
+        assert!((fracs[1][0] - (0.5 + 7.0 / 12.0) / 2.0).abs() < 1e-12);
+
+        // the endpoints are preserved, up to unwrapping
+        assert_eq!(format!("{:?}", out.images[0]), format!("{:?}", initial));
+        assert!((out.images[2].scaled_cart_positions()[0][0] - (9.5 - 12.0)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn mismatch() {
+        let initial = structure(10.0, vec![[0.0; 3], [1.0; 3]]);
+        let mut raw = initial.clone().into_raw();
+        raw.group_symbols = Some(vec!["He".into()]);
+        let other = raw.validate().unwrap();
+        assert!(interpolate_neb_images(&initial, &other, 3, &NebInterpolationOptions::new()).is_err());
+
+        let mut raw = initial.clone().into_raw();
+        raw.group_counts = vec![1, 1];
+        raw.group_symbols = Some(vec!["H".into(), "H".into()]);
+        let other = raw.validate().unwrap();
+        assert!(interpolate_neb_images(&initial, &other, 3, &NebInterpolationOptions::new()).is_err());
+    }
+
+    #[test]
+    fn close_contacts() {
+        // two sites swap places through each other
+        let initial = structure(10.0, vec![[4.0, 5.0, 5.0], [6.0, 5.0, 5.0]]);
+        let final_ = structure(10.0, vec![[6.0, 5.0, 5.0], [4.0, 5.0, 5.0]]);
+        let out = interpolate_neb_images(&initial, &final_, 3, &NebInterpolationOptions::new()).unwrap();
+        assert_eq!(out.close_contacts.len(), 1);
+        let contact = out.close_contacts[0];
+        assert_eq!((contact.image, contact.i, contact.j), (2, 0, 1));
+        assert!(contact.distance < 1e-12);
+    }
+
+    // a scratch directory, removed when dropped
+    struct TempDir(PathBuf);
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("vasp-poscar-{}-{}", name, std::process::id()));
+            let _ = std::fs::remove_dir_all(&path);
+            TempDir(path)
+        }
+    }
+    impl Drop for TempDir {
+        fn drop(&mut self) { let _ = std::fs::remove_dir_all(&self.0); }
+    }
+
+    #[test]
+    fn directories() {
+        let dir = TempDir::new("neb-directories");
+        let initial = structure(10.0, vec![[4.0, 5.0, 5.0], [6.0, 5.0, 5.0]]);
+        let final_ = structure(10.0, vec![[5.0, 4.0, 5.0], [5.0, 6.0, 5.0]]);
+        let options = NebInterpolationOptions::new();
+        let mut images = interpolate_neb_images(&initial, &final_, 3, &options).unwrap().images;
+        images[2] = images[2].to_frac_storage();
+        write_neb_images(&dir.0, &images).unwrap();
+
+        let names: Vec<_> = (0..5).map(|i| dir.0.join(format!("0{}", i))).collect();
+        assert!(names.iter().all(|path| path.join("POSCAR").is_file()));
+        let written = Poscar::from_path(names[2].join("POSCAR")).unwrap();
+        assert_eq!(written.0.positions.tag(), Coords::Cart(()));
+
+        // a blank CONTCAR is ignored, but a complete one is preferred
+        std::fs::write(names[0].join("CONTCAR"), "").unwrap();
+        let mut moved = images[1].clone();
+        moved.0.comment = "relaxed".into();
+        std::fs::write(names[1].join("CONTCAR"), moved.to_string()).unwrap();
+        std::fs::create_dir(dir.0.join("unrelated")).unwrap();
+
+        let read = read_neb_images(&dir.0).unwrap();
+        assert_eq!(read.len(), 5);
+        assert_eq!(read[1].comment(), "relaxed");
+        assert_eq!(read[0].to_string(), images[0].to_string());
+        assert_eq!(read[4].to_string(), images[4].to_string());
+
+        // a gap in the numbering
+        std::fs::remove_dir_all(&names[3]).unwrap();
+        assert!(read_neb_images(&dir.0).is_err());
+        std::fs::create_dir(&names[3]).unwrap();
+        std::fs::write(names[3].join("POSCAR"), images[3].to_string()).unwrap();
+        assert!(read_neb_images(&dir.0).is_ok());
+
+        // inconsistent atoms
+        let mut raw = images[3].clone().into_raw();
+        raw.group_symbols = Some(vec!["He".into()]);
+        std::fs::write(names[3].join("CONTCAR"), raw.validate().unwrap().to_string()).unwrap();
+        assert!(read_neb_images(&dir.0).is_err());
+        assert!(write_neb_images(&dir.0, &images[..1]).is_err());
+
+        // old images are never mixed with new ones
+        assert!(write_neb_images(&dir.0, &images[..3]).is_err());
+        let error = read_neb_images(&dir.0).unwrap_err();
+        assert_eq!(error.to_string(), "image 3 does not have the same atoms in the same order as image 0");
+        std::fs::remove_dir_all(&dir.0).unwrap();
+        std::fs::create_dir(&dir.0).unwrap();
+        std::fs::create_dir(dir.0.join("unrelated")).unwrap();
+        write_neb_images(&dir.0, &images[..3]).unwrap();
+        assert_eq!(read_neb_images(&dir.0).unwrap().len(), 3);
+
+        // a CONTCAR that can't be read never falls back to the POSCAR
+        let not_utf8 = [&b"\xff"[..], images[1].to_string().as_bytes()].concat();
+        std::fs::write(names[1].join("CONTCAR"), not_utf8).unwrap();
+        assert!(read_neb_images(&dir.0).is_err());
+        std::fs::remove_file(names[1].join("CONTCAR")).unwrap();
+        std::fs::create_dir(names[1].join("CONTCAR")).unwrap();
+        let error = read_neb_images(&dir.0).unwrap_err();
+        assert!(error.to_string().starts_with("could not read"), "{}", error);
+    }
+
+    #[test]
+    fn idpp() {
+        // a rotating dimer; linear interpolation shortens the bond
+        let initial = structure(10.0, vec![[4.0, 5.0, 5.0], [6.0, 5.0, 5.0]]);
+        let final_ = structure(10.0, vec![[5.0, 4.0, 5.0], [5.0, 6.0, 5.0]]);
+
+        let linear = interpolate_neb_images(&initial, &final_, 1, &NebInterpolationOptions::new()).unwrap();
+        assert!((linear.images[1].distance(0, 1) - 2f64.sqrt()).abs() < 1e-12);
+
+        let options = NebInterpolationOptions { method: NebInterpolationMethod::Idpp, ..Default::default() };
+        let out = interpolate_neb_images(&initial, &final_, 1, &options).unwrap();
+        assert!(out.converged);
+        assert!((out.images[1].distance(0, 1) - 2.0).abs() < 1e-2);
+    }
+
+    #[test]
+    fn idpp_coincident_sites() {
+        // the sites meet in the middle image, where the objective is undefined
+        let initial = structure(10.0, vec![[4.0, 5.0, 5.0], [6.0, 5.0, 5.0]]);
+        let final_ = structure(10.0, vec![[6.0, 5.0, 5.0], [4.0, 5.0, 5.0]]);
+        let options = NebInterpolationOptions { method: NebInterpolationMethod::Idpp, ..Default::default() };
+        let out = interpolate_neb_images(&initial, &final_, 1, &options).unwrap();
+        assert!(!out.converged);
+
+        let linear = interpolate_neb_images(&initial, &final_, 1, &NebInterpolationOptions::new()).unwrap();
+        assert_eq!(out.images[1].to_string(), linear.images[1].to_string());
+        assert_eq!(out.close_contacts, linear.close_contacts);
+    }
+}
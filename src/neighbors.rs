@@ -0,0 +1,230 @@
+// Copyright 2018 Michael Lamparski
+// Part of the vasp-poscar crate.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Periodic neighbor search.
+
+use crate::Poscar;
+use crate::math::{inv_f64, mul_3_33, norm_3};
+
+/// A single entry in the neighbor list returned by [`Poscar::neighbors`].
+///
+/// Describes the vector from site `i` in the unit cell to the periodic
+/// image of site `j` that is displaced by `image` lattice vectors from
+/// where it is written in the file.
+///
+/// [`Poscar::neighbors`]: struct.Poscar.html#method.neighbors
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Neighbor {
+    /// Index of the central site.
+    pub i: usize,
+    /// Index of the neighboring site.
+    pub j: usize,
+    /// The lattice translation applied to site `j`, relative to its
+    /// position as stored in the Poscar.
+    pub image: [i32; 3],
+    /// Cartesian vector from site `i` to the image of site `j`.
+    pub displacement: [f64; 3],
+    /// Length of `displacement`.
+    pub distance: f64,
+}
+
+/// # Neighbor search
+impl Poscar {
+    /// Find all pairs of sites within `cutoff` of each other, under periodic
+    /// boundary conditions.
+    ///
+    /// The scale line is taken into account.  The returned list is "full";
+    /// that is, every pair appears in both directions, once as `(i, j, image)`
+    /// and once as `(j, i, -image)`.  A site is never considered to be its own
+    /// neighbor, but it may neighbor its own periodic images when the cutoff
+    /// is larger than the cell.  Entries are sorted by `i`, then `j`, then `image`.
+    ///
+    /// Sites are binned into a grid of cells at least as wide as the cutoff,
+    /// so that the cost grows linearly with the number of sites.  Cutoffs larger
+    /// than the unit cell are supported, in which case additional periodic images
+    /// are enumerated.
+    ///
+    /// ```rust
+    /// # fn main() -> Result<(), failure::Error> {Ok({
+    /// use vasp_poscar::{Builder, Coords, ScaleLine};
+    ///
+    /// // simple cubic lattice with a single site
+    /// let poscar =
+    ///     Builder::new()
+    ///     .scale(ScaleLine::Factor(2.0))
+    ///     .dummy_lattice_vectors()
+    ///     .positions(Coords::Frac(vec![[0.0; 3]]))
+    ///     .build()?;
+    ///
+    /// // six nearest neighbors at a distance of 2
+    /// let neighbors = poscar.neighbors(2.5);
+    /// assert_eq!(neighbors.len(), 6);
+    /// assert!(neighbors.iter().all(|n| n.distance == 2.0));
+    /// assert_eq!(neighbors[0].image, [-1, 0, 0]);
+    ///
+    /// // the next shell (at 2 * sqrt(2)) has twelve more
+    /// assert_eq!(poscar.neighbors(3.0).len(), 18);
+    /// # })}
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if `cutoff` is negative or not finite.
+    pub fn neighbors(&self, cutoff: f64) -> Vec<Neighbor>
+    {
+        assert!(cutoff.is_finite() && cutoff >= 0.0, "invalid cutoff: {}", cutoff);
+
+        let lattice = self.scaled_lattice();
+        let inv = inv_f64(&lattice);
+        // `inv_norms[k] * cutoff` bounds how far along axis `k` (in fractional units)
+        // a point within the cutoff may lie.  Its reciprocal is the spacing between
+        // the planes of the lattice perpendicular to that axis.
+        let inv_norms = arr_3![k => norm_3(&arr_3![r => inv[r][k]])];
+
+        // Bins should be no narrower than the cutoff.  There's also no sense in
+        // having many more bins than sites (the bins can be wider than necessary,
+        // as long as `reach` accounts for it).
+        let max_bins = 2.0 * (self.num_sites() as f64).cbrt().ceil();
+        let num_bins = arr_3![k => {
+            let n = (1.0 / (inv_norms[k] * cutoff)).floor();
+            f64::max(1.0, f64::min(n, max_bins)) as i64
+        }];
+        // how many bins away a neighbor can be
+        let reach = arr_3![k => (cutoff * inv_norms[k] * num_bins[k] as f64).ceil() as i64];
+
+        // Wrap the sites into the cell and bin them, remembering how they were moved.
+        let mut fracs = self.frac_positions().into_owned();
+        let mut wrap_shifts = vec![[0i64; 3]; fracs.len()];
+        let mut bins = vec![vec![]; (num_bins[0] * num_bins[1] * num_bins[2]) as usize];
+        let bin_index = |b: [i64; 3]| ((b[0] * num_bins[1] + b[1]) * num_bins[2] + b[2]) as usize;
+        let mut site_bins = vec![[0i64; 3]; fracs.len()];
+        for (site, frac) in fracs.iter_mut().enumerate() {
+            for k in 0..3 {
+                let floor = frac[k].floor();
+                frac[k] -= floor;
+                wrap_shifts[site][k] = floor as i64;
+                // (the min() guards against frac[k] == 1.0 due to roundoff)
+                site_bins[site][k] = i64::min((frac[k] * num_bins[k] as f64) as i64, num_bins[k] - 1);
+            }
+            bins[bin_index(site_bins[site])].push(site);
+        }
+
+        let mut out = vec![];
+        for i in 0..fracs.len() {
+            let bin = site_bins[i];
+            for dx in -reach[0]..=reach[0] {
+                for dy in -reach[1]..=reach[1] {
+                    for dz in -reach[2]..=reach[2] {
+                        let unwrapped = [bin[0] + dx, bin[1] + dy, bin[2] + dz];
+                        let cell_shift = arr_3![k => unwrapped[k].div_euclid(num_bins[k])];
+                        let target = arr_3![k => unwrapped[k].rem_euclid(num_bins[k])];
+
+                        for &j in &bins[bin_index(target)] {
+                            if i == j && cell_shift == [0; 3] {
+                                continue;
+                            }
+                            let diff = arr_3![k => fracs[j][k] + cell_shift[k] as f64 - fracs[i][k]];
+                            let displacement = mul_3_33(&diff, &lattice);
+                            let distance = norm_3(&displacement);
+                            if distance <= cutoff {
+                                let image = arr_3![k => (cell_shift[k] - wrap_shifts[j][k] + wrap_shifts[i][k]) as i32];
+                                out.push(Neighbor { i, j, image, displacement, distance });
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        out.sort_by_key(|n| (n.i, n.j, n.image));
+        out
+    }
+}
+
+#[cfg(test)]
+#[deny(unused)]
+mod tests {
+    use super::*;
+    use crate::{Builder, Coords, ScaleLine};
+    use crate::math::add_3;
+
+    // a quick and dirty deterministic source of numbers in [0, 1)
+    fn lcg(state: &mut u64) -> f64 {
+        *state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (*state >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn brute_force(poscar: &Poscar, cutoff: f64) -> Vec<(usize, usize, [i32; 3])> {
+        let lattice = poscar.scaled_lattice();
+        let fracs = poscar.frac_positions();
+        let r = 8;
+        let mut out = vec![];
+        for i in 0..fracs.len() {
+            for j in 0..fracs.len() {
+                for a in -r..=r {
+                    for b in -r..=r {
+                        for c in -r..=r {
+                            if i == j && [a, b, c] == [0; 3] {
+                                continue;
+                            }
+                            let image = [a as f64, b as f64, c as f64];
+                            let diff = add_3(&crate::math::sub_3(&fracs[j], &fracs[i]), &image);
+                            if norm_3(&mul_3_33(&diff, &lattice)) <= cutoff {
+                                out.push((i, j, [a, b, c]));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        out.sort();
+        out
+    }
+
+    #[test]
+    fn against_brute_force() {
+        let mut state = 1;
+        let fracs: Vec<_> = (0..12).map(|_| {
+            // include some positions outside the unit cell
+            [3.0 * lcg(&mut state) - 1.0, lcg(&mut state), lcg(&mut state)]
+        }).collect();
+
+        let poscar =
+            Builder::new()
+            .scale(ScaleLine::Factor(1.5))
+            .lattice_vectors(&[[3.0, 0.0, 0.0], [2.5, 2.0, 0.0], [0.5, -0.5, 3.5]])
+            .positions(Coords::Frac(fracs))
+            .build().unwrap();
+
+        // small cutoffs use many bins, large cutoffs require images beyond the cell
+        for &cutoff in &[0.0, 1.0, 2.0, 3.5, 6.0, 9.0] {
+            let found = poscar.neighbors(cutoff);
+            let actual: Vec<_> = found.iter().map(|n| (n.i, n.j, n.image)).collect();
+            assert_eq!(actual, brute_force(&poscar, cutoff), "cutoff {}", cutoff);
+
+            for n in &found {
+                assert!((n.distance - norm_3(&n.displacement)).abs() < 1e-12);
+            }
+        }
+    }
+
+    #[test]
+    fn symmetric() {
+        let poscar =
+            Builder::new()
+            .lattice_vectors(&[[2.0, 0.0, 0.0], [0.0, 2.0, 0.0], [0.0, 0.0, 2.0]])
+            .positions(Coords::Cart(vec![[0.1, 0.2, 0.3], [1.9, 1.5, -0.2]]))
+            .build().unwrap();
+
+        let neighbors = poscar.neighbors(3.0);
+        for n in &neighbors {
+            let neg_image = [-n.image[0], -n.image[1], -n.image[2]];
+            assert!(neighbors.iter().any(|m| (m.i, m.j, m.image) == (n.j, n.i, neg_image)));
+        }
+    }
+}
@@ -0,0 +1,207 @@
+// Copyright 2018 Michael Lamparski
+// Part of the vasp-poscar crate.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use crate::{Poscar, Coords};
+use crate::math::{cross_f64, det_f64, dot_f64, mul_3_33, mul_33_33, norm_3, scale_n3, transpose_33};
+use crate::types::CART;
+
+/// # Rotations
+impl Poscar {
+    /// Rigidly rotate the structure.
+    ///
+    /// `rotation` is a proper rotation matrix in the usual sense; i.e. it acts on
+    /// *column* vectors, taking each Cartesian vector `v` to `rotation * v`.
+    /// (since this crate stores vectors as rows, this is `v * transpose(rotation)`
+    /// in terms of the stored data).
+    ///
+    /// The lattice vectors, Cartesian positions and Cartesian velocities are rotated.
+    /// Direct coordinates are unchanged, since they are relative to the lattice.
+    ///
+    /// # Errors
+    ///
+    /// Fails (leaving the Poscar unchanged) if `rotation` is not orthogonal with a
+    /// determinant of `+1` (within `1e-8`), or if it would mix Cartesian components
+    /// that have different selective dynamics flags.
+    pub fn rotate(&mut self, rotation: &[[f64; 3]; 3]) -> Result<(), failure::Error>
+    {
+        let product = mul_33_33(rotation, &transpose_33(rotation));
+        let orthogonal = (0..3).all(|r| (0..3).all(|c| {
+            let expected = if r == c { 1.0 } else { 0.0 };
+            (product[r][c] - expected).abs() <= 1e-8
+        }));
+        ensure!(orthogonal, "matrix is not orthogonal: {:?}", rotation);
+        ensure!(det_f64(rotation) > 0.0, "matrix is an improper rotation: {:?}", rotation);
+
+        // row vectors transform by the transpose
+        let matrix = transpose_33(rotation);
+        let dynamics = self.transformed_dynamics(CART, &matrix)?;
+
+        let rotate_coords = |coords: &mut Coords| {
+            if let Coords::Cart(v) = coords {
+                *v = v.iter().map(|x| mul_3_33(x, &matrix)).collect();
+            }
+        };
+        rotate_coords(&mut self.0.positions);
+        if let Some(velocities) = self.0.velocities.as_mut() {
+            rotate_coords(velocities);
+        }
+        self.0.lattice_vectors = mul_33_33(&self.0.lattice_vectors, &matrix);
+        self.0.dynamics = dynamics;
+        Ok(())
+    }
+
+    /// Rotate the structure so that the lattice is lower-triangular; i.e. so that
+    /// the first lattice vector points along `+x`, and the second lies in the `xy`
+    /// plane with a positive `y` component.
+    ///
+    /// The third lattice vector has a positive `z` component if the lattice is
+    /// right-handed (no reflection is ever performed).  The rotation is applied in
+    /// the same manner as [`rotate`], and is returned so that it can be applied
+    /// to other data (e.g. forces).
+    ///
+    /// ```rust
+    /// # fn main() -> Result<(), failure::Error> {Ok({
+    /// use vasp_poscar::{Builder, Coords};
+    ///
+    /// let mut poscar =
+    ///     Builder::new()
+    ///     .lattice_vectors(&[[0.0, 2.0, 0.0], [-3.0, 0.0, 0.0], [0.0, 0.0, 4.0]])
+    ///     .positions(Coords::Cart(vec![[1.0, 1.0, 1.0]]))
+    ///     .build()?;
+    ///
+    /// let rotation = poscar.standard_orientation()?;
+    /// assert_eq!(rotation, [[0.0, 1.0, 0.0], [-1.0, 0.0, 0.0], [0.0, 0.0, 1.0]]);
+    /// assert_eq!(poscar.unscaled_lattice_vectors(), [[2.0, 0.0, 0.0], [0.0, 3.0, 0.0], [0.0, 0.0, 4.0]]);
+    /// assert_eq!(poscar.unscaled_cart_positions(), vec![[1.0, -1.0, 1.0]]);
+    /// # })}
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Fails under the same conditions regarding selective dynamics as [`rotate`].
+    ///
+    /// [`rotate`]: #method.rotate
+    pub fn standard_orientation(&mut self) -> Result<[[f64; 3]; 3], failure::Error>
+    {
+        let rotation = standard_orientation_rotation(&self.unscaled_lattice());
+        self.rotate(&rotation)?;
+
+        // Clean up the roundoff in the entries that should be zero.
+        let lattice = &mut self.0.lattice_vectors;
+        lattice[0][1] = 0.0;
+        lattice[0][2] = 0.0;
+        lattice[1][2] = 0.0;
+        Ok(rotation)
+    }
+}
+
+/// The rotation (acting on column vectors) that brings `lattice` into lower triangular form.
+pub(crate) fn standard_orientation_rotation(lattice: &[[f64; 3]; 3]) -> [[f64; 3]; 3]
+{
+    let [a, b, _] = *lattice;
+    let e1 = scale_n3(&[a], 1.0 / norm_3(&a)).0[0];
+    let b_perp = arr_3![k => b[k] - dot_f64(&b, &e1) * e1[k]];
+    let e2 = scale_n3(&[b_perp], 1.0 / norm_3(&b_perp)).0[0];
+    let e3 = cross_f64(&e1, &e2);
+    // The rows of the rotation are the new Cartesian axes.
+    [e1, e2, e3]
+}
+
+#[cfg(test)]
+#[deny(unused)]
+mod tests {
+    use super::*;
+    use crate::util::test_structures::oblique_pair;
+
+    // all pairwise distances and dot products between the lattice and positions
+    fn invariants(poscar: &Poscar) -> Vec<f64> {
+        let lattice = poscar.scaled_lattice();
+        let mut vectors: Vec<[f64; 3]> = lattice.to_vec();
+        vectors.extend(poscar.scaled_cart_positions().iter().cloned());
+        vectors.extend(poscar.cart_velocities().unwrap().iter().cloned());
+        let mut out = vec![];
+        for a in &vectors {
+            for b in &vectors {
+                out.push(dot_f64(a, b));
+            }
+        }
+        out.push(det_f64(&lattice));
+        out
+    }
+
+    #[test]
+    fn standard_orientation() {
+        for &left_handed in &[false, true] {
+            let mut poscar = oblique_pair();
+            if left_handed {
+                let mut raw = poscar.into_raw();
+                raw.lattice_vectors.swap(0, 1);
+                poscar = raw.validate().unwrap();
+            }
+            let original = poscar.clone();
+            let rotation = poscar.standard_orientation().unwrap();
+
+            let lattice = poscar.unscaled_lattice_vectors();
+            assert_eq!([lattice[0][1], lattice[0][2], lattice[1][2]], [0.0; 3]);
+            assert!(lattice[0][0] > 0.0);
+            assert!(lattice[1][1] > 0.0);
+            assert_eq!(lattice[2][2] > 0.0, !left_handed);
+
+            for (a, b) in zip!(invariants(&poscar), invariants(&original)) {
+                assert!((a - b).abs() < 1e-10);
+            }
+
+            // the returned rotation reproduces the result
+            let mut again = original.clone();
+            again.rotate(&rotation).unwrap();
+            for (a, b) in zip!(again.scaled_cart_positions().iter(), poscar.scaled_cart_positions().iter()) {
+                for k in 0..3 {
+                    assert!((a[k] - b[k]).abs() < 1e-10);
+                }
+            }
+
+            // direct data is untouched
+            assert_eq!(poscar.0.velocities, original.0.velocities);
+        }
+    }
+
+    #[test]
+    fn rotate() {
+        let mut poscar = oblique_pair();
+        let rotation = [[0.0, -1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0]];
+        poscar.rotate(&rotation).unwrap();
+        assert_eq!(poscar.unscaled_cart_positions(), vec![[-0.5, 0.5, 0.5], [1.0, 1.0, 2.0]]);
+        assert_eq!(poscar.unscaled_lattice_vectors()[0], [-2.0, 1.0, 0.5]);
+
+        assert!(poscar.rotate(&[[2.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]]).is_err());
+        assert!(poscar.rotate(&[[-1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]]).is_err());
+    }
+
+    #[test]
+    fn rotate_dynamics() {
+        let mut raw = oblique_pair().into_raw();
+        raw.dynamics = Some(vec![[true, false, true], [false; 3]]);
+        let poscar = raw.validate().unwrap();
+
+        // a quarter turn about z swaps the x and y constraints
+        let mut rotated = poscar.clone();
+        rotated.rotate(&[[0.0, -1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0]]).unwrap();
+        assert_eq!(rotated.0.dynamics, Some(vec![[false, true, true], [false; 3]]));
+
+        // other rotations can't preserve the constraints
+        let (c, s) = (0.6, 0.8);
+        let mut rotated = poscar.clone();
+        assert!(rotated.rotate(&[[c, -s, 0.0], [s, c, 0.0], [0.0, 0.0, 1.0]]).is_err());
+
+        // ...unless they're stored in direct coordinates
+        let mut rotated = poscar.to_frac_storage();
+        rotated.rotate(&[[c, -s, 0.0], [s, c, 0.0], [0.0, 0.0, 1.0]]).unwrap();
+        assert_eq!(rotated.0.dynamics, poscar.0.dynamics);
+    }
+}
@@ -0,0 +1,369 @@
+// Copyright 2018 Michael Lamparski
+// Part of the vasp-poscar crate.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Random perturbations.
+//
+// The random numbers are fully specified, so that the output for a given seed
+// is identical on every platform and will not change between versions:
+//
+// * The generator is xoshiro256\*\*, with its state initialized from the seed
+//   by four successive outputs of SplitMix64.
+// * Uniform deviates in `[0, 1)` are the upper 53 bits of an output, times `2^-53`.
+// * Normal deviates use Marsaglia's polar method, consuming two uniforms per attempt
+//   and discarding the second deviate of each accepted pair.  Its logarithm is
+//   implemented in the crate using only basic arithmetic, so that it does not depend
+//   on the platform's math library.
+
+use crate::{Poscar, Coords};
+use crate::math::{inv_f64, mul_3_33};
+
+/// # Random perturbations
+impl Poscar {
+    /// Displace each site randomly, with each Cartesian component drawn independently
+    /// from a normal distribution with standard deviation `stdev` (in scaled units).
+    ///
+    /// If `respect_dynamics` is `true`, components that are fixed by selective dynamics
+    /// are left exactly as they are.  (these refer to the stored coordinates; so for
+    /// direct coordinates, the displacement along each fixed lattice vector is removed).
+    /// Otherwise, the flags are ignored (but kept).  Velocities are not modified.
+    ///
+    /// The output is determined entirely by the seed.  The displacements of site 0 are
+    /// drawn first (`x`, `y`, then `z`), followed by those of site 1, and so on; see the
+    /// source of this module for the exact specification of the generator.
+    ///
+    /// ```rust
+    /// # fn main() -> Result<(), failure::Error> {Ok({
+    /// use vasp_poscar::{Builder, Coords};
+    ///
+    /// let poscar =
+    ///     Builder::new()
+    ///     .dummy_lattice_vectors()
+    ///     .positions(Coords::Cart(vec![[0.0; 3], [0.5; 3]]))
+    ///     .build()?;
+    ///
+    /// let mut a = poscar.clone();
+    /// let mut b = poscar.clone();
+    /// a.rattle(0.01, 42, true)?;
+    /// b.rattle(0.01, 42, true)?;
+    /// assert_eq!(a.unscaled_cart_positions(), b.unscaled_cart_positions());
+    /// assert_ne!(a.unscaled_cart_positions(), poscar.unscaled_cart_positions());
+    /// # })}
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Fails (leaving the Poscar unchanged) if `stdev` is negative or not finite.
+    pub fn rattle(&mut self, stdev: f64, seed: u64, respect_dynamics: bool) -> Result<(), failure::Error>
+    {
+        ensure!(stdev.is_finite() && stdev >= 0.0, "standard deviation must be non-negative: {}", stdev);
+
+        let mut rng = Rng::new(seed);
+        let inverse = inv_f64(&self.scaled_lattice());
+        let scale = self.effective_scale_factor();
+        let dynamics = match respect_dynamics {
+            true => self.0.dynamics.clone(),
+            false => None,
+        };
+
+        // Displace the stored coordinates directly, so that fixed components
+        // are never converted and stay bit-for-bit identical.
+        let (is_frac, stored) = match self.0.positions {
+            Coords::Frac(ref mut v) => (true, v),
+            Coords::Cart(ref mut v) => (false, v),
+        };
+        for (site, x) in stored.iter_mut().enumerate() {
+            let cart_displacement = arr_3![_k => stdev * rng.next_normal()];
+            let displacement = match is_frac {
+                true => mul_3_33(&cart_displacement, &inverse),
+                false => arr_3![k => cart_displacement[k] / scale],
+            };
+            let free = dynamics.as_ref().map_or([true; 3], |dynamics| dynamics[site]);
+            for k in 0..3 {
+                if free[k] {
+                    x[k] += displacement[k];
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Apply a random symmetric strain (see [`apply_strain`]).
+    ///
+    /// Each of the six independent elements of the strain tensor is drawn from a normal
+    /// distribution with standard deviation `stdev`, in the order `xx, yy, zz, yz, xz, xy`.
+    /// The generator is the same as in [`rattle`], so use a different seed for each if
+    /// both are applied.
+    ///
+    /// # Errors
+    ///
+    /// Fails (leaving the Poscar unchanged) if `stdev` is negative or not finite, or under
+    /// the same conditions as [`apply_strain`].
+    ///
+    /// [`apply_strain`]: #method.apply_strain
+    /// [`rattle`]: #method.rattle
+    pub fn rattle_lattice(&mut self, stdev: f64, seed: u64) -> Result<(), failure::Error>
+    {
+        ensure!(stdev.is_finite() && stdev >= 0.0, "standard deviation must be non-negative: {}", stdev);
+
+        let mut rng = Rng::new(seed);
+        let mut strain = [[0.0; 3]; 3];
+        for &(r, c) in &[(0, 0), (1, 1), (2, 2), (1, 2), (0, 2), (0, 1)] {
+            let x = stdev * rng.next_normal();
+            strain[r][c] = x;
+            strain[c][r] = x;
+        }
+        self.apply_strain(&strain)
+    }
+}
+
+/// The xoshiro256** generator, seeded with SplitMix64.
+#[derive(Debug, Clone)]
+pub(crate) struct Rng {
+    state: [u64; 4],
+}
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Self
+    {
+        let mut splitmix = seed;
+        let mut state = [0u64; 4];
+        for x in &mut state {
+            *x = splitmix64(&mut splitmix);
+        }
+        Rng { state }
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64
+    {
+        let s = &mut self.state;
+        let result = s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = s[1] << 17;
+        s[2] ^= s[0];
+        s[3] ^= s[1];
+        s[1] ^= s[2];
+        s[0] ^= s[3];
+        s[2] ^= t;
+        s[3] = s[3].rotate_left(45);
+        result
+    }
+
+    /// Uniform in `[0, 1)`.
+    pub(crate) fn next_f64(&mut self) -> f64
+    { (self.next_u64() >> 11) as f64 * (1.0 / (1u64 << 53) as f64) }
+
+    /// Standard normal deviate.
+    pub(crate) fn next_normal(&mut self) -> f64
+    {
+        loop {
+            let u = 2.0 * self.next_f64() - 1.0;
+            let v = 2.0 * self.next_f64() - 1.0;
+            let s = u * u + v * v;
+            if (f64::MIN_POSITIVE..1.0).contains(&s) {
+                return u * (-2.0 * portable_ln(s) / s).sqrt();
+            }
+        }
+    }
+}
+
+fn splitmix64(state: &mut u64) -> u64
+{
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Natural logarithm of a positive, finite, normal number, using only basic arithmetic.
+///
+/// With `x = m * 2^e` for `m` in `[sqrt(1/2), sqrt(2))`, this computes
+/// `ln(m) = 2 atanh((m - 1) / (m + 1))` by its Taylor series.
+fn portable_ln(x: f64) -> f64
+{
+    debug_assert!(x.is_normal() && x > 0.0);
+    let bits = x.to_bits();
+    let mut exponent = ((bits >> 52) & 0x7ff) as i64 - 1023;
+    let mut m = f64::from_bits((bits & 0x000f_ffff_ffff_ffff) | (1023 << 52));
+    if m > std::f64::consts::SQRT_2 {
+        m *= 0.5;
+        exponent += 1;
+    }
+
+    // |z| < 0.172, so each term is 34x smaller than the last
+    let z = (m - 1.0) / (m + 1.0);
+    let z2 = z * z;
+    let mut term = z;
+    let mut sum = 0.0;
+    for k in 0..12 {
+        sum += term / f64::from(2 * k + 1);
+        term *= z2;
+    }
+    2.0 * sum + exponent as f64 * std::f64::consts::LN_2
+}
+
+#[cfg(test)]
+#[deny(unused)]
+mod tests {
+    use super::*;
+    use crate::{Builder, ScaleLine};
+
+    #[test]
+    fn generator() {
+        // reference value for the first output of SplitMix64 with seed 0
+        assert_eq!(splitmix64(&mut 0), 0xe220_a839_7b1d_cdaf);
+
+        let mut rng = Rng::new(1);
+        let first: Vec<u64> = (0..3).map(|_| rng.next_u64()).collect();
+        let mut again = Rng::new(1);
+        assert_eq!(first, (0..3).map(|_| again.next_u64()).collect::<Vec<_>>());
+        assert_ne!(first, (0..3).map(|_| Rng::new(2).next_u64()).collect::<Vec<_>>());
+
+        let mut rng = Rng::new(7);
+        let n = 20000;
+        let samples: Vec<f64> = (0..n).map(|_| rng.next_normal()).collect();
+        let mean = samples.iter().sum::<f64>() / n as f64;
+        let variance = samples.iter().map(|x| (x - mean) * (x - mean)).sum::<f64>() / n as f64;
+        assert!(mean.abs() < 0.03, "{}", mean);
+        assert!((variance - 1.0).abs() < 0.05, "{}", variance);
+    }
+
+    // Reference values; these must never change.
+    #[test]
+    fn stable_output() {
+        // published test vector for xoshiro256** from the state [1, 2, 3, 4]
+        let mut rng = Rng { state: [1, 2, 3, 4] };
+        let outputs: Vec<u64> = (0..4).map(|_| rng.next_u64()).collect();
+        assert_eq!(outputs, vec![11520, 0, 1509978240, 1215971899390074240]);
+
+        let mut rng = Rng::new(42);
+        let outputs: Vec<u64> = (0..3).map(|_| rng.next_u64()).collect();
+        assert_eq!(outputs, vec![0x1578_0b2e_0c2e_c716, 0x6104_d986_6d11_3a7e, 0xae17_5332_39e4_99a1]);
+
+        let mut rng = Rng::new(42);
+        let normals: Vec<u64> = (0..4).map(|_| rng.next_normal().to_bits()).collect();
+        assert_eq!(normals, vec![
+            0xbfe7_3d2f_eb0f_b377, // -0.7262191382447857
+            0x3fcc_5e21_f781_2a4c, // 0.2216227015035933
+            0x3fdd_b514_bfac_5b4e, // 0.46417731016247366
+            0x3ff7_9eb7_c13c_fccd, // 1.476249461018415
+        ]);
+
+        let mut poscar = {
+            Builder::new()
+                .scale(ScaleLine::Factor(1.5))
+                .lattice_vectors(&[[3.0, 0.0, 0.0], [0.5, 2.5, 0.0], [0.0, 0.25, 4.0]])
+                .positions(Coords::Frac(vec![[0.0; 3], [0.25, 0.5, 0.75]]))
+                .build().unwrap()
+        };
+        poscar.rattle(0.1, 42, true).unwrap();
+        assert_eq!(poscar.0.positions, Coords::Frac(vec![
+            [-0.016994254714854964, 0.005136309856491699, 0.007736288502707894],
+            [0.2786116804039888, 0.525163179045189, 0.7671201826793256],
+        ]));
+    }
+
+    #[test]
+    fn logarithm() {
+        for &x in &[1.0, 0.5, 2.0, 1e-300, 0.999999, 1.5, 0.7, 1.4143, 12345.678, f64::MIN_POSITIVE] {
+            let expected = x.ln();
+            assert!((portable_ln(x) - expected).abs() <= 4.0 * f64::EPSILON * expected.abs().max(1.0), "{}", x);
+        }
+        assert_eq!(portable_ln(1.0), 0.0);
+
+        // reference values (the first is one ulp away from the correctly rounded result)
+        assert_eq!(portable_ln(0.3).to_bits(), 0xbff3_4378_fcbd_a720);
+        assert_eq!(portable_ln(12345.678).to_bits(), 0x4022_d795_5979_1e31);
+    }
+
+    // Values that do not survive a round trip through the other coordinate system.
+    fn messy(storage: Coords<()>) -> Poscar {
+        let values = vec![[0.1234567, 0.2345678, 0.3456789], [0.9876543, -0.1111111, 0.7182818], [0.5772157, 0.3141593, 1.4242136]];
+        let positions = match storage {
+            Coords::Frac(()) => Coords::Frac(values),
+            Coords::Cart(()) => Coords::Cart(values),
+        };
+        Builder::new()
+            .scale(ScaleLine::Volume(47.123))
+            .lattice_vectors(&[[3.1234567, 0.0123, -0.2], [0.987654, 2.7281828, 0.1], [-0.3141592, 0.5772156, 4.6692016]])
+            .group_counts(vec![3])
+            .positions(positions)
+            .dynamics(vec![[true; 3], [false, true, false], [false; 3]])
+            .build().unwrap()
+    }
+
+    #[test]
+    fn rattle() {
+        for &storage in &[Coords::Frac(()), Coords::Cart(())] {
+            let original = messy(storage);
+            let mut poscar = original.clone();
+            poscar.rattle(0.05, 123, true).unwrap();
+            assert_eq!(poscar.0.positions.tag(), storage);
+            assert_eq!(poscar.0.dynamics, original.0.dynamics);
+
+            // frozen components are untouched, bit for bit
+            let old = original.0.positions.as_ref().raw();
+            let new = poscar.0.positions.as_ref().raw();
+            for (site, flags) in original.0.dynamics.as_ref().unwrap().iter().enumerate() {
+                for k in 0..3 {
+                    assert_eq!(new[site][k] == old[site][k], !flags[k], "{} {}", site, k);
+                }
+            }
+
+            // everything moves when the flags are ignored, but they are kept
+            let mut ignored = original.clone();
+            ignored.rattle(0.05, 123, false).unwrap();
+            assert_eq!(ignored.0.dynamics, original.0.dynamics);
+            let new = ignored.0.positions.as_ref().raw();
+            assert!((0..3).all(|site| (0..3).all(|k| new[site][k] != old[site][k])));
+
+            let mut again = original.clone();
+            again.rattle(0.05, 123, true).unwrap();
+            assert_eq!(format!("{:?}", again), format!("{:?}", poscar));
+
+            let mut zero = original.clone();
+            zero.rattle(0.0, 123, true).unwrap();
+            assert_eq!(format!("{:?}", zero), format!("{:?}", original));
+        }
+        assert!(messy(Coords::Frac(())).rattle(-1.0, 0, true).is_err());
+        assert!(messy(Coords::Frac(())).rattle(f64::NAN, 0, true).is_err());
+    }
+
+    #[test]
+    fn rattle_storage_agrees() {
+        // the same Cartesian displacements are drawn regardless of storage
+        let displacements = |poscar: &Poscar| {
+            let mut rattled = poscar.clone();
+            rattled.rattle(0.05, 9, false).unwrap();
+            let (old, new) = (poscar.scaled_cart_positions(), rattled.scaled_cart_positions());
+            zip!(old.iter(), new.iter()).map(|(a, b)| arr_3![k => b[k] - a[k]]).collect::<Vec<_>>()
+        };
+        let frac = messy(Coords::Frac(()));
+        let from_frac = displacements(&frac);
+        let from_cart = displacements(&frac.to_cart_storage());
+        for (a, b) in zip!(from_frac, from_cart) {
+            for k in 0..3 {
+                assert!((a[k] - b[k]).abs() < 1e-12);
+                assert!(a[k] != 0.0);
+            }
+        }
+    }
+
+    #[test]
+    fn rattle_lattice() {
+        let original = messy(Coords::Frac(()));
+        let mut poscar = original.clone();
+        poscar.rattle_lattice(0.01, 5).unwrap();
+        assert_eq!(poscar.frac_positions(), original.frac_positions());
+        assert_ne!(poscar.scaled_lattice(), original.scaled_lattice());
+
+        let mut again = original.clone();
+        again.rattle_lattice(0.01, 5).unwrap();
+        assert_eq!(again.scaled_lattice(), poscar.scaled_lattice());
+    }
+}
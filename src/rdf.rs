@@ -0,0 +1,218 @@
+// Copyright 2018 Michael Lamparski
+// Part of the vasp-poscar crate.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Radial distribution functions.
+
+use crate::Poscar;
+
+use std::f64::consts::PI;
+
+/// The output of [`radial_distribution`].
+///
+/// [`radial_distribution`]: fn.radial_distribution.html
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rdf {
+    /// The center of each bin.
+    pub r: Vec<f64>,
+    /// The total `g(r)` over all pairs of sites.
+    pub total: Vec<f64>,
+    /// The partial `g(r)` for each unordered pair of species, sorted by species.
+    pub partials: Vec<PartialRdf>,
+}
+
+/// A partial radial distribution function between two species.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PartialRdf {
+    /// The species labels, in sorted order.
+    pub species: (String, String),
+    pub g: Vec<f64>,
+}
+
+/// Compute the total and partial radial distribution functions, averaged over
+/// one or more frames.
+///
+/// Distances are binned into bins of width `bin_width` from `0` up to at least
+/// `r_max`.  Each bin is normalized by the volume of its spherical shell and by
+/// the average density (using [`Poscar::scaled_volume`]), so that `g(r)` tends to
+/// `1` for an uncorrelated system.  Specifically, the partial for species `a` and `b` is
+///
+/// ```text
+///            V
+/// g_ab(r) = ------- < number of b sites in the shell at r around each a site >
+///           N_a N_b                  (summed over a sites)
+///                   ------------------------------------------------------
+///                                  volume of the shell
+/// ```
+///
+/// (with a site never counted as its own neighbor, though its periodic images are).
+///
+/// Species are identified in the same manner as in [`Poscar::bonds`];
+/// that is, by symbol, or by group index if there are no symbols.
+///
+/// # Errors
+///
+/// Fails if there are no frames, if the frames do not all have the same species
+/// for each site, or if `bin_width` or `r_max` is not positive.
+///
+/// [`Poscar::scaled_volume`]: struct.Poscar.html#method.scaled_volume
+/// [`Poscar::bonds`]: struct.Poscar.html#method.bonds
+pub fn radial_distribution(frames: &[Poscar], bin_width: f64, r_max: f64) -> Result<Rdf, failure::Error>
+{
+    ensure!(!frames.is_empty(), "no frames were provided");
+    ensure!(bin_width > 0.0 && r_max > 0.0, "bin width and r_max must be positive");
+
+    let labels = frames[0].site_species_labels();
+    for frame in &frames[1..] {
+        ensure!(
+            frame.site_species_labels() == labels,
+            "all frames must have the same species in the same order",
+        );
+    }
+
+    let mut species: Vec<&str> = labels.iter().map(|s| &s[..]).collect();
+    species.sort();
+    species.dedup();
+    let site_species: Vec<usize> = {
+        labels.iter()
+            .map(|l| species.binary_search(&&l[..]).expect("(BUG) missing species"))
+            .collect()
+    };
+    let species_counts: Vec<f64> = {
+        (0..species.len())
+            .map(|s| site_species.iter().filter(|&&x| x == s).count() as f64)
+            .collect()
+    };
+    let n_sites = labels.len() as f64;
+
+    let n_bins = (r_max / bin_width * (1.0 - 1e-12)).ceil() as usize;
+    let cutoff = n_bins as f64 * bin_width;
+    let shell_volumes: Vec<f64> = {
+        (0..n_bins)
+            .map(|k| {
+                let (lo, hi) = (k as f64 * bin_width, (k + 1) as f64 * bin_width);
+                4.0 / 3.0 * PI * (hi * hi * hi - lo * lo * lo)
+            })
+            .collect()
+    };
+
+    // Accumulates per-frame normalized counts.  Partials are indexed by
+    // ordered species pairs, and symmetrized at the end.
+    let n_species = species.len();
+    let mut total = vec![0.0; n_bins];
+    let mut partials = vec![vec![vec![0.0; n_bins]; n_species]; n_species];
+    for frame in frames {
+        let volume = frame.scaled_volume();
+        for neighbor in frame.neighbors(cutoff) {
+            let bin = (neighbor.distance / bin_width) as usize;
+            if bin >= n_bins {
+                continue;
+            }
+            let (a, b) = (site_species[neighbor.i], site_species[neighbor.j]);
+            total[bin] += volume / (n_sites * n_sites);
+            partials[a][b][bin] += volume / (species_counts[a] * species_counts[b]);
+        }
+    }
+
+    let n_frames = frames.len() as f64;
+    let finish = |counts: &[f64]| -> Vec<f64> {
+        zip!(counts, &shell_volumes)
+            .map(|(c, shell)| c / (shell * n_frames))
+            .collect()
+    };
+
+    let mut out_partials = vec![];
+    for a in 0..n_species {
+        for b in a..n_species {
+            // the full neighbor list makes partials[a][b] equal to partials[b][a]
+            out_partials.push(PartialRdf {
+                species: (species[a].to_string(), species[b].to_string()),
+                g: finish(&partials[a][b]),
+            });
+        }
+    }
+
+    Ok(Rdf {
+        r: (0..n_bins).map(|k| (k as f64 + 0.5) * bin_width).collect(),
+        total: finish(&total),
+        partials: out_partials,
+    })
+}
+
+#[cfg(test)]
+#[deny(unused)]
+mod tests {
+    use super::*;
+    use crate::{Builder, Coords, ScaleLine};
+
+    fn rocksalt(a: f64) -> Poscar {
+        Builder::new()
+            .scale(ScaleLine::Factor(a))
+            .dummy_lattice_vectors()
+            .group_symbols(vec!["Na", "Cl"])
+            .group_counts(vec![4, 4])
+            .positions(Coords::Frac(vec![
+                [0.0, 0.0, 0.0], [0.0, 0.5, 0.5], [0.5, 0.0, 0.5], [0.5, 0.5, 0.0],
+                [0.5, 0.5, 0.5], [0.5, 0.0, 0.0], [0.0, 0.5, 0.0], [0.0, 0.0, 0.5],
+            ]))
+            .build().unwrap()
+    }
+
+    // Integrate a partial g(r) into a running coordination number.
+    fn coordination(rdf: &Rdf, g: &[f64], density: f64, r: f64) -> f64 {
+        let width = rdf.r[1] - rdf.r[0];
+        zip!(&rdf.r, g)
+            .take_while(|&(&center, _)| center < r)
+            .map(|(&center, &g)| {
+                let (lo, hi) = (center - width / 2.0, center + width / 2.0);
+                density * g * 4.0 / 3.0 * PI * (hi * hi * hi - lo * lo * lo)
+            })
+            .sum()
+    }
+
+    #[test]
+    fn rocksalt_shells() {
+        let a = 5.0;
+        let poscar = rocksalt(a);
+        let rdf = radial_distribution(&[poscar.clone(), poscar], 0.1, 6.0).unwrap();
+        assert_eq!(rdf.r.len(), 60);
+        assert!((rdf.r[0] - 0.05).abs() < 1e-12);
+
+        let species: Vec<_> = rdf.partials.iter().map(|p| p.species.clone()).collect();
+        assert_eq!(species, vec![
+            ("Cl".to_string(), "Cl".to_string()),
+            ("Cl".to_string(), "Na".to_string()),
+            ("Na".to_string(), "Na".to_string()),
+        ]);
+
+        // density of each species is 4 / a^3.
+        let density = 4.0 / (a * a * a);
+        let cl_na = &rdf.partials[1].g;
+        let na_na = &rdf.partials[2].g;
+
+        // first shell (a/2): 6 unlike neighbors.  second shell (a/sqrt(2)): 12 like neighbors.
+        assert!((coordination(&rdf, cl_na, density, 3.0) - 6.0).abs() < 1e-10);
+        assert!(coordination(&rdf, na_na, density, 3.0).abs() < 1e-10);
+        assert!((coordination(&rdf, na_na, density, 4.0) - 12.0).abs() < 1e-10);
+
+        // total: 6 neighbors within 3.0 at a total density of 8 / a^3
+        assert!((coordination(&rdf, &rdf.total, 2.0 * density, 3.0) - 6.0).abs() < 1e-10);
+    }
+
+    #[test]
+    fn errors() {
+        let poscar = rocksalt(5.0);
+        assert!(radial_distribution(&[], 0.1, 5.0).is_err());
+        assert!(radial_distribution(std::slice::from_ref(&poscar), 0.0, 5.0).is_err());
+
+        let mut other = poscar.clone().into_raw();
+        other.group_symbols = Some(vec!["K".into(), "Cl".into()]);
+        let other = other.validate().unwrap();
+        assert!(radial_distribution(&[poscar, other], 0.1, 5.0).is_err());
+    }
+}
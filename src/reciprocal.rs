@@ -0,0 +1,185 @@
+// Copyright 2018 Michael Lamparski
+// Part of the vasp-poscar crate.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use crate::Poscar;
+use crate::math::{inv_f64, transpose_33, scale_33, scale_n3, mul_n3_33, norm_3};
+
+use std::f64::consts::PI;
+
+/// Selects whether reciprocal lattice vectors include a factor of `2π`.
+///
+/// With `TwoPi`, the reciprocal vectors `b_j` satisfy `a_i · b_j = 2π δ_ij`,
+/// which is the convention usually used by physicists (and by the `KSPACING`
+/// tag of VASP).  With `NoTwoPi`, they satisfy `a_i · b_j = δ_ij`, which is
+/// the convention usually used by crystallographers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReciprocalConvention {
+    TwoPi,
+    NoTwoPi,
+}
+
+impl ReciprocalConvention {
+    fn factor(self) -> f64
+    { match self {
+        ReciprocalConvention::TwoPi => 2.0 * PI,
+        ReciprocalConvention::NoTwoPi => 1.0,
+    }}
+}
+
+/// # Reciprocal space
+///
+/// All of these take the scale line into account, so that
+/// Cartesian k-points are in inverse units of the true lattice.
+impl Poscar {
+    /// Compute the reciprocal lattice vectors.
+    ///
+    /// ```rust
+    /// # fn main() -> Result<(), failure::Error> {Ok({
+    /// use vasp_poscar::{Builder, Coords, ScaleLine, ReciprocalConvention};
+    ///
+    /// let poscar =
+    ///     Builder::new()
+    ///     .scale(ScaleLine::Factor(2.0))
+    ///     .lattice_vectors(&[[1.0, 0.0, 0.0], [0.0, 2.0, 0.0], [0.0, 0.0, 4.0]])
+    ///     .positions(Coords::Frac(vec![[0.0; 3]]))
+    ///     .build()?;
+    ///
+    /// assert_eq!(
+    ///     poscar.reciprocal_lattice_vectors(ReciprocalConvention::NoTwoPi),
+    ///     [[0.5, 0.0, 0.0], [0.0, 0.25, 0.0], [0.0, 0.0, 0.125]],
+    /// );
+    /// # })}
+    /// ```
+    pub fn reciprocal_lattice_vectors(&self, convention: ReciprocalConvention) -> [[f64; 3]; 3]
+    {
+        let inv = inv_f64(&self.scaled_lattice());
+        scale_33(&transpose_33(&inv), convention.factor()).0
+    }
+
+    /// Convert k-points from fractional units of the reciprocal lattice into
+    /// Cartesian coordinates.
+    pub fn kpoints_frac_to_cart(&self, kpoints: &[[f64; 3]], convention: ReciprocalConvention) -> Vec<[f64; 3]>
+    { mul_n3_33(kpoints, &self.reciprocal_lattice_vectors(convention)) }
+
+    /// Convert k-points from Cartesian coordinates into fractional units of
+    /// the reciprocal lattice.
+    pub fn kpoints_cart_to_frac(&self, kpoints: &[[f64; 3]], convention: ReciprocalConvention) -> Vec<[f64; 3]>
+    {
+        // The inverse of the reciprocal lattice matrix is the transposed lattice
+        // (up to the factor), so no inverse needs to be computed.
+        let lattice_t = transpose_33(&self.scaled_lattice());
+        scale_n3(&mul_n3_33(kpoints, &lattice_t), convention.factor().recip()).0
+    }
+
+    /// Suggest a Monkhorst-Pack grid from a target spacing between k-points.
+    ///
+    /// The number of divisions along each reciprocal lattice vector is
+    /// `max(1, ceil(|b_i| / spacing))`, where the length of `b_i` depends on the
+    /// convention.  Using `ReciprocalConvention::TwoPi` reproduces the grid
+    /// VASP generates for `KSPACING = spacing`.
+    ///
+    /// ```rust
+    /// # fn main() -> Result<(), failure::Error> {Ok({
+    /// use vasp_poscar::{Builder, Coords, ReciprocalConvention};
+    ///
+    /// let poscar =
+    ///     Builder::new()
+    ///     .lattice_vectors(&[[4.0, 0.0, 0.0], [0.0, 4.0, 0.0], [0.0, 0.0, 20.0]])
+    ///     .positions(Coords::Frac(vec![[0.0; 3]]))
+    ///     .build()?;
+    ///
+    /// assert_eq!(poscar.monkhorst_pack_grid(0.05, ReciprocalConvention::NoTwoPi), [5, 5, 1]);
+    /// # })}
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if `spacing` is not positive.
+    pub fn monkhorst_pack_grid(&self, spacing: f64, convention: ReciprocalConvention) -> [u32; 3]
+    {
+        assert!(spacing > 0.0, "k-point spacing must be positive");
+
+        let recip = self.reciprocal_lattice_vectors(convention);
+        arr_3![i => {
+            // (a small tolerance keeps e.g. 5.000000000001 from becoming 6)
+            let n = (norm_3(&recip[i]) / spacing * (1.0 - 1e-10)).ceil();
+            f64::max(n, 1.0) as u32
+        }]
+    }
+}
+
+#[cfg(test)]
+#[deny(unused)]
+mod tests {
+    use super::*;
+    use crate::{Builder, Coords, ScaleLine};
+    use crate::math::{dot_f64, det_f64};
+
+    fn example_poscar(scale: ScaleLine) -> Poscar {
+        Builder::new()
+            .scale(scale)
+            .lattice_vectors(&[[2.0, 0.5, 0.0], [-1.0, 3.0, 0.25], [0.5, 0.0, 4.0]])
+            .positions(Coords::Frac(vec![[0.0; 3]]))
+            .build().unwrap()
+    }
+
+    #[test]
+    fn duality() {
+        for &scale in &[ScaleLine::Factor(1.5), ScaleLine::Volume(100.0)] {
+            let poscar = example_poscar(scale);
+            let lattice = poscar.scaled_lattice_vectors();
+            for &(convention, factor) in &[
+                (ReciprocalConvention::TwoPi, 2.0 * PI),
+                (ReciprocalConvention::NoTwoPi, 1.0),
+            ] {
+                let recip = poscar.reciprocal_lattice_vectors(convention);
+                for (i, a) in lattice.iter().enumerate() {
+                    for (j, b) in recip.iter().enumerate() {
+                        let expected = if i == j { factor } else { 0.0 };
+                        assert!((dot_f64(a, b) - expected).abs() < 1e-12);
+                    }
+                }
+                let recip_volume = det_f64(&recip);
+                let expected = factor.powi(3) / poscar.scaled_volume();
+                assert!((recip_volume - expected).abs() < 1e-12 * expected);
+            }
+        }
+    }
+
+    #[test]
+    fn kpoint_round_trip() {
+        let poscar = example_poscar(ScaleLine::Factor(1.5));
+        let kpoints = vec![[0.5, 0.0, 0.0], [0.25, -0.5, 0.125], [0.0, 0.0, 0.0]];
+        for &convention in &[ReciprocalConvention::TwoPi, ReciprocalConvention::NoTwoPi] {
+            let carts = poscar.kpoints_frac_to_cart(&kpoints, convention);
+            let back = poscar.kpoints_cart_to_frac(&carts, convention);
+            for (a, b) in zip!(&kpoints, &back) {
+                for k in 0..3 {
+                    assert!((a[k] - b[k]).abs() < 1e-12);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn grid() {
+        let poscar =
+            Builder::new()
+            .scale(ScaleLine::Factor(2.0))
+            .lattice_vectors(&[[1.0, 0.0, 0.0], [0.0, 2.0, 0.0], [0.0, 0.0, 5.0]])
+            .positions(Coords::Frac(vec![[0.0; 3]]))
+            .build().unwrap();
+
+        // |b| = 1/2, 1/4, 1/10
+        assert_eq!(poscar.monkhorst_pack_grid(0.05, ReciprocalConvention::NoTwoPi), [10, 5, 2]);
+        assert_eq!(poscar.monkhorst_pack_grid(0.04, ReciprocalConvention::NoTwoPi), [13, 7, 3]);
+        assert_eq!(poscar.monkhorst_pack_grid(10.0, ReciprocalConvention::NoTwoPi), [1, 1, 1]);
+        assert_eq!(poscar.monkhorst_pack_grid(0.5, ReciprocalConvention::TwoPi), [7, 4, 2]);
+    }
+}
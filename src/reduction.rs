@@ -0,0 +1,432 @@
+// Copyright 2018 Michael Lamparski
+// Part of the vasp-poscar crate.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use crate::{Poscar, Coords};
+use crate::math::{det_i32, adj_i32, det_f64, dot_f64, mul_3_33, mul_33_33, mul_i32_33};
+
+/// # Lattice reduction
+impl Poscar {
+    /// Replace the lattice with its Niggli-reduced basis.
+    ///
+    /// This is the Křivý–Gruber algorithm, with the tolerances described by
+    /// Grosse-Kunstleve et al. (2004).  `tol` is relative; comparisons between
+    /// entries of the metric tensor use a tolerance of `tol * V^(2/3)`, where `V`
+    /// is the scaled volume.  A typical value is `1e-5`.
+    ///
+    /// The Niggli-reduced cell of a lattice is unique, so any two bases of the
+    /// same lattice are reduced to cells with the same lengths and angles (within
+    /// the tolerance).  The handedness of the lattice is preserved.
+    ///
+    /// Only the basis changes; the physical structure is left untouched.  Cartesian
+    /// data is unchanged, while direct coordinates are re-expressed in the new basis.
+    /// Sites are not wrapped into the new cell.
+    ///
+    /// The return value `m` is the unimodular change-of-basis matrix, satisfying
+    /// `new_lattice = m * old_lattice` (with lattice vectors as rows, as they are stored).
+    ///
+    /// ```rust
+    /// # fn main() -> Result<(), failure::Error> {Ok({
+    /// use vasp_poscar::{Builder, Coords};
+    ///
+    /// let mut poscar =
+    ///     Builder::new()
+    ///     .lattice_vectors(&[[1.0, 0.0, 0.0], [3.0, 2.0, 0.0], [-1.0, 4.0, 3.0]])
+    ///     .positions(Coords::Cart(vec![[0.25, 0.5, 0.5]]))
+    ///     .build()?;
+    ///
+    /// let original = poscar.unscaled_lattice_vectors();
+    /// let m = poscar.niggli_reduce(1e-5)?;
+    ///
+    /// let params = poscar.unscaled_lattice_parameters();
+    /// assert_eq!((params.a, params.b, params.c), (1.0, 2.0, 3.0));
+    /// assert_eq!((params.alpha, params.beta, params.gamma), (90.0, 90.0, 90.0));
+    ///
+    /// let new = poscar.unscaled_lattice_vectors();
+    /// for r in 0..3 {
+    ///     for c in 0..3 {
+    ///         let value: f64 = (0..3).map(|k| f64::from(m[r][k]) * original[k][c]).sum();
+    ///         assert_eq!(value, new[r][c]);
+    ///     }
+    /// }
+    /// # })}
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Selective dynamics flags for direct coordinates constrain motion along the
+    /// lattice vectors, so they cannot always be expressed in a new basis.  This
+    /// fails (leaving the Poscar unchanged) if positions are stored in direct
+    /// coordinates, some site has a mixture of `T` and `F` flags, and the change of
+    /// basis is not a signed permutation of the lattice vectors.
+    pub fn niggli_reduce(&mut self, tol: f64) -> Result<[[i32; 3]; 3], failure::Error>
+    {
+        let m = niggli_matrix(&self.scaled_lattice(), tol);
+        self.change_basis(&m)?;
+        Ok(m)
+    }
+
+    /// Replace the lattice with a Delaunay-reduced basis.
+    ///
+    /// Selling's algorithm is used to reduce the superbase `{a, b, c, -(a + b + c)}`
+    /// until all of its scalar products are non-positive (within `tol * V^(2/3)`).
+    /// The new basis consists of the three shortest vectors among the resulting
+    /// superbase and its pairwise sums that form a unimodular basis, negated if
+    /// necessary so that the handedness of the lattice is preserved.
+    ///
+    /// Unlike the Niggli cell, the Delaunay cell is not unique for some lattices.
+    /// The lattice is transformed in the same manner as by [`niggli_reduce`],
+    /// and the return value has the same meaning.
+    ///
+    /// # Errors
+    ///
+    /// Fails under the same conditions as [`niggli_reduce`].
+    ///
+    /// [`niggli_reduce`]: #method.niggli_reduce
+    pub fn delaunay_reduce(&mut self, tol: f64) -> Result<[[i32; 3]; 3], failure::Error>
+    {
+        let m = delaunay_matrix(&self.scaled_lattice(), tol);
+        self.change_basis(&m)?;
+        Ok(m)
+    }
+}
+
+impl Poscar {
+    /// Re-express the structure in the basis `m * lattice`, where `m` is unimodular.
+    ///
+    /// This only fails on account of selective dynamics; see `niggli_reduce`.
+    pub(crate) fn change_basis(&mut self, m: &[[i32; 3]; 3]) -> Result<(), failure::Error>
+    {
+        assert_eq!(det_i32(m).abs(), 1, "(BUG) change of basis must be unimodular");
+
+        // new_frac = old_frac * inv(m)
+        let inv = adj_i32(m);
+        let inv = mat_3!((r, c) => det_i32(m) as f64 * inv[r][c] as f64);
+        let m_f = mat_3!((r, c) => f64::from(m[r][c]));
+
+        let dynamics = self.transformed_dynamics(crate::types::FRAC, &inv)?;
+
+        let transform = |coords: &mut Coords| {
+            if let Coords::Frac(v) = coords {
+                for x in v.iter_mut() {
+                    *x = mul_3_33(x, &inv);
+                }
+            }
+        };
+        transform(&mut self.0.positions);
+        if let Some(velocities) = self.0.velocities.as_mut() {
+            transform(velocities);
+        }
+        self.0.lattice_vectors = mul_33_33(&m_f, &self.0.lattice_vectors);
+        self.0.dynamics = dynamics;
+        Ok(())
+    }
+}
+
+fn metric_tolerance(lattice: &[[f64; 3]; 3], tol: f64) -> f64
+{ tol * det_f64(lattice).abs().powf(2.0 / 3.0) }
+
+/// Change of basis matrix to the Niggli cell.  (new_lattice = m * lattice)
+pub(crate) fn niggli_matrix(lattice: &[[f64; 3]; 3], tol: f64) -> [[i32; 3]; 3]
+{
+    let eps = metric_tolerance(lattice, tol);
+    let mut total = [[1, 0, 0], [0, 1, 0], [0, 0, 1]];
+
+    // Each pass performs the first applicable step and restarts.  (Steps 3 and 4
+    // normally fall through to step 5, but restarting is equivalent since they do
+    // not affect the conditions for steps 1 and 2.)  The metric is recomputed from
+    // scratch each time to avoid drift.
+    for _ in 0..10_000 {
+        let current = mul_33_33(&mat_3!((r, c) => f64::from(total[r][c])), lattice);
+        let g = |i: usize, j: usize| dot_f64(&current[i], &current[j]);
+        let (a, b, c) = (g(0, 0), g(1, 1), g(2, 2));
+        let (xi, eta, zeta) = (2.0 * g(1, 2), 2.0 * g(0, 2), 2.0 * g(0, 1));
+        let close = |x: f64, y: f64| (x - y).abs() <= eps;
+        let sign = |x: f64| if x > eps { 1 } else if x < -eps { -1 } else { 0 };
+        let signum = |x: f64| if x > 0.0 { 1 } else { -1 };
+
+        let step = if a > b + eps || (close(a, b) && xi.abs() > eta.abs() + eps) {
+            // A1
+            [[0, -1, 0], [-1, 0, 0], [0, 0, -1]]
+        } else if b > c + eps || (close(b, c) && eta.abs() > zeta.abs() + eps) {
+            // A2
+            [[-1, 0, 0], [0, 0, -1], [0, -1, 0]]
+        } else {
+            let (l, m, n) = (sign(xi), sign(eta), sign(zeta));
+            let flip = if l * m * n == 1 {
+                // A3
+                [signum(xi), signum(eta), signum(zeta)]
+            } else {
+                // A4
+                let mut flip = [1, 1, 1];
+                let mut zero_index = None;
+                for (k, &s) in [l, m, n].iter().enumerate() {
+                    match s {
+                        1 => flip[k] = -1,
+                        0 => zero_index = Some(k),
+                        _ => {},
+                    }
+                }
+                if flip[0] * flip[1] * flip[2] < 0 {
+                    match zero_index {
+                        Some(k) => flip[k] = -1,
+                        None => unreachable!("(BUG) A4 without a zero angle term"),
+                    }
+                }
+                flip
+            };
+            if flip != [1, 1, 1] {
+                total = mul_i32_33(&[[flip[0], 0, 0], [0, flip[1], 0], [0, 0, flip[2]]], &total);
+                continue;
+            }
+
+            if xi.abs() > b + eps || (close(xi, b) && 2.0 * eta < zeta - eps) || (close(xi, -b) && zeta < -eps) {
+                // A5
+                [[1, 0, 0], [0, 1, 0], [0, -signum(xi), 1]]
+            } else if eta.abs() > a + eps || (close(eta, a) && 2.0 * xi < zeta - eps) || (close(eta, -a) && zeta < -eps) {
+                // A6
+                [[1, 0, 0], [0, 1, 0], [-signum(eta), 0, 1]]
+            } else if zeta.abs() > a + eps || (close(zeta, a) && 2.0 * xi < eta - eps) || (close(zeta, -a) && eta < -eps) {
+                // A7
+                [[1, 0, 0], [-signum(zeta), 1, 0], [0, 0, 1]]
+            } else if xi + eta + zeta + a + b < -eps || (close(xi + eta + zeta + a + b, 0.0) && 2.0 * (a + eta) + zeta > eps) {
+                // A8
+                [[1, 0, 0], [0, 1, 0], [1, 1, 1]]
+            } else {
+                return total;
+            }
+        };
+        total = mul_i32_33(&step, &total);
+    }
+    panic!("(BUG) Niggli reduction failed to converge (is the tolerance negative?)")
+}
+
+/// Change of basis matrix to a Delaunay cell.  (new_lattice = m * lattice)
+pub(crate) fn delaunay_matrix(lattice: &[[f64; 3]; 3], tol: f64) -> [[i32; 3]; 3]
+{
+    let eps = metric_tolerance(lattice, tol);
+
+    // Superbase, as integer coefficients of the original lattice vectors.
+    let mut superbase = [[1, 0, 0], [0, 1, 0], [0, 0, 1], [-1, -1, -1]];
+    let cart = |v: &[i32; 3]| mul_3_33(&arr_3![k => f64::from(v[k])], lattice);
+
+    'selling: for _ in 0..10_000 {
+        for i in 0..4 {
+            for j in i + 1..4 {
+                if dot_f64(&cart(&superbase[i]), &cart(&superbase[j])) > eps {
+                    let bi = superbase[i];
+                    for (k, v) in superbase.iter_mut().enumerate() {
+                        if k == i {
+                            *v = arr_3![d => -v[d]];
+                        } else if k != j {
+                            *v = arr_3![d => v[d] + bi[d]];
+                        }
+                    }
+                    continue 'selling;
+                }
+            }
+        }
+
+        // Reduced.  Choose the shortest basis from the Delaunay set.
+        let sb = superbase;
+        let add = |a: [i32; 3], b: [i32; 3]| arr_3![d => a[d] + b[d]];
+        let mut candidates = [sb[0], sb[1], sb[2], sb[3], add(sb[0], sb[1]), add(sb[0], sb[2]), add(sb[1], sb[2])];
+        // (stable sort, so ties are resolved by the order above)
+        let length_sq = |v: &[i32; 3]| dot_f64(&cart(v), &cart(v));
+        candidates.sort_by(|a, b| length_sq(a).partial_cmp(&length_sq(b)).expect("(BUG) NaN"));
+
+        for i in 0..candidates.len() {
+            for j in i + 1..candidates.len() {
+                for k in j + 1..candidates.len() {
+                    let m = [candidates[i], candidates[j], candidates[k]];
+                    match det_i32(&m) {
+                        1 => return m,
+                        -1 => return mat_3!((r, c) => -m[r][c]),
+                        _ => {},
+                    }
+                }
+            }
+        }
+        unreachable!("(BUG) no unimodular basis in Delaunay set");
+    }
+    panic!("(BUG) Selling reduction failed to converge (is the tolerance negative?)")
+}
+
+#[cfg(test)]
+#[deny(unused)]
+mod tests {
+    use super::*;
+    use crate::Builder;
+    use crate::util::test_matrices::*;
+
+    // Lattices in various crystal systems.
+    fn examples() -> Vec<[[f64; 3]; 3]> {
+        vec![
+            // already reduced
+            [[2.0, 0.0, 0.0], [0.0, 3.0, 0.0], [0.0, 0.0, 4.0]],
+            // fcc primitive
+            [[0.0, 2.0, 2.0], [2.0, 0.0, 2.0], [2.0, 2.0, 0.0]],
+            // bcc primitive
+            [[-1.5, 1.5, 1.5], [1.5, -1.5, 1.5], [1.5, 1.5, -1.5]],
+            // hexagonal
+            [[3.0, 0.0, 0.0], [-1.5, 1.5 * 3f64.sqrt(), 0.0], [0.0, 0.0, 5.0]],
+            // triclinic
+            [[4.0, 0.0, 0.0], [1.25, 4.5, 0.0], [-0.75, 1.5, 5.25]],
+        ]
+    }
+
+    // Skewed bases of the same lattice.
+    fn unimodular_transforms() -> Vec<[[f64; 3]; 3]> {
+        vec![
+            EXAMPLE_UNIMODULAR,
+            EXAMPLE_UNIMODULAR_INV,
+            mul_33_33(&EXAMPLE_UNIMODULAR, &EXAMPLE_UNIMODULAR),
+            [[1.0, 0.0, 0.0], [4.0, 1.0, 0.0], [-3.0, 2.0, 1.0]],
+            // left-handed
+            [[0.0, 1.0, 0.0], [1.0, 0.0, 0.0], [1.0, 1.0, 1.0]],
+        ]
+    }
+
+    fn poscar_with_lattice(lattice: &[[f64; 3]; 3]) -> Poscar {
+        Builder::new()
+            .lattice_vectors(lattice)
+            .group_counts(vec![2])
+            .positions(Coords::Frac(vec![[0.1, 0.2, 0.3], [0.7, -0.4, 1.2]]))
+            .velocities(Coords::Frac(vec![[1.0, 0.0, 0.0], [0.0, 0.5, -0.5]]))
+            .build().unwrap()
+    }
+
+    fn assert_close_33(a: &[[f64; 3]; 3], b: &[[f64; 3]; 3]) {
+        for r in 0..3 {
+            for c in 0..3 {
+                assert!((a[r][c] - b[r][c]).abs() < 1e-9, "{:?} != {:?}", a, b);
+            }
+        }
+    }
+
+    fn assert_same_structure(a: &Poscar, b: &Poscar) {
+        let (pa, pb) = (a.scaled_cart_positions(), b.scaled_cart_positions());
+        let (va, vb) = (a.cart_velocities().unwrap(), b.cart_velocities().unwrap());
+        for (x, y) in zip!(pa.iter().chain(va.iter()), pb.iter().chain(vb.iter())) {
+            for k in 0..3 {
+                assert!((x[k] - y[k]).abs() < 1e-9, "{:?} != {:?}", x, y);
+            }
+        }
+        assert!((det_f64(&a.scaled_lattice()) - det_f64(&b.scaled_lattice())).abs() < 1e-9);
+    }
+
+    #[test]
+    fn niggli_recovers_reduced_cell() {
+        for lattice in examples() {
+            let mut reduced = poscar_with_lattice(&lattice);
+            reduced.niggli_reduce(1e-5).unwrap();
+            let reduced_lattice = reduced.scaled_lattice();
+
+            for transform in unimodular_transforms() {
+                let skewed_lattice = mul_33_33(&transform, &lattice);
+                let original = poscar_with_lattice(&skewed_lattice);
+                for storage in [original.clone(), original.to_cart_storage()] {
+                    let mut poscar = storage.clone();
+                    let m = poscar.niggli_reduce(1e-5).unwrap();
+                    assert_eq!(det_i32(&m), 1);
+
+                    let m_f = mat_3!((r, c) => f64::from(m[r][c]));
+                    assert_close_33(&mul_33_33(&m_f, &skewed_lattice), &poscar.scaled_lattice());
+                    assert_same_structure(&poscar, &storage);
+                    assert_eq!(poscar.0.positions.tag(), storage.0.positions.tag());
+
+                    // the metric of the Niggli cell is unique
+                    let metric = |l: &[[f64; 3]; 3]| mat_3!((r, c) => dot_f64(&l[r], &l[c]));
+                    assert_close_33(&metric(&poscar.scaled_lattice()), &metric(&reduced_lattice));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn niggli_basis_of_triclinic_lattice() {
+        // For a lattice with no symmetry besides inversion, the basis itself
+        // is unique (given the handedness).
+        let lattice = [[4.0, 0.0, 0.0], [1.25, 4.5, 0.0], [-0.75, 1.5, 5.25]];
+        let mut expected = poscar_with_lattice(&lattice);
+        expected.niggli_reduce(1e-5).unwrap();
+
+        for transform in unimodular_transforms() {
+            if det_f64(&transform) > 0.0 {
+                let mut poscar = poscar_with_lattice(&mul_33_33(&transform, &lattice));
+                poscar.niggli_reduce(1e-5).unwrap();
+                assert_close_33(&poscar.scaled_lattice(), &expected.scaled_lattice());
+            }
+        }
+    }
+
+    #[test]
+    fn delaunay() {
+        for lattice in examples() {
+            let sorted_lengths = |poscar: &Poscar| {
+                let params = poscar.scaled_lattice_parameters();
+                let mut lengths = vec![params.a, params.b, params.c];
+                lengths.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                lengths
+            };
+            let mut reduced = poscar_with_lattice(&lattice);
+            reduced.delaunay_reduce(1e-5).unwrap();
+            let expected_lengths = sorted_lengths(&reduced);
+
+            for transform in unimodular_transforms() {
+                let skewed_lattice = mul_33_33(&transform, &lattice);
+                let original = poscar_with_lattice(&skewed_lattice);
+                let mut poscar = original.clone();
+                let m = poscar.delaunay_reduce(1e-5).unwrap();
+                assert_eq!(det_i32(&m), 1);
+
+                let m_f = mat_3!((r, c) => f64::from(m[r][c]));
+                assert_close_33(&mul_33_33(&m_f, &skewed_lattice), &poscar.scaled_lattice());
+                assert_same_structure(&poscar, &original);
+
+                for (a, b) in zip!(sorted_lengths(&poscar), &expected_lengths) {
+                    assert!((a - b).abs() < 1e-9, "{} != {}", a, b);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn dynamics() {
+        let lattice = mul_33_33(&EXAMPLE_UNIMODULAR, &examples()[0]);
+        let with_dynamics = |dynamics| {
+            let mut raw = poscar_with_lattice(&lattice).into_raw();
+            raw.dynamics = Some(dynamics);
+            raw.validate().unwrap()
+        };
+
+        // uniform flags are fine
+        let mut poscar = with_dynamics(vec![[true; 3], [false; 3]]);
+        poscar.niggli_reduce(1e-5).unwrap();
+        assert_eq!(poscar.0.dynamics, Some(vec![[true; 3], [false; 3]]));
+
+        // mixed flags in direct coordinates cannot be transformed
+        let poscar = with_dynamics(vec![[true, false, true], [false; 3]]);
+        let mut modified = poscar.clone();
+        assert!(modified.niggli_reduce(1e-5).is_err());
+        assert_eq!(format!("{:?}", modified), format!("{:?}", poscar));
+
+        // ...but they can in Cartesian coordinates
+        let mut cart = poscar.to_cart_storage();
+        cart.niggli_reduce(1e-5).unwrap();
+        assert_eq!(cart.0.dynamics, poscar.0.dynamics);
+
+        // ...and under a permutation
+        let mut raw = poscar.into_raw();
+        raw.lattice_vectors = [[0.0, 3.0, 0.0], [2.0, 0.0, 0.0], [0.0, 0.0, 4.0]];
+        let mut poscar = raw.validate().unwrap();
+        let m = poscar.niggli_reduce(1e-5).unwrap();
+        assert_eq!(m, [[0, -1, 0], [-1, 0, 0], [0, 0, -1]]);
+        assert_eq!(poscar.0.dynamics, Some(vec![[false, true, true], [false; 3]]));
+    }
+}
@@ -0,0 +1,353 @@
+// Copyright 2018 Michael Lamparski
+// Part of the vasp-poscar crate.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use crate::{Poscar, Coords, ScaleLine};
+use crate::canonical::wrap_unit;
+use crate::math::{cross_f64, det_f64, det_i32, dot_f64, inv_f64, mul_3_33, mul_33_33, mul_n3_33, norm_3, scale_33};
+use crate::types::FRAC;
+
+/// # Surfaces
+impl Poscar {
+    /// Build a slab exposing the `(h k l)` surface.
+    ///
+    /// The Miller indices refer to the lattice as given (not any conventional cell),
+    /// and are divided by their greatest common divisor.  The first two lattice vectors
+    /// of the slab are a reduced basis of the lattice vectors lying in the plane, and the
+    /// third is along the surface normal, so that `c` is perpendicular to `a` and `b`.
+    /// The Cartesian frame is not rotated.
+    ///
+    /// A *layer* is one period of the bulk along the normal, whose thickness is the
+    /// interplanar spacing `d_hkl`.  The slab contains `num_layers` of them, followed
+    /// by `vacuum` (in scaled units, like the lattice) of empty space along `c`.
+    /// Positions are wrapped into the cell, so that the bottom of the slab is at
+    /// the bottom of the cell.
+    ///
+    /// Sites are grouped by species (merging groups with the same symbol), and each
+    /// group is sorted from bottom to top.  The comment, the kind of scale line, and
+    /// the choice of Cartesian or direct coordinates are preserved, and velocities are
+    /// copied to each image.
+    ///
+    /// When `frozen_layers` is `Some(n)`, selective dynamics are set so that all sites in
+    /// the bottom `n` layers are fixed, and all others are free.  When it is `None`,
+    /// existing selective dynamics are copied to each image.
+    ///
+    /// ```rust
+    /// # fn main() -> Result<(), failure::Error> {Ok({
+    /// use vasp_poscar::{Builder, Coords};
+    ///
+    /// // FCC primitive cell
+    /// let poscar =
+    ///     Builder::new()
+    ///     .lattice_vectors(&[[0.0, 2.0, 2.0], [2.0, 0.0, 2.0], [2.0, 2.0, 0.0]])
+    ///     .site_symbols(vec!["Cu"])
+    ///     .positions(Coords::Frac(vec![[0.0; 3]]))
+    ///     .build()?;
+    ///
+    /// // FCC (111) in these indices is (1 1 1) in the primitive cell as well
+    /// let slab = poscar.slab([1, 1, 1], 4, 10.0, Some(2))?;
+    /// assert_eq!(slab.num_sites(), 4);
+    /// assert_eq!(slab.into_raw().dynamics, Some(vec![
+    ///     [false; 3], [false; 3], [true; 3], [true; 3],
+    /// ]));
+    /// # })}
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Fails if the Miller indices are all zero, if `num_layers` is zero, if `vacuum`
+    /// is negative, or if `frozen_layers` exceeds `num_layers`.  When `frozen_layers`
+    /// is `None`, this also fails if the existing selective dynamics cannot be expressed
+    /// in the new coordinates (see [`niggli_reduce`]).
+    ///
+    /// [`niggli_reduce`]: #method.niggli_reduce
+    pub fn slab(
+        &self,
+        miller: [i32; 3],
+        num_layers: u32,
+        vacuum: f64,
+        frozen_layers: Option<u32>,
+    ) -> Result<Poscar, failure::Error>
+    {
+        ensure!(miller != [0; 3], "Miller indices cannot all be zero");
+        ensure!(num_layers > 0, "a slab must have at least one layer");
+        ensure!(vacuum >= 0.0, "vacuum thickness must be non-negative: {}", vacuum);
+        if let Some(frozen) = frozen_layers {
+            ensure!(frozen <= num_layers, "cannot freeze {} of {} layers", frozen, num_layers);
+        }
+
+        let lattice = self.scaled_lattice();
+        let basis = surface_basis(&lattice, miller);
+        let stacking = [basis[0], basis[1], arr_3![k => basis[2][k] * num_layers as i32]];
+        let (stacked, mapping) = self.supercell(&stacking)?;
+
+        // Replace the third vector with one along the normal, keeping Cartesian positions.
+        let stacked_lattice = stacked.scaled_lattice();
+        let [a, b, c] = stacked_lattice;
+        let normal = {
+            let cross = cross_f64(&a, &b);
+            arr_3![k => cross[k] / norm_3(&cross)]
+        };
+        let thickness = dot_f64(&c, &normal);
+        let height = thickness + vacuum;
+        let new_lattice = [a, b, arr_3![k => normal[k] * height]];
+
+        let carts = {
+            let fracs: Vec<[f64; 3]> = {
+                stacked.frac_positions().iter()
+                    .map(|x| arr_3![k => wrap_unit(x[k], 1e-8)])
+                    .collect()
+            };
+            mul_n3_33(&fracs, &stacked_lattice)
+        };
+        let fracs: Vec<[f64; 3]> = {
+            mul_n3_33(&carts, &inv_f64(&new_lattice)).iter()
+                .map(|x| [wrap_unit(x[0], 1e-8), wrap_unit(x[1], 1e-8), x[2].max(0.0)])
+                .collect()
+        };
+        let heights: Vec<f64> = fracs.iter().map(|x| x[2] * height).collect();
+
+        let dynamics = match frozen_layers {
+            Some(frozen) => Some({
+                let layer_thickness = thickness / f64::from(num_layers);
+                heights.iter().map(|&z| {
+                    let layer = (z / layer_thickness + 1e-8).floor();
+                    [layer >= f64::from(frozen); 3]
+                }).collect()
+            }),
+            None => {
+                let frac_matrix = mul_33_33(&lattice, &inv_f64(&new_lattice));
+                self.transformed_dynamics(FRAC, &frac_matrix)?.map(|flags| {
+                    mapping.iter().map(|&(site, _)| flags[site]).collect()
+                })
+            },
+        };
+
+        let tag = self.0.positions.tag();
+        let unscaled_lattice = scale_33(&new_lattice, 1.0 / self.effective_scale_factor()).0;
+        let mut raw = stacked.clone().into_raw();
+        raw.lattice_vectors = unscaled_lattice;
+        if let ScaleLine::Volume(_) = raw.scale {
+            raw.scale = ScaleLine::Volume(det_f64(&new_lattice).abs());
+        }
+        raw.positions = Coords::Frac(fracs).into_tag(&unscaled_lattice, tag);
+        raw.velocities = stacked.cart_velocities().map(|carts| {
+            let velocity_tag = stacked.0.velocities.as_ref().expect("(BUG) velocities").tag();
+            Coords::Cart(carts.into_owned()).into_tag(&unscaled_lattice, velocity_tag)
+        });
+        raw.dynamics = dynamics;
+        let mut poscar = raw.validate()?;
+
+        let types = poscar.site_type_indices();
+        let mut perm: Vec<usize> = (0..poscar.num_sites()).collect();
+        perm.sort_by(|&i, &j| {
+            types[i].cmp(&types[j])
+                .then_with(|| heights[i].partial_cmp(&heights[j]).expect("(BUG) NaN height"))
+        });
+        poscar.permute_sites(&perm);
+        Ok(poscar)
+    }
+}
+
+/// Find a unimodular matrix (with determinant `+1`) whose first two rows are a
+/// reduced basis for the lattice vectors in the `(h k l)` plane, and whose third
+/// row `r` satisfies `r . hkl = 1` after dividing `hkl` by its GCD.
+///
+/// The third row is chosen to be as close to the normal as possible.
+pub(crate) fn surface_basis(lattice: &[[f64; 3]; 3], miller: [i32; 3]) -> [[i32; 3]; 3]
+{
+    // Integer row operations on the column vector `v`, tracked in `m` so that
+    // `m * miller = v` always holds.  This is Euclid's algorithm.
+    let mut v = arr_3![k => i64::from(miller[k])];
+    let mut m = [[1i64, 0, 0], [0, 1, 0], [0, 0, 1]];
+    loop {
+        let nonzero: Vec<usize> = (0..3).filter(|&k| v[k] != 0).collect();
+        let pivot = *nonzero.iter().min_by_key(|&&k| v[k].abs()).expect("(BUG) zero miller");
+        if nonzero.len() == 1 {
+            m.swap(pivot, 2);
+            v.swap(pivot, 2);
+            break;
+        }
+        for &k in &nonzero {
+            if k != pivot {
+                let q = v[k].div_euclid(v[pivot]);
+                v[k] -= q * v[pivot];
+                m[k] = arr_3![i => m[k][i] - q * m[pivot][i]];
+            }
+        }
+    }
+    // v[2] is now the GCD, up to sign
+    if v[2] < 0 {
+        m[2] = arr_3![i => -m[2][i]];
+    }
+
+    let cart = |u: &[i64; 3]| mul_3_33(&arr_3![k => u[k] as f64], lattice);
+    let dot = |u: &[i64; 3], w: &[i64; 3]| dot_f64(&cart(u), &cart(w));
+    let add_multiple = |u: &[i64; 3], q: i64, w: &[i64; 3]| arr_3![k => u[k] + q * w[k]];
+
+    // Lagrange reduction of the in-plane basis
+    let [mut a, mut b, mut c] = m;
+    loop {
+        if dot(&b, &b) < dot(&a, &a) {
+            std::mem::swap(&mut a, &mut b);
+        }
+        let q = (dot(&a, &b) / dot(&a, &a)).round() as i64;
+        let shorter = add_multiple(&b, -q, &a);
+        // stop once no progress is made (ties can otherwise cycle)
+        if q == 0 || dot(&shorter, &shorter) >= dot(&b, &b) {
+            break;
+        }
+        b = shorter;
+    }
+
+    // remove the in-plane part of the third vector, as far as possible
+    let (aa, ab, bb) = (dot(&a, &a), dot(&a, &b), dot(&b, &b));
+    let (ac, bc) = (dot(&a, &c), dot(&b, &c));
+    let det = aa * bb - ab * ab;
+    let x = ((bb * ac - ab * bc) / det).round() as i64;
+    let y = ((aa * bc - ab * ac) / det).round() as i64;
+    c = add_multiple(&add_multiple(&c, -x, &a), -y, &b);
+
+    let mut out = [a, b, c].map(|row| arr_3![k => row[k] as i32]);
+    if det_i32(&out) < 0 {
+        out[1] = arr_3![k => -out[1][k]];
+    }
+    out
+}
+
+#[cfg(test)]
+#[deny(unused)]
+mod tests {
+    use super::*;
+    use crate::Builder;
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{} != {}", a, b);
+    }
+
+    fn fcc() -> Poscar {
+        Builder::new()
+            .lattice_vectors(&[[0.0, 2.0, 2.0], [2.0, 0.0, 2.0], [2.0, 2.0, 0.0]])
+            .site_symbols(vec!["Cu"])
+            .positions(Coords::Frac(vec![[0.0; 3]]))
+            .build().unwrap()
+    }
+
+    fn cscl() -> Poscar {
+        Builder::new()
+            .lattice_vectors(&[[3.0, 0.0, 0.0], [0.0, 3.0, 0.0], [0.0, 0.0, 3.0]])
+            .site_symbols(vec!["Cs", "Cl"])
+            .positions(Coords::Cart(vec![[0.0; 3], [1.5; 3]]))
+            .velocities(Coords::Cart(vec![[0.1, 0.2, 0.3], [0.0, 0.0, -0.1]]))
+            .build().unwrap()
+    }
+
+    #[test]
+    fn basis() {
+        let lattice = fcc().scaled_lattice();
+        for &miller in &[[1, 0, 0], [1, 1, 1], [2, -1, 3], [0, 0, -1], [4, 6, 0], [1, 1, 0]] {
+            let basis = surface_basis(&lattice, miller);
+            assert_eq!(det_i32(&basis), 1);
+            let g = miller.iter().fold(0, |g, &x| gcd(g, x.abs()));
+            let dots: Vec<i32> = basis.iter().map(|row| (0..3).map(|k| row[k] * miller[k]).sum()).collect();
+            assert_eq!(dots, vec![0, 0, g]);
+        }
+    }
+
+    fn gcd(a: i32, b: i32) -> i32 {
+        if b == 0 { a } else { gcd(b, a % b) }
+    }
+
+    #[test]
+    fn fcc_111() {
+        let slab = fcc().slab([1, 1, 1], 4, 10.0, None).unwrap();
+        assert_eq!(slab.num_sites(), 4);
+
+        let [a, b, c] = slab.scaled_lattice();
+        let spacing = 4.0 / 3f64.sqrt();
+        assert_close(norm_3(&a), 8f64.sqrt());
+        assert_close(norm_3(&b), 8f64.sqrt());
+        assert_close(dot_f64(&a, &c), 0.0);
+        assert_close(dot_f64(&b, &c), 0.0);
+        assert_close(norm_3(&c), 4.0 * spacing + 10.0);
+        assert!(det_f64(&slab.scaled_lattice()) > 0.0);
+
+        // one site per layer, sorted from the bottom
+        let fracs = slab.frac_positions();
+        for (i, x) in fracs.iter().enumerate() {
+            assert_close(x[2] * norm_3(&c), i as f64 * spacing);
+            assert!(x.iter().all(|x| (0.0..1.0).contains(x)));
+        }
+
+        // the Miller indices are reduced
+        let again = fcc().slab([2, 2, 2], 4, 10.0, None).unwrap();
+        assert_eq!(format!("{:?}", again), format!("{:?}", slab));
+    }
+
+    #[test]
+    fn species_and_dynamics() {
+        let mut raw = cscl().into_raw();
+        raw.scale = ScaleLine::Volume(27.0);
+        let poscar = raw.validate().unwrap();
+
+        let slab = poscar.slab([1, 1, 0], 3, 5.0, Some(1)).unwrap();
+        let spacing = 3.0 / 2f64.sqrt();
+        assert_eq!(slab.group_symbols().unwrap().collect::<Vec<_>>(), vec!["Cs", "Cl"]);
+        assert_eq!(slab.group_counts().collect::<Vec<_>>(), vec![3, 3]);
+        assert_close(slab.scaled_volume(), 9f64.sqrt() * 18f64.sqrt() * (3.0 * spacing + 5.0));
+        assert_eq!(slab.0.positions.tag(), Coords::Cart(()));
+        assert_eq!(slab.0.dynamics, Some(vec![
+            [false; 3], [true; 3], [true; 3],
+            [false; 3], [true; 3], [true; 3],
+        ]));
+        // Cartesian velocities are unaffected by the Cartesian frame being kept
+        assert_eq!(slab.cart_velocities().unwrap()[0], [0.1, 0.2, 0.3]);
+        assert_eq!(slab.cart_velocities().unwrap()[5], [0.0, 0.0, -0.1]);
+
+        // existing flags are copied when not overridden
+        let mut raw = cscl().into_raw();
+        raw.dynamics = Some(vec![[true, false, true], [false; 3]]);
+        let slab = raw.validate().unwrap().slab([0, 0, 1], 2, 5.0, None).unwrap();
+        assert_eq!(slab.0.dynamics, Some(vec![
+            [true, false, true], [true, false, true],
+            [false; 3], [false; 3],
+        ]));
+    }
+
+    #[test]
+    fn grouping() {
+        let poscar = {
+            Builder::new()
+                .lattice_vectors(&[[3.0, 0.0, 0.0], [0.0, 3.0, 0.0], [0.0, 0.0, 3.0]])
+                .site_symbols(vec!["Na", "Cl", "Na"])
+                .positions(Coords::Frac(vec![[0.0; 3], [0.5; 3], [0.5, 0.5, 0.0]]))
+                .build().unwrap()
+        };
+        let slab = poscar.slab([0, 0, 1], 2, 0.0, None).unwrap();
+        assert_eq!(slab.group_symbols().unwrap().collect::<Vec<_>>(), vec!["Na", "Cl"]);
+        assert_eq!(slab.group_counts().collect::<Vec<_>>(), vec![4, 2]);
+        assert_close(slab.scaled_volume(), 54.0);
+    }
+
+    #[test]
+    fn errors() {
+        let poscar = cscl();
+        assert!(poscar.slab([0, 0, 0], 2, 5.0, None).is_err());
+        assert!(poscar.slab([1, 0, 0], 0, 5.0, None).is_err());
+        assert!(poscar.slab([1, 0, 0], 2, -1.0, None).is_err());
+        assert!(poscar.slab([1, 0, 0], 2, 5.0, Some(3)).is_err());
+        assert!(poscar.slab([1, 0, 0], 2, 5.0, Some(2)).is_ok());
+
+        // flags along the lattice can't survive a change of axes
+        let mut raw = poscar.to_frac_storage().into_raw();
+        raw.dynamics = Some(vec![[true, false, true], [false; 3]]);
+        let poscar = raw.validate().unwrap();
+        assert!(poscar.slab([1, 1, 0], 2, 5.0, None).is_err());
+        assert!(poscar.slab([1, 1, 0], 2, 5.0, Some(0)).is_ok());
+    }
+}
@@ -0,0 +1,271 @@
+// Copyright 2018 Michael Lamparski
+// Part of the vasp-poscar crate.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use crate::{Poscar, Coords, ScaleLine};
+use crate::math::{det_f64, mul_3_33, mul_33_33, transpose_33};
+use crate::types::CART;
+
+/// A strained structure produced by [`Poscar::elastic_strain_set`].
+///
+/// [`Poscar::elastic_strain_set`]: struct.Poscar.html#method.elastic_strain_set
+#[derive(Debug, Clone)]
+pub struct StrainedPoscar {
+    /// A label such as `"e4+0.0100"`, built from the Voigt component
+    /// (numbered from 1) and the signed magnitude, written to four decimal places.
+    pub label: String,
+    /// The Voigt index of the strained component, from `0` to `5`
+    /// (in the order `xx, yy, zz, yz, xz, xy`).
+    pub voigt_index: usize,
+    /// The signed magnitude of the strain, in Voigt notation.
+    ///
+    /// For the shear components, this is the engineering strain, equal to
+    /// twice the corresponding off-diagonal element of [`strain`].
+    ///
+    /// [`strain`]: #structfield.strain
+    pub magnitude: f64,
+    /// The (symmetric) strain tensor that was applied.
+    pub strain: [[f64; 3]; 3],
+    /// The strained structure.
+    pub poscar: Poscar,
+}
+
+/// # Strain
+impl Poscar {
+    /// Deform the structure by a strain tensor.
+    ///
+    /// Each Cartesian vector `v` (as a column vector) is mapped to `(I + strain) * v`.
+    /// Since vectors are stored as rows, this is `v * transpose(I + strain)` in terms
+    /// of the stored data.  The strain need not be symmetric; an antisymmetric part
+    /// describes an infinitesimal rotation.
+    ///
+    /// The lattice vectors, Cartesian positions, and Cartesian velocities are deformed,
+    /// while direct coordinates are unchanged.  For a `ScaleLine::Volume`, the volume
+    /// is multiplied by `det(I + strain)`; otherwise the scale factor is unchanged.
+    ///
+    /// ```rust
+    /// # fn main() -> Result<(), failure::Error> {Ok({
+    /// use vasp_poscar::{Builder, Coords};
+    ///
+    /// let mut poscar =
+    ///     Builder::new()
+    ///     .lattice_vectors(&[[2.0, 0.0, 0.0], [0.0, 2.0, 0.0], [0.0, 0.0, 2.0]])
+    ///     .positions(Coords::Cart(vec![[1.0, 1.0, 0.0]]))
+    ///     .build()?;
+    ///
+    /// poscar.apply_strain(&[[0.5, 0.0, 0.0], [0.0, 0.0, 0.0], [0.0, 0.0, 0.0]])?;
+    /// assert_eq!(poscar.unscaled_lattice_vectors()[0], [3.0, 0.0, 0.0]);
+    /// assert_eq!(poscar.unscaled_cart_positions(), vec![[1.5, 1.0, 0.0]]);
+    /// # })}
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Fails (leaving the Poscar unchanged) if `det(I + strain)` is not positive, or if
+    /// the deformation would mix Cartesian components that have different selective
+    /// dynamics flags.
+    pub fn apply_strain(&mut self, strain: &[[f64; 3]; 3]) -> Result<(), failure::Error>
+    {
+        let deformation = mat_3!((r, c) => strain[r][c] + if r == c { 1.0 } else { 0.0 });
+        let det = det_f64(&deformation);
+        ensure!(det > 0.0, "strain would invert or collapse the cell: {:?}", strain);
+
+        // row vectors transform by the transpose
+        let matrix = transpose_33(&deformation);
+        let dynamics = self.transformed_dynamics(CART, &matrix)?;
+
+        let deform_coords = |coords: &mut Coords| {
+            if let Coords::Cart(v) = coords {
+                *v = v.iter().map(|x| mul_3_33(x, &matrix)).collect();
+            }
+        };
+        deform_coords(&mut self.0.positions);
+        if let Some(velocities) = self.0.velocities.as_mut() {
+            deform_coords(velocities);
+        }
+        self.0.lattice_vectors = mul_33_33(&self.0.lattice_vectors, &matrix);
+        if let ScaleLine::Volume(ref mut volume) = self.0.scale {
+            *volume *= det;
+        }
+        self.0.dynamics = dynamics;
+        Ok(())
+    }
+
+    /// Generate the strained structures used to fit elastic constants by finite differences.
+    ///
+    /// For each of the six Voigt components (`xx, yy, zz, yz, xz, xy`), and for each
+    /// value `m` in `magnitudes`, this applies a strain of `-m` and `+m` in that component
+    /// alone (see [`apply_strain`]).  Shear components are given as engineering strains,
+    /// so a magnitude `m` places `m / 2` in both off-diagonal elements.
+    ///
+    /// The output lists the components in Voigt order; within each component, the
+    /// negative strains come first (largest first), followed by the positive strains
+    /// (smallest first).  Each structure's comment has the label appended to it.
+    /// Magnitudes that agree to four decimal places will produce duplicate labels.
+    ///
+    /// ```rust
+    /// # fn main() -> Result<(), failure::Error> {Ok({
+    /// use vasp_poscar::{Builder, Coords};
+    ///
+    /// let poscar =
+    ///     Builder::new()
+    ///     .comment("Cu")
+    ///     .lattice_vectors(&[[0.0, 1.8, 1.8], [1.8, 0.0, 1.8], [1.8, 1.8, 0.0]])
+    ///     .positions(Coords::Frac(vec![[0.0; 3]]))
+    ///     .build()?;
+    ///
+    /// let set = poscar.elastic_strain_set(&[0.005, 0.01])?;
+    /// assert_eq!(set.len(), 24);
+    /// assert_eq!(set[0].label, "e1-0.0100");
+    /// assert_eq!(set[3].label, "e1+0.0100");
+    /// assert_eq!(set[3].poscar.comment(), "Cu e1+0.0100");
+    /// # })}
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Fails if any magnitude is not positive and finite, if the magnitudes are not
+    /// distinct, or if a strain cannot be applied (see [`apply_strain`]).
+    ///
+    /// [`apply_strain`]: #method.apply_strain
+    pub fn elastic_strain_set(&self, magnitudes: &[f64]) -> Result<Vec<StrainedPoscar>, failure::Error>
+    {
+        for &m in magnitudes {
+            ensure!(m.is_finite() && m > 0.0, "strain magnitudes must be positive: {}", m);
+        }
+        let mut sorted = magnitudes.to_vec();
+        sorted.sort_by(|a, b| a.partial_cmp(b).expect("(BUG) NaN"));
+        sorted.dedup();
+        ensure!(sorted.len() == magnitudes.len(), "strain magnitudes must be distinct: {:?}", magnitudes);
+
+        let signed: Vec<f64> = {
+            sorted.iter().rev().map(|&m| -m)
+                .chain(sorted.iter().cloned())
+                .collect()
+        };
+
+        let mut out = vec![];
+        for voigt_index in 0..6 {
+            for &magnitude in &signed {
+                let strain = voigt_strain(voigt_index, magnitude);
+                let label = format!("e{}{:+.4}", voigt_index + 1, magnitude);
+                let mut poscar = self.clone();
+                poscar.apply_strain(&strain)?;
+                poscar.0.comment = match poscar.0.comment.is_empty() {
+                    true => label.clone(),
+                    false => format!("{} {}", poscar.0.comment, label),
+                };
+                out.push(StrainedPoscar { label, voigt_index, magnitude, strain, poscar });
+            }
+        }
+        Ok(out)
+    }
+}
+
+// (the six Voigt components, as (row, column) pairs)
+const VOIGT_PAIRS: [(usize, usize); 6] = [(0, 0), (1, 1), (2, 2), (1, 2), (0, 2), (0, 1)];
+
+fn voigt_strain(index: usize, magnitude: f64) -> [[f64; 3]; 3]
+{
+    let mut strain = [[0.0; 3]; 3];
+    let (r, c) = VOIGT_PAIRS[index];
+    if r == c {
+        strain[r][c] = magnitude;
+    } else {
+        strain[r][c] = 0.5 * magnitude;
+        strain[c][r] = 0.5 * magnitude;
+    }
+    strain
+}
+
+#[cfg(test)]
+#[deny(unused)]
+mod tests {
+    use super::*;
+    use crate::util::test_structures::oblique_pair;
+
+    fn assert_close(a: &[f64], b: &[f64]) {
+        for (a, b) in zip!(a, b) {
+            assert!((a - b).abs() < 1e-12, "{:?} != {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn apply_strain() {
+        let strain = [[0.01, 0.002, -0.003], [0.002, -0.02, 0.004], [-0.003, 0.004, 0.03]];
+        let deform = |v: &[f64; 3]| arr_3![r => v[r] + (0..3).map(|c| strain[r][c] * v[c]).sum::<f64>()];
+
+        for original in [oblique_pair(), oblique_pair().to_frac_storage(), oblique_pair().to_cart_storage()] {
+            let mut raw = original.clone().into_raw();
+            raw.scale = ScaleLine::Volume(det_f64(&raw.lattice_vectors).abs() * 8.0);
+            let original_volume = raw.validate().unwrap();
+
+            for original in [original, original_volume] {
+                let mut poscar = original.clone();
+                poscar.apply_strain(&strain).unwrap();
+
+                for (a, b) in zip!(poscar.scaled_lattice().iter(), original.scaled_lattice().iter()) {
+                    assert_close(a, &deform(b));
+                }
+                for (a, b) in zip!(poscar.scaled_cart_positions().iter(), original.scaled_cart_positions().iter()) {
+                    assert_close(a, &deform(b));
+                }
+                for (a, b) in zip!(poscar.cart_velocities().unwrap().iter(), original.cart_velocities().unwrap().iter()) {
+                    assert_close(a, &deform(b));
+                }
+                for (a, b) in zip!(poscar.frac_positions().iter(), original.frac_positions().iter()) {
+                    assert_close(a, b);
+                }
+                assert_eq!(poscar.0.positions.tag(), original.0.positions.tag());
+            }
+        }
+    }
+
+    #[test]
+    fn strain_errors() {
+        let mut poscar = oblique_pair();
+        assert!(poscar.apply_strain(&[[-1.0, 0.0, 0.0], [0.0, 0.0, 0.0], [0.0, 0.0, 0.0]]).is_err());
+        assert!(poscar.elastic_strain_set(&[0.01, 0.0]).is_err());
+        assert!(poscar.elastic_strain_set(&[0.01, 0.01]).is_err());
+
+        // Cartesian flags can only survive a diagonal strain
+        let mut raw = poscar.into_raw();
+        raw.dynamics = Some(vec![[true, false, true], [true; 3]]);
+        let poscar = raw.validate().unwrap();
+        let shear = voigt_strain(5, 0.01);
+        assert!(poscar.clone().apply_strain(&shear).is_err());
+        assert!(poscar.to_frac_storage().apply_strain(&shear).is_ok());
+        assert!(poscar.clone().apply_strain(&voigt_strain(0, 0.01)).is_ok());
+    }
+
+    #[test]
+    fn strain_set() {
+        let poscar = oblique_pair();
+        let set = poscar.elastic_strain_set(&[0.01, 0.005]).unwrap();
+        let labels: Vec<&str> = set.iter().map(|s| &s.label[..]).collect();
+        assert_eq!(&labels[..4], &["e1-0.0100", "e1-0.0050", "e1+0.0050", "e1+0.0100"]);
+        assert_eq!(labels[23], "e6+0.0100");
+
+        let mut sorted = labels.clone();
+        sorted.sort();
+        sorted.dedup();
+        assert_eq!(sorted.len(), 24);
+
+        for strained in &set {
+            let (r, c) = VOIGT_PAIRS[strained.voigt_index];
+            let engineering = if r == c { 1.0 } else { 2.0 };
+            assert_eq!(strained.strain[r][c] * engineering, strained.magnitude);
+            assert_eq!(strained.strain[c][r], strained.strain[r][c]);
+
+            let mut expected = poscar.clone();
+            expected.apply_strain(&strained.strain).unwrap();
+            assert_eq!(expected.0.lattice_vectors, strained.poscar.0.lattice_vectors);
+            assert_eq!(strained.poscar.comment(), format!("{} {}", poscar.comment(), strained.label));
+        }
+    }
+}
@@ -0,0 +1,531 @@
+// Copyright 2018 Michael Lamparski
+// Part of the vasp-poscar crate.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use crate::{Poscar, Coords, ScaleLine};
+use crate::math::{det_i32, adj_i32, mul_3_33, mul_33_33, cross_f64, det_f64, dot_f64, norm_3};
+
+use std::collections::BTreeMap;
+
+/// Options for [`Poscar::find_supercells`].
+///
+/// [`Poscar::find_supercells`]: struct.Poscar.html#method.find_supercells
+#[derive(Debug, Clone, PartialEq)]
+pub struct SupercellSearchOptions {
+    /// Largest absolute value allowed for any entry of the matrix.
+    ///
+    /// The search visits `(2 * max_entry + 1)^6` pairs of rows, so this should be
+    /// kept small.  Defaults to `2`.
+    pub max_entry: i32,
+
+    /// How candidates are ranked.
+    ///
+    /// Defaults to [`SupercellCriterion::InscribedRadius`].
+    ///
+    /// [`SupercellCriterion::InscribedRadius`]: enum.SupercellCriterion.html#variant.InscribedRadius
+    pub criterion: SupercellCriterion,
+
+    /// Maximum number of candidates to return.
+    ///
+    /// Defaults to `5`.
+    pub max_results: usize,
+}
+
+impl Default for SupercellSearchOptions {
+    fn default() -> Self
+    { SupercellSearchOptions {
+        max_entry: 2,
+        criterion: SupercellCriterion::InscribedRadius,
+        max_results: 5,
+    }}
+}
+
+impl SupercellSearchOptions {
+    /// Alias for [`Default`]`::default`.
+    ///
+    /// [`Default`]: https://doc.rust-lang.org/std/default/trait.Default.html
+    pub fn new() -> Self
+    { Default::default() }
+}
+
+/// A figure of merit for ranking supercells.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SupercellCriterion {
+    /// Prefer the largest [`inscribed_radius`].
+    ///
+    /// [`inscribed_radius`]: struct.SupercellCandidate.html#structfield.inscribed_radius
+    InscribedRadius,
+    /// Prefer the smallest [`cube_deviation`].
+    ///
+    /// [`cube_deviation`]: struct.SupercellCandidate.html#structfield.cube_deviation
+    CubeDeviation,
+}
+
+/// A supercell found by [`Poscar::find_supercells`].
+///
+/// [`Poscar::find_supercells`]: struct.Poscar.html#method.find_supercells
+#[derive(Debug, Clone, PartialEq)]
+pub struct SupercellCandidate {
+    /// The matrix to supply to [`Poscar::supercell`].  Its determinant is positive.
+    ///
+    /// [`Poscar::supercell`]: struct.Poscar.html#method.supercell
+    pub matrix: [[i32; 3]; 3],
+    /// Radius of the largest sphere that fits inside the supercell;
+    /// i.e. half of the smallest distance between opposite faces.
+    ///
+    /// Twice this is a lower bound on the distance between a site and its
+    /// nearest periodic image.
+    pub inscribed_radius: f64,
+    /// Frobenius norm of `G / V^(2/3) - I`, where `G` is the metric tensor
+    /// of the supercell and `V` is its volume.  This is zero for a cube.
+    pub cube_deviation: f64,
+}
+
+/// # Supercells
+impl Poscar {
+    /// Search for supercells with a given number of sites that are as close
+    /// as possible to being cubic.
+    ///
+    /// All integer matrices with entries no larger than `options.max_entry` (in absolute
+    /// value) and with a determinant of `num_sites / self.num_sites()` are scored
+    /// according to `options.criterion`, using the scaled lattice.  Many of these matrices
+    /// describe the same supercell lattice in different bases; only the best basis of
+    /// each lattice is kept.  The result is sorted from best to worst, with ties broken
+    /// in favor of matrices with smaller entries.
+    ///
+    /// ```rust
+    /// # fn main() -> Result<(), failure::Error> {Ok({
+    /// use vasp_poscar::{Builder, Coords, SupercellSearchOptions};
+    ///
+    /// // FCC primitive cell
+    /// let poscar =
+    ///     Builder::new()
+    ///     .lattice_vectors(&[[0.0, 0.5, 0.5], [0.5, 0.0, 0.5], [0.5, 0.5, 0.0]])
+    ///     .positions(Coords::Frac(vec![[0.0; 3]]))
+    ///     .build()?;
+    ///
+    /// // the conventional cell is the best 4-site supercell
+    /// let best = &poscar.find_supercells(4, &SupercellSearchOptions::new())?[0];
+    /// assert_eq!(best.cube_deviation.round(), 0.0);
+    ///
+    /// let (supercell, _) = poscar.supercell(&best.matrix)?;
+    /// assert_eq!(supercell.num_sites(), 4);
+    /// # })}
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Fails if `num_sites` is not a positive multiple of the number of sites
+    /// in the structure, or if `options.max_entry` is not positive.
+    pub fn find_supercells(
+        &self,
+        num_sites: usize,
+        options: &SupercellSearchOptions,
+    ) -> Result<Vec<SupercellCandidate>, failure::Error>
+    {
+        let sites_per_cell = self.num_sites();
+        ensure!(
+            num_sites > 0 && num_sites.is_multiple_of(sites_per_cell),
+            "{} sites is not a multiple of the {} sites in the unit cell", num_sites, sites_per_cell,
+        );
+        ensure!(options.max_entry > 0, "max_entry must be positive");
+        let det = (num_sites / sites_per_cell) as i64;
+        let lattice = self.scaled_lattice();
+
+        let bound = options.max_entry;
+        let rows: Vec<[i32; 3]> = {
+            let range = || -bound..=bound;
+            range()
+                .flat_map(|a| range().flat_map(move |b| range().map(move |c| [a, b, c])))
+                .collect()
+        };
+
+        // best candidate for each distinct supercell lattice, keyed by Hermite normal form
+        let mut best = BTreeMap::new();
+        for &r0 in &rows {
+            for &r1 in &rows {
+                let cross = arr_3![k => {
+                    let (i, j) = ((k + 1) % 3, (k + 2) % 3);
+                    i64::from(r0[i]) * i64::from(r1[j]) - i64::from(r0[j]) * i64::from(r1[i])
+                }];
+                if cross == [0; 3] {
+                    continue;
+                }
+                for &r2 in &rows {
+                    if (0..3).map(|k| i64::from(r2[k]) * cross[k]).sum::<i64>() != det {
+                        continue;
+                    }
+                    let matrix = [r0, r1, r2];
+                    let candidate = score_supercell(&lattice, matrix);
+                    let key = hermite_normal_form(&matrix);
+                    let replace = match best.get(&key) {
+                        None => true,
+                        Some(old) => compare_candidates(&candidate, old, options.criterion).is_lt(),
+                    };
+                    if replace {
+                        best.insert(key, candidate);
+                    }
+                }
+            }
+        }
+
+        let mut out: Vec<_> = best.into_values().collect();
+        out.sort_by(|a, b| compare_candidates(a, b, options.criterion));
+        out.truncate(options.max_results);
+        Ok(out)
+    }
+
+    /// Construct a supercell.
+    ///
+    /// The rows of `matrix` express the new lattice vectors as integer combinations
+    /// of the old ones; i.e. new lattice vector `i` is `sum_j matrix[i][j] * old[j]`.
+    /// The matrix need not be diagonal.  The unit cell is replicated once for each
+    /// of the `|det(matrix)|` lattice points that lie inside the new cell.
+    ///
+    /// Sites are ordered so that all images of site 0 come first, followed by
+    /// all images of site 1, and so on.  Thus, each group of the original is
+    /// simply multiplied in size, and the symbols are unchanged.  Velocities and
+    /// selective dynamics flags are copied to each image.  The kind of scale line
+    /// and the choice of Cartesian or direct coordinates is preserved.
+    ///
+    /// Also returned is a list which, for each site in the supercell, gives the index
+    /// of the original site, and the lattice translation (in units of the original
+    /// lattice vectors) that was applied to it.
+    ///
+    /// ```rust
+    /// # fn main() -> Result<(), failure::Error> {Ok({
+    /// use vasp_poscar::{Builder, Coords};
+    ///
+    /// let poscar =
+    ///     Builder::new()
+    ///     .dummy_lattice_vectors()
+    ///     .site_symbols(vec!["Na", "Cl"])
+    ///     .positions(Coords::Frac(vec![[0.0, 0.0, 0.0], [0.5, 0.5, 0.5]]))
+    ///     .build()?;
+    ///
+    /// let (supercell, mapping) = poscar.supercell(&[[1, 1, 0], [-1, 1, 0], [0, 0, 1]])?;
+    /// assert_eq!(supercell.group_counts().collect::<Vec<_>>(), vec![2, 2]);
+    /// assert_eq!(supercell.scaled_volume(), 2.0);
+    /// assert_eq!(mapping, vec![
+    ///     (0, [0, 0, 0]), (0, [0, 1, 0]),
+    ///     (1, [0, 0, 0]), (1, [0, 1, 0]),
+    /// ]);
+    /// # })}
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Fails if the matrix is singular.
+    #[allow(clippy::type_complexity)]
+    pub fn supercell(&self, matrix: &[[i32; 3]; 3]) -> Result<(Poscar, Vec<(usize, [i32; 3])>), failure::Error>
+    {
+        let det = det_i32(matrix);
+        ensure!(det != 0, "supercell matrix is singular: {:?}", matrix);
+
+        let translations = lattice_points_in_supercell(matrix);
+        assert_eq!(translations.len() as i64, det.abs(), "(BUG) wrong number of lattice points");
+
+        let matrix_f = mat_3!((r, c) => f64::from(matrix[r][c]));
+        let adj = adj_i32(matrix);
+        let adj_f = mat_3!((r, c) => adj[r][c] as f64);
+        let det_f = det as f64;
+
+        let old_lattice = self.unscaled_lattice();
+        let new_lattice = mul_33_33(&matrix_f, &old_lattice);
+
+        let mapping: Vec<(usize, [i32; 3])> = {
+            (0..self.num_sites())
+                .flat_map(|site| translations.iter().map(move |&t| (site, t)))
+                .collect()
+        };
+        let replicate = |data: &[[f64; 3]], f: &dyn Fn(&[f64; 3], [f64; 3]) -> [f64; 3]| -> Vec<[f64; 3]> {
+            mapping.iter()
+                .map(|&(site, t)| f(&data[site], arr_3![k => f64::from(t[k])]))
+                .collect()
+        };
+
+        let positions = match self.0.positions {
+            Coords::Frac(ref fracs) => Coords::Frac(replicate(fracs, &|x, t| {
+                let shifted = arr_3![k => x[k] + t[k]];
+                arr_3![k => mul_3_33(&shifted, &adj_f)[k] / det_f]
+            })),
+            Coords::Cart(ref carts) => Coords::Cart(replicate(carts, &|x, t| {
+                let shift = mul_3_33(&t, &old_lattice);
+                arr_3![k => x[k] + shift[k]]
+            })),
+        };
+
+        let velocities = self.cart_velocities().map(|carts| {
+            let tag = self.0.velocities.as_ref().expect("(BUG) velocities").tag();
+            Coords::Cart(replicate(&carts, &|v, _| *v)).into_tag(&new_lattice, tag)
+        });
+
+        let dynamics = self.0.dynamics.as_ref().map(|dynamics| {
+            mapping.iter().map(|&(site, _)| dynamics[site]).collect()
+        });
+
+        let n = translations.len();
+        let mut raw = self.0.clone();
+        raw.lattice_vectors = new_lattice;
+        raw.scale = match raw.scale {
+            ScaleLine::Factor(f) => ScaleLine::Factor(f),
+            ScaleLine::Volume(v) => ScaleLine::Volume(v * n as f64),
+        };
+        raw.group_counts = raw.group_counts.iter().map(|c| c * n).collect();
+        raw.positions = positions;
+        raw.velocities = velocities;
+        raw.dynamics = dynamics;
+        Ok((Poscar(raw), mapping))
+    }
+}
+
+fn score_supercell(lattice: &[[f64; 3]; 3], matrix: [[i32; 3]; 3]) -> SupercellCandidate
+{
+    let matrix_f = mat_3!((r, c) => f64::from(matrix[r][c]));
+    let vecs = mul_33_33(&matrix_f, lattice);
+    let volume = det_f64(&vecs).abs();
+
+    let face_distance = |k: usize| volume / norm_3(&cross_f64(&vecs[(k + 1) % 3], &vecs[(k + 2) % 3]));
+    let inscribed_radius = 0.5 * (0..3).map(face_distance).fold(f64::INFINITY, f64::min);
+
+    let length_sq = volume.powf(2.0 / 3.0);
+    let cube_deviation = {
+        let mut sum = 0.0;
+        for r in 0..3 {
+            for c in 0..3 {
+                let identity = if r == c { 1.0 } else { 0.0 };
+                let x = dot_f64(&vecs[r], &vecs[c]) / length_sq - identity;
+                sum += x * x;
+            }
+        }
+        sum.sqrt()
+    };
+    SupercellCandidate { matrix, inscribed_radius, cube_deviation }
+}
+
+// Orders candidates from best to worst.
+fn compare_candidates(a: &SupercellCandidate, b: &SupercellCandidate, criterion: SupercellCriterion) -> std::cmp::Ordering
+{
+    // scores within roundoff of each other are considered equal
+    let fuzzy_cmp = |a: f64, b: f64| match (a - b).abs() <= 1e-9 * f64::max(a.abs(), b.abs()) {
+        true => std::cmp::Ordering::Equal,
+        false => a.partial_cmp(&b).expect("(BUG) NaN score"),
+    };
+    let score_order = match criterion {
+        SupercellCriterion::InscribedRadius => fuzzy_cmp(b.inscribed_radius, a.inscribed_radius),
+        SupercellCriterion::CubeDeviation => fuzzy_cmp(a.cube_deviation, b.cube_deviation),
+    };
+    let size = |m: &[[i32; 3]; 3]| m.iter().flat_map(|row| row.iter()).map(|x| x.abs()).sum::<i32>();
+    score_order
+        .then_with(|| size(&a.matrix).cmp(&size(&b.matrix)))
+        // prefer positive entries and entries near the diagonal
+        .then_with(|| b.matrix.cmp(&a.matrix))
+}
+
+/// Row-style Hermite normal form of an integer matrix; see `math::hermite_normal_form`.
+pub(crate) fn hermite_normal_form(matrix: &[[i32; 3]; 3]) -> [[i64; 3]; 3]
+{
+    let rows: Vec<[i64; 3]> = matrix.iter().map(|row| arr_3![k => i64::from(row[k])]).collect();
+    crate::math::hermite_normal_form(&rows)
+}
+
+/// Enumerate the translations `t` (integer vectors in units of the original
+/// lattice) for which `t * inv(matrix)` lies in `[0, 1)^3`.
+///
+/// Output is in lexicographic order.
+pub(crate) fn lattice_points_in_supercell(matrix: &[[i32; 3]; 3]) -> Vec<[i32; 3]>
+{
+    let det = det_i32(matrix);
+    let adj = adj_i32(matrix);
+
+    // bounding box of the corners of the supercell
+    let mut lo = [0i64; 3];
+    let mut hi = [0i64; 3];
+    for corner in 0..8 {
+        for k in 0..3 {
+            let x: i64 = {
+                (0..3)
+                    .filter(|&r| corner & (1 << r) != 0)
+                    .map(|r| i64::from(matrix[r][k]))
+                    .sum()
+            };
+            lo[k] = lo[k].min(x);
+            hi[k] = hi[k].max(x);
+        }
+    }
+
+    // Work with integers so that the test for the boundary is exact.
+    let mut out = vec![];
+    for a in lo[0]..=hi[0] {
+        for b in lo[1]..=hi[1] {
+            for c in lo[2]..=hi[2] {
+                let inside = (0..3).all(|k| {
+                    let x = (a * adj[0][k] + b * adj[1][k] + c * adj[2][k]) * det.signum();
+                    0 <= x && x < det.abs()
+                });
+                if inside {
+                    out.push([a as i32, b as i32, c as i32]);
+                }
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+#[deny(unused)]
+mod tests {
+    use super::*;
+    use crate::util::test_structures::skewed_builder;
+    use crate::Builder;
+
+    fn example() -> Poscar {
+        skewed_builder()
+            .scale(ScaleLine::Factor(1.5))
+            .group_symbols(vec!["B", "N"])
+            .group_counts(vec![1, 2])
+            .positions(Coords::Frac(vec![[0.0, 0.0, 0.0], [0.25, 0.5, 0.75], [0.5, 0.5, 0.5]]))
+            .velocities(Coords::Cart(vec![[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]]))
+            .dynamics(vec![[true, false, true], [false, false, false], [true, true, true]])
+            .build().unwrap()
+    }
+
+    #[test]
+    fn lattice_points() {
+        for matrix in [
+            [[2, 0, 0], [0, 3, 0], [0, 0, 1]],
+            [[1, 1, 0], [-1, 1, 0], [0, 0, 1]],
+            [[-1, 1, 1], [1, -1, 1], [1, 1, -1]],
+            [[2, -1, 2], [-1, 3, -3], [1, 1, 0]],
+            [[0, 1, 0], [1, 0, 0], [0, 0, 3]], // negative determinant
+        ] {
+            let points = lattice_points_in_supercell(&matrix);
+            assert_eq!(points.len() as i64, det_i32(&matrix).abs(), "{:?}", matrix);
+
+            // all points are distinct modulo the supercell lattice
+            let adj = adj_i32(&matrix);
+            let det = det_i32(&matrix);
+            let mut reduced: Vec<_> = points.iter().map(|t| {
+                arr_3![k => {
+                    let x: i64 = (0..3).map(|r| i64::from(t[r]) * adj[r][k]).sum();
+                    x.rem_euclid(det.abs())
+                }]
+            }).collect();
+            reduced.sort();
+            reduced.dedup();
+            assert_eq!(reduced.len(), points.len());
+        }
+    }
+
+    #[test]
+    fn structure_is_preserved() {
+        let poscar = example();
+        let matrix = [[1, 1, 0], [-1, 1, 1], [0, 0, 2]];
+        for &storage in &[true, false] {
+            let poscar = match storage {
+                true => poscar.to_cart_storage(),
+                false => poscar.clone(),
+            };
+            for &scale in &[ScaleLine::Factor(1.5), ScaleLine::Volume(27.0)] {
+                let poscar = {
+                    let mut raw = poscar.clone().into_raw();
+                    raw.scale = scale;
+                    raw.validate().unwrap()
+                };
+                let (supercell, mapping) = poscar.supercell(&matrix).unwrap();
+                let n = 4;
+
+                assert_eq!(supercell.num_sites(), 3 * n);
+                assert_eq!(supercell.group_counts().collect::<Vec<_>>(), vec![n, 2 * n]);
+                assert_eq!(supercell.0.positions.tag(), poscar.0.positions.tag());
+                assert!((supercell.scaled_volume() - n as f64 * poscar.scaled_volume()).abs() < 1e-10);
+
+                // every site is at the right place
+                let old_carts = poscar.scaled_cart_positions();
+                let new_carts = supercell.scaled_cart_positions();
+                let lattice = poscar.scaled_lattice_vectors();
+                for (new, &(site, t)) in zip!(&new_carts[..], &mapping) {
+                    let t = arr_3![k => f64::from(t[k])];
+                    let expected = crate::math::add_3(&old_carts[site], &mul_3_33(&t, &lattice));
+                    for k in 0..3 {
+                        assert!((new[k] - expected[k]).abs() < 1e-10);
+                    }
+                }
+
+                // velocities and dynamics are replicated
+                let old_v = poscar.cart_velocities().unwrap();
+                let new_v = supercell.cart_velocities().unwrap();
+                let old_dyn = poscar.0.dynamics.as_ref().unwrap();
+                let new_dyn = supercell.0.dynamics.as_ref().unwrap();
+                for (i, &(site, _)) in mapping.iter().enumerate() {
+                    for k in 0..3 {
+                        assert!((new_v[i][k] - old_v[site][k]).abs() < 1e-10);
+                    }
+                    assert_eq!(new_dyn[i], old_dyn[site]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn hnf() {
+        // different bases of the same lattice
+        let a = [[1, 1, 0], [-1, 1, 0], [0, 0, 1]];
+        let b = [[0, 2, 0], [1, -1, 0], [1, 1, -1]];
+        assert_eq!(hermite_normal_form(&a), hermite_normal_form(&b));
+        assert_eq!(hermite_normal_form(&a), [[1, 1, 0], [0, 2, 0], [0, 0, 1]]);
+
+        let c = [[2, 0, 0], [0, 1, 0], [0, 0, 1]];
+        assert_ne!(hermite_normal_form(&a), hermite_normal_form(&c));
+    }
+
+    #[test]
+    fn search() {
+        // simple cubic with two sites: 8 copies should make a 2x2x2 cube.
+        let poscar =
+            Builder::new()
+            .dummy_lattice_vectors()
+            .group_symbols(vec!["A", "B"])
+            .group_counts(vec![1, 1])
+            .positions(Coords::Frac(vec![[0.0; 3], [0.5; 3]]))
+            .build().unwrap();
+
+        for &criterion in &[SupercellCriterion::InscribedRadius, SupercellCriterion::CubeDeviation] {
+            let options = SupercellSearchOptions { criterion, ..SupercellSearchOptions::new() };
+            let found = poscar.find_supercells(16, &options).unwrap();
+            assert_eq!(found.len(), 5);
+            assert_eq!(found[0].matrix, [[2, 0, 0], [0, 2, 0], [0, 0, 2]]);
+            assert!((found[0].inscribed_radius - 1.0).abs() < 1e-12);
+            assert!(found[0].cube_deviation < 1e-12);
+
+            // results are sorted and describe distinct lattices
+            for pair in found.windows(2) {
+                assert_ne!(compare_candidates(&pair[0], &pair[1], criterion), std::cmp::Ordering::Greater);
+                assert_ne!(hermite_normal_form(&pair[0].matrix), hermite_normal_form(&pair[1].matrix));
+            }
+            for candidate in &found {
+                let (supercell, _) = poscar.supercell(&candidate.matrix).unwrap();
+                assert_eq!(supercell.group_counts().collect::<Vec<_>>(), vec![8, 8]);
+            }
+        }
+    }
+
+    #[test]
+    fn search_errors() {
+        let poscar = example();
+        assert!(poscar.find_supercells(4, &SupercellSearchOptions::new()).is_err());
+        assert!(poscar.find_supercells(0, &SupercellSearchOptions::new()).is_err());
+        let options = SupercellSearchOptions { max_entry: 0, ..SupercellSearchOptions::new() };
+        assert!(poscar.find_supercells(6, &options).is_err());
+    }
+
+    #[test]
+    fn singular() {
+        assert!(example().supercell(&[[1, 0, 0], [2, 0, 0], [0, 0, 1]]).is_err());
+    }
+}
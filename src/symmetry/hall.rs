@@ -0,0 +1,577 @@
+// Copyright 2018 Michael Lamparski
+// Part of the vasp-poscar crate.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Hall symbols, and the groups they describe.
+//!
+//! Reference: S. R. Hall, "Space-group notation with an explicit origin",
+//! Acta Cryst. A37, 517-525 (1981), and the corrections in
+//! International Tables for Crystallography Vol. B, Sec. 1.4.
+
+use crate::math::mul_i32_33;
+
+use std::sync::OnceLock;
+
+/// Hall symbol and short Hermann–Mauguin symbol of the standard setting of
+/// each space group type, indexed by `number - 1`.
+///
+/// Where the International Tables give two origin choices, the second (with
+/// the inversion center at the origin) is used.  Rhombohedral groups use
+/// hexagonal axes (obverse setting).
+pub(crate) const SPACE_GROUP_TYPES: [(&str, &str); 230] = [
+    // triclinic
+    ("P 1", "P1"),
+    ("-P 1", "P-1"),
+    // monoclinic (unique axis b, cell choice 1)
+    ("P 2y", "P2"),
+    ("P 2yb", "P2_1"),
+    ("C 2y", "C2"),
+    ("P -2y", "Pm"),
+    ("P -2yc", "Pc"),
+    ("C -2y", "Cm"),
+    ("C -2yc", "Cc"),
+    ("-P 2y", "P2/m"),
+    ("-P 2yb", "P2_1/m"),
+    ("-C 2y", "C2/m"),
+    ("-P 2yc", "P2/c"),
+    ("-P 2ybc", "P2_1/c"),
+    ("-C 2yc", "C2/c"),
+    // orthorhombic
+    ("P 2 2", "P222"),
+    ("P 2c 2", "P222_1"),
+    ("P 2 2ab", "P2_12_12"),
+    ("P 2ac 2ab", "P2_12_12_1"),
+    ("C 2c 2", "C222_1"),
+    ("C 2 2", "C222"),
+    ("F 2 2", "F222"),
+    ("I 2 2", "I222"),
+    ("I 2b 2c", "I2_12_12_1"),
+    ("P 2 -2", "Pmm2"),
+    ("P 2c -2", "Pmc2_1"),
+    ("P 2 -2c", "Pcc2"),
+    ("P 2 -2a", "Pma2"),
+    ("P 2c -2ac", "Pca2_1"),
+    ("P 2 -2bc", "Pnc2"),
+    ("P 2ac -2", "Pmn2_1"),
+    ("P 2 -2ab", "Pba2"),
+    ("P 2c -2n", "Pna2_1"),
+    ("P 2 -2n", "Pnn2"),
+    ("C 2 -2", "Cmm2"),
+    ("C 2c -2", "Cmc2_1"),
+    ("C 2 -2c", "Ccc2"),
+    ("A 2 -2", "Amm2"),
+    ("A 2 -2c", "Aem2"),
+    ("A 2 -2a", "Ama2"),
+    ("A 2 -2ac", "Aea2"),
+    ("F 2 -2", "Fmm2"),
+    ("F 2 -2d", "Fdd2"),
+    ("I 2 -2", "Imm2"),
+    ("I 2 -2c", "Iba2"),
+    ("I 2 -2a", "Ima2"),
+    ("-P 2 2", "Pmmm"),
+    ("-P 2ab 2bc", "Pnnn"),
+    ("-P 2 2c", "Pccm"),
+    ("-P 2ab 2b", "Pban"),
+    ("-P 2a 2a", "Pmma"),
+    ("-P 2a 2bc", "Pnna"),
+    ("-P 2ac 2", "Pmna"),
+    ("-P 2a 2ac", "Pcca"),
+    ("-P 2 2ab", "Pbam"),
+    ("-P 2ab 2ac", "Pccn"),
+    ("-P 2c 2b", "Pbcm"),
+    ("-P 2 2n", "Pnnm"),
+    ("-P 2ab 2a", "Pmmn"),
+    ("-P 2n 2ab", "Pbcn"),
+    ("-P 2ac 2ab", "Pbca"),
+    ("-P 2ac 2n", "Pnma"),
+    ("-C 2c 2", "Cmcm"),
+    ("-C 2bc 2", "Cmce"),
+    ("-C 2 2", "Cmmm"),
+    ("-C 2 2c", "Cccm"),
+    ("-C 2b 2", "Cmme"),
+    ("-C 2b 2bc", "Ccce"),
+    ("-F 2 2", "Fmmm"),
+    ("-F 2uv 2vw", "Fddd"),
+    ("-I 2 2", "Immm"),
+    ("-I 2 2c", "Ibam"),
+    ("-I 2b 2c", "Ibca"),
+    ("-I 2b 2", "Imma"),
+    // tetragonal
+    ("P 4", "P4"),
+    ("P 4w", "P4_1"),
+    ("P 4c", "P4_2"),
+    ("P 4cw", "P4_3"),
+    ("I 4", "I4"),
+    ("I 4bw", "I4_1"),
+    ("P -4", "P-4"),
+    ("I -4", "I-4"),
+    ("-P 4", "P4/m"),
+    ("-P 4c", "P4_2/m"),
+    ("-P 4a", "P4/n"),
+    ("-P 4bc", "P4_2/n"),
+    ("-I 4", "I4/m"),
+    ("-I 4ad", "I4_1/a"),
+    ("P 4 2", "P422"),
+    ("P 4ab 2ab", "P42_12"),
+    ("P 4w 2c", "P4_122"),
+    ("P 4abw 2nw", "P4_12_12"),
+    ("P 4c 2", "P4_222"),
+    ("P 4n 2n", "P4_22_12"),
+    ("P 4cw 2c", "P4_322"),
+    ("P 4nw 2abw", "P4_32_12"),
+    ("I 4 2", "I422"),
+    ("I 4bw 2bw", "I4_122"),
+    ("P 4 -2", "P4mm"),
+    ("P 4 -2ab", "P4bm"),
+    ("P 4c -2c", "P4_2cm"),
+    ("P 4n -2n", "P4_2nm"),
+    ("P 4 -2c", "P4cc"),
+    ("P 4 -2n", "P4nc"),
+    ("P 4c -2", "P4_2mc"),
+    ("P 4c -2ab", "P4_2bc"),
+    ("I 4 -2", "I4mm"),
+    ("I 4 -2c", "I4cm"),
+    ("I 4bw -2", "I4_1md"),
+    ("I 4bw -2c", "I4_1cd"),
+    ("P -4 2", "P-42m"),
+    ("P -4 2c", "P-42c"),
+    ("P -4 2ab", "P-42_1m"),
+    ("P -4 2n", "P-42_1c"),
+    ("P -4 -2", "P-4m2"),
+    ("P -4 -2c", "P-4c2"),
+    ("P -4 -2ab", "P-4b2"),
+    ("P -4 -2n", "P-4n2"),
+    ("I -4 -2", "I-4m2"),
+    ("I -4 -2c", "I-4c2"),
+    ("I -4 2", "I-42m"),
+    ("I -4 2bw", "I-42d"),
+    ("-P 4 2", "P4/mmm"),
+    ("-P 4 2c", "P4/mcc"),
+    ("-P 4a 2b", "P4/nbm"),
+    ("-P 4a 2bc", "P4/nnc"),
+    ("-P 4 2ab", "P4/mbm"),
+    ("-P 4 2n", "P4/mnc"),
+    ("-P 4a 2a", "P4/nmm"),
+    ("-P 4a 2ac", "P4/ncc"),
+    ("-P 4c 2", "P4_2/mmc"),
+    ("-P 4c 2c", "P4_2/mcm"),
+    ("-P 4ac 2b", "P4_2/nbc"),
+    ("-P 4ac 2bc", "P4_2/nnm"),
+    ("-P 4c 2ab", "P4_2/mbc"),
+    ("-P 4n 2n", "P4_2/mnm"),
+    ("-P 4ac 2a", "P4_2/nmc"),
+    ("-P 4ac 2ac", "P4_2/ncm"),
+    ("-I 4 2", "I4/mmm"),
+    ("-I 4 2c", "I4/mcm"),
+    ("-I 4bd 2", "I4_1/amd"),
+    ("-I 4bd 2c", "I4_1/acd"),
+    // trigonal
+    ("P 3", "P3"),
+    ("P 31", "P3_1"),
+    ("P 32", "P3_2"),
+    ("R 3", "R3"),
+    ("-P 3", "P-3"),
+    ("-R 3", "R-3"),
+    ("P 3 2", "P312"),
+    ("P 3 2\"", "P321"),
+    ("P 31 2c (0 0 1)", "P3_112"),
+    ("P 31 2\"", "P3_121"),
+    ("P 32 2c (0 0 -1)", "P3_212"),
+    ("P 32 2\"", "P3_221"),
+    ("R 3 2\"", "R32"),
+    ("P 3 -2\"", "P3m1"),
+    ("P 3 -2", "P31m"),
+    ("P 3 -2\"c", "P3c1"),
+    ("P 3 -2c", "P31c"),
+    ("R 3 -2\"", "R3m"),
+    ("R 3 -2\"c", "R3c"),
+    ("-P 3 2", "P-31m"),
+    ("-P 3 2c", "P-31c"),
+    ("-P 3 2\"", "P-3m1"),
+    ("-P 3 2\"c", "P-3c1"),
+    ("-R 3 2\"", "R-3m"),
+    ("-R 3 2\"c", "R-3c"),
+    // hexagonal
+    ("P 6", "P6"),
+    ("P 61", "P6_1"),
+    ("P 65", "P6_5"),
+    ("P 62", "P6_2"),
+    ("P 64", "P6_4"),
+    ("P 6c", "P6_3"),
+    ("P -6", "P-6"),
+    ("-P 6", "P6/m"),
+    ("-P 6c", "P6_3/m"),
+    ("P 6 2", "P622"),
+    ("P 61 2 (0 0 -1)", "P6_122"),
+    ("P 65 2 (0 0 1)", "P6_522"),
+    ("P 62 2c (0 0 1)", "P6_222"),
+    ("P 64 2c (0 0 -1)", "P6_422"),
+    ("P 6c 2c", "P6_322"),
+    ("P 6 -2", "P6mm"),
+    ("P 6 -2c", "P6cc"),
+    ("P 6c -2", "P6_3cm"),
+    ("P 6c -2c", "P6_3mc"),
+    ("P -6 2", "P-6m2"),
+    ("P -6c 2", "P-6c2"),
+    ("P -6 -2", "P-62m"),
+    ("P -6c -2c", "P-62c"),
+    ("-P 6 2", "P6/mmm"),
+    ("-P 6 2c", "P6/mcc"),
+    ("-P 6c 2", "P6_3/mcm"),
+    ("-P 6c 2c", "P6_3/mmc"),
+    // cubic
+    ("P 2 2 3", "P23"),
+    ("F 2 2 3", "F23"),
+    ("I 2 2 3", "I23"),
+    ("P 2ac 2ab 3", "P2_13"),
+    ("I 2b 2c 3", "I2_13"),
+    ("-P 2 2 3", "Pm-3"),
+    ("-P 2ab 2bc 3", "Pn-3"),
+    ("-F 2 2 3", "Fm-3"),
+    ("-F 2uv 2vw 3", "Fd-3"),
+    ("-I 2 2 3", "Im-3"),
+    ("-P 2ac 2ab 3", "Pa-3"),
+    ("-I 2b 2c 3", "Ia-3"),
+    ("P 4 2 3", "P432"),
+    ("P 4n 2 3", "P4_232"),
+    ("F 4 2 3", "F432"),
+    ("F 4d 2 3", "F4_132"),
+    ("I 4 2 3", "I432"),
+    ("P 4acd 2ab 3", "P4_332"),
+    ("P 4bd 2ab 3", "P4_132"),
+    ("I 4bd 2c 3", "I4_132"),
+    ("P -4 2 3", "P-43m"),
+    ("F -4 2 3", "F-43m"),
+    ("I -4 2 3", "I-43m"),
+    ("P -4n 2 3", "P-43n"),
+    ("F -4c 2 3", "F-43c"),
+    ("I -4bd 2c 3", "I-43d"),
+    ("-P 4 2 3", "Pm-3m"),
+    ("-P 4a 2bc 3", "Pn-3n"),
+    ("-P 4n 2 3", "Pm-3n"),
+    ("-P 4bc 2bc 3", "Pn-3m"),
+    ("-F 4 2 3", "Fm-3m"),
+    ("-F 4c 2 3", "Fm-3c"),
+    ("-F 4vw 2vw 3", "Fd-3m"),
+    ("-F 4cvw 2vw 3", "Fd-3c"),
+    ("-I 4 2 3", "Im-3m"),
+    ("-I 4bd 2c 3", "Ia-3d"),
+];
+
+/// A space group in some particular setting.
+///
+/// Operations use the column-vector convention on fractional coordinates:
+/// `x -> rotation * x + translation`.
+#[derive(Debug, Clone)]
+pub(crate) struct HallGroup {
+    /// One operation for each distinct rotation, sorted by rotation.
+    pub(crate) ops: Vec<([[i32; 3]; 3], [f64; 3])>,
+    /// Centering translations (including zero), reduced into `[0, 1)` and sorted.
+    pub(crate) centering: Vec<[f64; 3]>,
+}
+
+/// Groups for each entry of `SPACE_GROUP_TYPES`.
+pub(crate) fn standard_groups() -> &'static [HallGroup]
+{
+    static GROUPS: OnceLock<Vec<HallGroup>> = OnceLock::new();
+    GROUPS.get_or_init(|| {
+        SPACE_GROUP_TYPES.iter()
+            .map(|&(hall, _)| parse_hall(hall).expect("(BUG) bad Hall symbol in table"))
+            .collect()
+    })
+}
+
+const IDENTITY: [[i32; 3]; 3] = [[1, 0, 0], [0, 1, 0], [0, 0, 1]];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Axis { X, Y, Z, Prime, DoublePrime, Star }
+
+/// Build the group described by a Hall symbol.
+pub(crate) fn parse_hall(symbol: &str) -> Result<HallGroup, failure::Error>
+{
+    let (symbol, origin_shift) = match symbol.find('(') {
+        None => (symbol, [0.0; 3]),
+        Some(open) => {
+            let close = symbol.find(')').ok_or_else(|| format_err!("unclosed parenthesis in {:?}", symbol))?;
+            let words: Vec<f64> = {
+                symbol[open + 1..close].split_whitespace()
+                    .map(|w| w.parse::<f64>().map(|x| x / 12.0))
+                    .collect::<Result<_, _>>()?
+            };
+            ensure!(words.len() == 3, "bad origin shift in {:?}", symbol);
+            (&symbol[..open], [words[0], words[1], words[2]])
+        },
+    };
+
+    let mut tokens = symbol.split_whitespace();
+    let lattice = tokens.next().ok_or_else(|| format_err!("empty Hall symbol"))?;
+    let (centrosymmetric, lattice) = match lattice.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, lattice),
+    };
+    let centering_generators: Vec<[f64; 3]> = match lattice {
+        "P" => vec![],
+        "A" => vec![[0.0, 0.5, 0.5]],
+        "B" => vec![[0.5, 0.0, 0.5]],
+        "C" => vec![[0.5, 0.5, 0.0]],
+        "I" => vec![[0.5, 0.5, 0.5]],
+        "R" => vec![[2.0 / 3.0, 1.0 / 3.0, 1.0 / 3.0]],
+        "F" => vec![[0.0, 0.5, 0.5], [0.5, 0.0, 0.5]],
+        _ => bail!("unknown lattice symbol {:?}", lattice),
+    };
+
+    let mut generators = vec![];
+    if centrosymmetric {
+        generators.push((mat_3!((r, c) => -IDENTITY[r][c]), [0.0; 3]));
+    }
+
+    let mut previous: Option<(u32, Axis)> = None;
+    for (index, token) in tokens.enumerate() {
+        let (improper, token) = match token.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, token),
+        };
+        let mut chars = token.chars().peekable();
+        let order = match chars.next().and_then(|c| c.to_digit(10)) {
+            Some(n @ 1..=4) | Some(n @ 6) => n,
+            _ => bail!("bad rotation in {:?}", token),
+        };
+        let screw = match chars.peek().and_then(|c| c.to_digit(10)) {
+            Some(s) => { chars.next(); s },
+            None => 0,
+        };
+        let explicit_axis = match chars.peek() {
+            Some('x') => Some(Axis::X),
+            Some('y') => Some(Axis::Y),
+            Some('z') => Some(Axis::Z),
+            Some('\'') => Some(Axis::Prime),
+            Some('"') => Some(Axis::DoublePrime),
+            Some('*') => Some(Axis::Star),
+            _ => None,
+        };
+        if explicit_axis.is_some() {
+            chars.next();
+        }
+        let axis = match (explicit_axis, index, previous) {
+            (Some(axis), _, _) => axis,
+            (None, 0, _) => Axis::Z,
+            (None, 1, Some((2, _))) | (None, 1, Some((4, _))) if order == 2 => Axis::X,
+            (None, 1, Some((3, _))) | (None, 1, Some((6, _))) if order == 2 => Axis::Prime,
+            (None, 2, _) if order == 3 => Axis::Star,
+            _ if order == 1 => Axis::Z,
+            _ => bail!("cannot determine axis of {:?}", token),
+        };
+
+        let preceding_axis = previous.map_or(Axis::Z, |(_, axis)| axis);
+        let mut rotation = rotation_matrix(order, axis, preceding_axis)?;
+        if improper {
+            rotation = mat_3!((r, c) => -rotation[r][c]);
+        }
+
+        let mut translation = [0.0; 3];
+        if screw != 0 {
+            let fraction = f64::from(screw) / f64::from(order);
+            match axis {
+                Axis::X => translation[0] = fraction,
+                Axis::Y => translation[1] = fraction,
+                Axis::Z => translation[2] = fraction,
+                _ => bail!("screw along a non-principal axis in {:?}", token),
+            }
+        }
+        for c in chars {
+            let shift = match c {
+                'a' => [0.5, 0.0, 0.0],
+                'b' => [0.0, 0.5, 0.0],
+                'c' => [0.0, 0.0, 0.5],
+                'n' => [0.5, 0.5, 0.5],
+                'u' => [0.25, 0.0, 0.0],
+                'v' => [0.0, 0.25, 0.0],
+                'w' => [0.0, 0.0, 0.25],
+                'd' => [0.25, 0.25, 0.25],
+                _ => bail!("bad translation symbol {:?} in {:?}", c, token),
+            };
+            translation = arr_3![k => translation[k] + shift[k]];
+        }
+
+        generators.push((rotation, translation));
+        previous = Some((order, axis));
+    }
+
+    // change of origin
+    let generators = generators.into_iter().map(|(rot, t)| {
+        let rv = apply_rotation(&rot, &origin_shift);
+        (rot, arr_3![k => t[k] + origin_shift[k] - rv[k]])
+    }).collect::<Vec<_>>();
+
+    let centering = close_centering(&centering_generators);
+    let ops = close_group(&generators);
+    Ok(HallGroup { ops, centering })
+}
+
+fn rotation_matrix(order: u32, axis: Axis, preceding: Axis) -> Result<[[i32; 3]; 3], failure::Error>
+{
+    // matrices for rotations about z; other principal axes are obtained by
+    // cyclically permuting the coordinates.
+    let about_z = match order {
+        1 => IDENTITY,
+        2 => [[-1, 0, 0], [0, -1, 0], [0, 0, 1]],
+        3 => [[0, -1, 0], [1, -1, 0], [0, 0, 1]],
+        4 => [[0, -1, 0], [1, 0, 0], [0, 0, 1]],
+        6 => [[1, -1, 0], [1, 0, 0], [0, 0, 1]],
+        _ => unreachable!(),
+    };
+    let cycle = |m: [[i32; 3]; 3], shift: usize| mat_3!((r, c) => m[(r + shift) % 3][(c + shift) % 3]);
+    Ok(match axis {
+        Axis::Z => about_z,
+        Axis::X => cycle(about_z, 2),
+        Axis::Y => cycle(about_z, 1),
+        Axis::Prime | Axis::DoublePrime => {
+            ensure!(order == 2, "primed axes are only allowed for two-fold rotations");
+            let sign = if axis == Axis::Prime { -1 } else { 1 };
+            // two-fold rotation about (a - b) or (a + b), for a preceding axis of z.
+            let about_z = [[0, sign, 0], [sign, 0, 0], [0, 0, -1]];
+            match preceding {
+                Axis::Z => about_z,
+                Axis::X => cycle(about_z, 2),
+                Axis::Y => cycle(about_z, 1),
+                _ => bail!("primed axis following a non-principal axis"),
+            }
+        },
+        Axis::Star => {
+            ensure!(order == 3, "the body diagonal is only allowed for three-fold rotations");
+            [[0, 0, 1], [1, 0, 0], [0, 1, 0]]
+        },
+    })
+}
+
+pub(crate) fn apply_rotation(rot: &[[i32; 3]; 3], x: &[f64; 3]) -> [f64; 3]
+{ arr_3![r => (0..3).map(|c| f64::from(rot[r][c]) * x[c]).sum()] }
+
+/// Reduce into `[0, 1)`, snapping values within roundoff of `1` to `0`.
+pub(crate) fn reduce_translation(t: &[f64; 3]) -> [f64; 3]
+{
+    arr_3![k => {
+        let x = t[k] - t[k].floor();
+        if x > 1.0 - 1e-9 { 0.0 } else { x }
+    }]
+}
+
+pub(crate) fn close_centering(generators: &[[f64; 3]]) -> Vec<[f64; 3]>
+{
+    let mut out = vec![[0.0; 3]];
+    let mut index = 0;
+    while index < out.len() {
+        for g in generators {
+            let new = reduce_translation(&arr_3![k => out[index][k] + g[k]]);
+            if !out.iter().any(|t| translations_equal(t, &new)) {
+                out.push(new);
+            }
+        }
+        index += 1;
+    }
+    out.sort_by(|a, b| a.partial_cmp(b).expect("(BUG) NaN"));
+    out
+}
+
+pub(crate) fn translations_equal(a: &[f64; 3], b: &[f64; 3]) -> bool
+{
+    (0..3).all(|k| {
+        let d = a[k] - b[k];
+        (d - d.round()).abs() < 1e-6
+    })
+}
+
+/// Close a set of generators into a group, keeping one translation per rotation.
+fn close_group(generators: &[([[i32; 3]; 3], [f64; 3])]) -> Vec<([[i32; 3]; 3], [f64; 3])>
+{
+    let mut ops = vec![(IDENTITY, [0.0; 3])];
+    let mut index = 0;
+    while index < ops.len() {
+        for (gen_rot, gen_t) in generators {
+            let (rot, t) = ops[index];
+            let new_rot = mul_i32_33(gen_rot, &rot);
+            let rt = apply_rotation(gen_rot, &t);
+            let new_t = reduce_translation(&arr_3![k => rt[k] + gen_t[k]]);
+            if !ops.iter().any(|&(r, _)| r == new_rot) {
+                ops.push((new_rot, new_t));
+            }
+        }
+        index += 1;
+        assert!(ops.len() <= 48, "(BUG) infinite group from Hall symbol");
+    }
+    ops.sort_by_key(|op| op.0);
+    ops
+}
+
+#[cfg(test)]
+#[deny(unused)]
+mod tests {
+    use super::*;
+
+    fn expected_order(number: usize) -> usize {
+        // orders of the point groups, by the number of the first space group of each
+        let table = [
+            (1, 1), (2, 2), (3, 2), (6, 2), (10, 4), (16, 4), (25, 4), (47, 8),
+            (75, 4), (81, 4), (83, 8), (89, 8), (99, 8), (111, 8), (123, 16),
+            (143, 3), (147, 6), (149, 6), (156, 6), (162, 12),
+            (168, 6), (174, 6), (175, 12), (177, 12), (183, 12), (187, 12), (191, 24),
+            (195, 12), (200, 24), (207, 24), (215, 24), (221, 48),
+        ];
+        table.iter().rev().find(|&&(first, _)| first <= number).unwrap().1
+    }
+
+    #[test]
+    fn table_is_consistent() {
+        for (index, &(hall, hm)) in SPACE_GROUP_TYPES.iter().enumerate() {
+            let number = index + 1;
+            let group = parse_hall(hall).unwrap();
+            assert_eq!(group.ops.len(), expected_order(number), "{} {}", number, hall);
+
+            // lattice symbols agree
+            let hall_lattice = hall.trim_start_matches('-').chars().next().unwrap();
+            assert_eq!(hm.chars().next().unwrap(), hall_lattice, "{}", number);
+            let expected_centering = match hall_lattice {
+                'P' => 1, 'A' | 'C' | 'I' => 2, 'R' => 3, 'F' => 4, _ => unreachable!(),
+            };
+            assert_eq!(group.centering.len(), expected_centering);
+
+            // closed under composition, modulo centering
+            for (ra, ta) in &group.ops {
+                for (rb, tb) in &group.ops {
+                    let rot = mul_i32_33(ra, rb);
+                    let rtb = apply_rotation(ra, tb);
+                    let t = arr_3![k => rtb[k] + ta[k]];
+                    let &(_, expected) = group.ops.iter().find(|op| op.0 == rot).unwrap();
+                    assert!(
+                        group.centering.iter().any(|c| translations_equal(&arr_3![k => t[k] + c[k]], &expected)),
+                        "{} {}", number, hall,
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn examples() {
+        // P2_1/c:  x,y,z  -x,y+1/2,-z+1/2  -x,-y,-z  x,-y+1/2,z+1/2
+        let group = parse_hall("-P 2ybc").unwrap();
+        let two_fold = group.ops.iter().find(|op| op.0 == [[-1, 0, 0], [0, 1, 0], [0, 0, -1]]).unwrap();
+        assert!(translations_equal(&two_fold.1, &[0.0, 0.5, 0.5]));
+        let mirror = group.ops.iter().find(|op| op.0 == [[1, 0, 0], [0, -1, 0], [0, 0, 1]]).unwrap();
+        assert!(translations_equal(&mirror.1, &[0.0, 0.5, 0.5]));
+
+        // P3_112: -y,-x,-z+2/3
+        let group = parse_hall("P 31 2c (0 0 1)").unwrap();
+        let two_fold = group.ops.iter().find(|op| op.0 == [[0, -1, 0], [-1, 0, 0], [0, 0, -1]]).unwrap();
+        assert!(translations_equal(&two_fold.1, &[0.0, 0.0, 2.0 / 3.0]));
+
+        assert!(parse_hall("Q 2").is_err());
+        assert!(parse_hall("P 5").is_err());
+        assert!(parse_hall("P 2q").is_err());
+    }
+}
@@ -0,0 +1,837 @@
+// Copyright 2018 Michael Lamparski
+// Part of the vasp-poscar crate.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Space group symmetry.
+
+mod hall;
+mod primitive;
+mod standardize;
+
+use crate::{Poscar, Builder, Coords};
+use crate::math::{add_3, adj_i32, det_i32, dot_f64, hermite_normal_form, inv_f64, mul_3_33, mul_33_33, norm_3, sub_3, transpose_33};
+use crate::reduction::delaunay_matrix;
+use crate::supercell::lattice_points_in_supercell;
+
+use self::hall::{apply_rotation, reduce_translation, SPACE_GROUP_TYPES};
+use self::standardize::Standardization;
+pub(crate) use self::standardize::alternative_settings;
+
+const IDENTITY: [[i32; 3]; 3] = [[1, 0, 0], [0, 1, 0], [0, 0, 1]];
+
+/// A symmetry operation of a structure.
+///
+/// This takes fractional coordinates `x` (in the basis of the lattice of the
+/// structure, written as a column vector) to `rotation * x + translation`.
+///
+/// Unlike the rest of this crate, symmetry operations follow the usual
+/// crystallographic convention of acting on *column* vectors.  This is the same
+/// convention used by the International Tables and by most other symmetry software.
+/// Transformations between cells, on the other hand, follow the conventions of
+/// the rest of the crate; e.g. [`SymmetryDataset::transformation`] has the same
+/// meaning as the matrix returned by [`Poscar::niggli_reduce`].
+///
+/// [`SymmetryDataset::transformation`]: struct.SymmetryDataset.html#structfield.transformation
+/// [`Poscar::niggli_reduce`]: struct.Poscar.html#method.niggli_reduce
+#[derive(Debug, Clone, PartialEq)]
+pub struct SymmetryOperation {
+    pub rotation: [[i32; 3]; 3],
+    /// Reduced into `[0, 1)`.
+    pub translation: [f64; 3],
+}
+
+impl SymmetryOperation {
+    /// Apply the operation to fractional coordinates.
+    pub fn apply(&self, frac: &[f64; 3]) -> [f64; 3]
+    { add_3(&apply_rotation(&self.rotation, frac), &self.translation) }
+}
+
+/// The output of [`Poscar::symmetry_dataset`].
+///
+/// [`Poscar::symmetry_dataset`]: struct.Poscar.html#method.symmetry_dataset
+#[derive(Debug, Clone)]
+pub struct SymmetryDataset {
+    /// The number of the space group type, from `1` to `230`.
+    pub number: u32,
+    /// Short Hermann–Mauguin symbol of the space group, e.g. `"P6_3/mmc"`.
+    ///
+    /// Subscripts are written with an underscore, and overbars as a minus sign.
+    pub international: String,
+    /// Hall symbol of the standard setting of the space group, e.g. `"-P 6c 2c"`.
+    ///
+    /// This describes the operations of [`conventional`], not those of the input.
+    ///
+    /// [`conventional`]: #structfield.conventional
+    pub hall_symbol: String,
+    /// The symmetry operations of the input structure, in its own basis.
+    ///
+    /// These are the same as the output of [`Poscar::symmetry_operations`].
+    ///
+    /// [`Poscar::symmetry_operations`]: struct.Poscar.html#method.symmetry_operations
+    pub operations: Vec<SymmetryOperation>,
+    /// For each site, the index of the first site that is symmetrically equivalent to it.
+    pub equivalent_atoms: Vec<usize>,
+    /// The matrix `T` such that the lattice of [`conventional`] is `T * L`, where `L`
+    /// is the scaled lattice of the input (with lattice vectors as rows).
+    ///
+    /// This is generally not an integer matrix, as the conventional cell may be
+    /// smaller than the input if the input is a supercell.
+    ///
+    /// [`conventional`]: #structfield.conventional
+    pub transformation: [[f64; 3]; 3],
+    /// The origin of the standard setting, in fractional coordinates of the input.
+    ///
+    /// Fractional coordinates `x` of the input correspond to the coordinates
+    /// `(x - origin_shift) * inv(T)` in [`conventional`].  (with `x` as a row vector)
+    ///
+    /// [`conventional`]: #structfield.conventional
+    pub origin_shift: [f64; 3],
+    /// The conventional cell, in the standard setting of the space group.
+    ///
+    /// This cell has the lattice described by [`transformation`], with the origin
+    /// placed at [`origin_shift`], and contains every site of the structure that lies
+    /// within it (in direct coordinates, reduced into `[0, 1)`).  Sites are grouped by
+    /// species, in order of first appearance in the input.  The comment is copied from
+    /// the input; velocities and selective dynamics are not included.
+    ///
+    /// The lattice and positions are **not** symmetrized; they are simply the input
+    /// data, re-expressed in the new cell.  The Cartesian axes are the same as those
+    /// of the input, so you may wish to call [`Poscar::standard_orientation`].
+    ///
+    /// [`transformation`]: #structfield.transformation
+    /// [`origin_shift`]: #structfield.origin_shift
+    /// [`Poscar::standard_orientation`]: struct.Poscar.html#method.standard_orientation
+    pub conventional: Poscar,
+}
+
+/// # Symmetry
+impl Poscar {
+    /// Find all symmetry operations of the structure.
+    ///
+    /// An operation is a symmetry of the structure if it maps every site onto a site of
+    /// the same species within a Cartesian distance of `symprec` (taking periodic images
+    /// into account), and likewise for the lattice vectors.  The translation of each
+    /// operation is refined to minimize the average displacement.
+    ///
+    /// For a supercell, the pure translations of the primitive cell appear as distinct
+    /// operations.  Only the operations that map the lattice of the Poscar onto itself
+    /// can be written as an integer matrix, so the operations of the primitive cell that
+    /// do not (e.g. a four-fold rotation in a `2x1x1` supercell of a cubic structure)
+    /// are omitted.  They are still taken into account by [`symmetry_dataset`].
+    ///
+    /// Operations are sorted; pure translations are listed first, beginning with the
+    /// identity.  See [`SymmetryOperation`] for conventions.
+    ///
+    /// Species are identified in the same manner as in [`bonds`]; that is, by symbol,
+    /// or by group index if there are no symbols.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the pure translations found do not form a group, which can happen
+    /// when `symprec` is too large.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `symprec` is not positive.
+    ///
+    /// [`SymmetryOperation`]: struct.SymmetryOperation.html
+    /// [`bonds`]: #method.bonds
+    /// [`symmetry_dataset`]: #method.symmetry_dataset
+    pub fn symmetry_operations(&self, symprec: f64) -> Result<Vec<SymmetryOperation>, failure::Error>
+    { Ok(search_operations(self, symprec)?.input_operations()) }
+
+    /// Find the symmetry operations and identify the space group of the structure.
+    ///
+    /// `symprec` is the tolerance described in [`symmetry_operations`].  A typical
+    /// value is `1e-3` to `1e-5` (in the units of the scaled lattice; usually angstroms).
+    ///
+    /// The standard settings are those of the International Tables (Vol. A); where two
+    /// origin choices are given, the second is used (inversion center at the origin),
+    /// and rhombohedral groups use hexagonal axes (obverse setting).  Monoclinic groups
+    /// use unique axis `b` and cell choice 1, with `a` and `c` as short as possible
+    /// and `beta` not less than 90 degrees.
+    ///
+    /// ```rust
+    /// # fn main() -> Result<(), failure::Error> {Ok({
+    /// use vasp_poscar::{Builder, Coords};
+    ///
+    /// // rocksalt, in its primitive cell
+    /// let poscar =
+    ///     Builder::new()
+    ///     .lattice_vectors(&[[0.0, 2.8, 2.8], [2.8, 0.0, 2.8], [2.8, 2.8, 0.0]])
+    ///     .site_symbols(vec!["Na", "Cl"])
+    ///     .positions(Coords::Frac(vec![[0.0; 3], [0.5; 3]]))
+    ///     .build()?;
+    ///
+    /// let dataset = poscar.symmetry_dataset(1e-5)?;
+    /// assert_eq!(dataset.number, 225);
+    /// assert_eq!(dataset.international, "Fm-3m");
+    /// assert_eq!(dataset.operations.len(), 48);
+    /// assert_eq!(dataset.conventional.num_sites(), 8);
+    /// # })}
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Fails if the operations found do not form a space group.  This can happen when
+    /// `symprec` is too large relative to the distortion of the structure.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `symprec` is not positive.
+    ///
+    /// [`symmetry_operations`]: #method.symmetry_operations
+    pub fn symmetry_dataset(&self, symprec: f64) -> Result<SymmetryDataset, failure::Error>
+    {
+        let search = search_operations(self, symprec)?;
+        let standardization = self::standardize::identify(&search, symprec)?;
+
+        let transformation = standardization.transformation(&search.cell);
+        let origin_shift = mul_3_33(&standardization.origin, &transformation);
+        let conventional = self.conventional_cell(&search, &standardization, &transformation)?;
+
+        let (hall_symbol, international) = SPACE_GROUP_TYPES[standardization.number - 1];
+        Ok(SymmetryDataset {
+            number: standardization.number as u32,
+            international: international.to_string(),
+            hall_symbol: hall_symbol.to_string(),
+            operations: search.input_operations(),
+            equivalent_atoms: search.equivalent_atoms(),
+            transformation,
+            origin_shift: reduce_translation(&origin_shift),
+            conventional,
+        })
+    }
+}
+
+impl Poscar {
+    fn conventional_cell(
+        &self,
+        search: &OperationSearch,
+        standardization: &Standardization,
+        transformation: &[[f64; 3]; 3],
+    ) -> Result<Poscar, failure::Error>
+    {
+        let cell = &search.cell;
+
+        // One site from each orbit of the pure translations (i.e. the sites of the
+        // primitive cell) is placed at every lattice point of the conventional cell.
+        let to_primitive = inv_f64(&cell.primitive_basis);
+        let to_conventional = standardization.to_conventional;
+        let from_primitive = inv_f64(&mat_3!((r, c) => f64::from(to_conventional[r][c])));
+        let lattice_points = lattice_points_in_supercell(&to_conventional);
+
+        let mut sites = vec![];
+        for (i, frac) in cell.fracs.iter().enumerate() {
+            if !cell.is_representative(i) {
+                continue;
+            }
+            let primitive_frac = mul_3_33(frac, &to_primitive);
+            for point in &lattice_points {
+                let shifted = add_3(&primitive_frac, &arr_3![k => f64::from(point[k])]);
+                let conventional_frac = sub_3(&mul_3_33(&shifted, &from_primitive), &standardization.origin);
+                sites.push((cell.types[i], reduce_translation(&conventional_frac)));
+            }
+        }
+        // (stable sort)
+        sites.sort_by_key(|&(t, _)| t);
+
+        let (counts, group_types) = crate::builder::get_run_length_encoding(sites.iter().map(|&(t, _)| t));
+        let mut builder = Builder::new();
+        builder
+            .comment(self.comment())
+            .lattice_vectors(&mul_33_33(transformation, &self.scaled_lattice()))
+            .group_counts(counts)
+            .positions(Coords::Frac(sites.into_iter().map(|(_, x)| x).collect::<Vec<_>>()));
+        if let Some(symbols) = self.type_symbols() {
+            builder.group_symbols(group_types.into_iter().map(|t| symbols[t].clone()).collect::<Vec<_>>());
+        }
+        Ok(builder.build()?)
+    }
+}
+
+/// A symmetry operation found by the search, along with its action on the sites.
+#[derive(Debug, Clone)]
+pub(crate) struct FoundOperation {
+    pub(crate) rotation: [[i32; 3]; 3],
+    pub(crate) translation: [f64; 3],
+    /// The operation maps site `i` onto site `mapping[i]`.
+    ///
+    /// For operations in the primitive basis, the target is only determined up to
+    /// the pure translations.
+    pub(crate) mapping: Vec<usize>,
+}
+
+/// The pure translations of a structure, and the primitive cell they generate.
+#[derive(Debug, Clone)]
+pub(crate) struct TranslationSearch {
+    /// Change of basis to a reduced cell.  (`reduced_lattice = reduction * scaled_lattice`)
+    pub(crate) reduction: [[i32; 3]; 3],
+    /// Fractional positions in the reduced basis.
+    pub(crate) fracs: Vec<[f64; 3]>,
+    /// The output of `site_type_indices`.
+    pub(crate) types: Vec<usize>,
+    /// Pure translations in the reduced basis, beginning with the identity.
+    pub(crate) translations: Vec<FoundOperation>,
+    /// Rows are the (Delaunay-reduced) primitive lattice vectors, in fractional
+    /// coordinates of the reduced cell.
+    pub(crate) primitive_basis: [[f64; 3]; 3],
+    pub(crate) primitive_lattice: [[f64; 3]; 3],
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct OperationSearch {
+    pub(crate) cell: TranslationSearch,
+    /// Operations in the primitive basis, one for each rotation, beginning with the identity.
+    pub(crate) primitive_ops: Vec<FoundOperation>,
+}
+
+impl TranslationSearch {
+    /// Whether a site is the first of its orbit under the pure translations.
+    pub(crate) fn is_representative(&self, site: usize) -> bool
+    { self.representative(site) == site }
+
+    /// Get the first site in the orbit of a site under the pure translations.
+    pub(crate) fn representative(&self, site: usize) -> usize
+    { self.translations.iter().map(|op| op.mapping[site]).min().unwrap_or(site) }
+}
+
+impl OperationSearch {
+    /// Get the operations that can be expressed in the basis of the input lattice.
+    pub(crate) fn input_operations(&self) -> Vec<SymmetryOperation>
+    {
+        let cell = &self.cell;
+        let reduction = mat_3!((r, c) => f64::from(cell.reduction[r][c]));
+        // input_frac = transpose(primitive_basis * reduction) * primitive_frac  (as columns)
+        let to_input = transpose_33(&mul_33_33(&cell.primitive_basis, &reduction));
+        let from_input = inv_f64(&to_input);
+
+        let mut out = vec![];
+        for op in &self.primitive_ops {
+            let rotation = mat_3!((r, c) => f64::from(op.rotation[r][c]));
+            let rotation = mul_33_33(&to_input, &mul_33_33(&rotation, &from_input));
+            if rotation.iter().flatten().any(|x| (x - x.round()).abs() > 1e-6) {
+                continue; // not a symmetry of the input lattice
+            }
+            let rotation = mat_3!((r, c) => rotation[r][c].round() as i32);
+            let translation = mul_3_33(&op.translation, &transpose_33(&to_input));
+            for pure in &cell.translations {
+                let pure = mul_3_33(&pure.translation, &reduction);
+                out.push(SymmetryOperation {
+                    rotation,
+                    translation: reduce_translation(&add_3(&translation, &pure)),
+                });
+            }
+        }
+        out.sort_by(|a, b| {
+            (a.rotation != IDENTITY).cmp(&(b.rotation != IDENTITY))
+                .then_with(|| a.rotation.cmp(&b.rotation))
+                .then_with(|| a.translation.partial_cmp(&b.translation).expect("(BUG) NaN"))
+        });
+        out
+    }
+
+    /// For each site, the index of the first site that is equivalent to it.
+    pub(crate) fn equivalent_atoms(&self) -> Vec<usize>
+    {
+        fn find(parent: &mut [usize], i: usize) -> usize {
+            let mut root = i;
+            while parent[root] != root {
+                root = parent[root];
+            }
+            parent[i] = root;
+            root
+        }
+
+        let mut parent: Vec<usize> = (0..self.cell.fracs.len()).collect();
+        for op in self.primitive_ops.iter().chain(&self.cell.translations) {
+            for (i, &j) in op.mapping.iter().enumerate() {
+                let (a, b) = (find(&mut parent, i), find(&mut parent, j));
+                parent[a.max(b)] = a.min(b);
+            }
+        }
+        (0..parent.len()).map(|i| find(&mut parent, i)).collect()
+    }
+}
+
+fn assert_symprec(symprec: f64)
+{ assert!(symprec > 0.0 && symprec.is_finite(), "symprec must be positive (got {})", symprec); }
+
+pub(crate) fn search_translations(poscar: &Poscar, symprec: f64) -> Result<TranslationSearch, failure::Error>
+{
+    assert_symprec(symprec);
+
+    let lattice = poscar.scaled_lattice();
+    let reduction = delaunay_matrix(&lattice, 1e-5);
+    let reduced_lattice = mul_33_33(&mat_3!((r, c) => f64::from(reduction[r][c])), &lattice);
+    let inverse = adj_i32(&reduction); // det(reduction) = 1
+    let inverse = mat_3!((r, c) => inverse[r][c] as f64);
+    let fracs: Vec<[f64; 3]> = poscar.frac_positions().iter().map(|x| mul_3_33(x, &inverse)).collect();
+
+    let mut out = TranslationSearch {
+        reduction,
+        fracs,
+        types: poscar.site_type_indices(),
+        translations: vec![],
+        primitive_basis: [[0.0; 3]; 3],
+        primitive_lattice: [[0.0; 3]; 3],
+    };
+
+    // Candidates map the first site of the rarest species onto each site of that species.
+    let sites_of_type = sites_of_type(&out.types);
+    match sites_of_type.iter().min_by_key(|sites| sites.len()) {
+        None => out.translations.push(FoundOperation { rotation: IDENTITY, translation: [0.0; 3], mapping: vec![] }),
+        Some(reference_sites) => {
+            let reference = out.fracs[reference_sites[0]];
+            for &target in reference_sites {
+                let translation = sub_3(&out.fracs[target], &reference);
+                let found = try_operation(&IDENTITY, &translation, &out.fracs, &out.types, &sites_of_type, &reduced_lattice, symprec, true);
+                if let Some((mapping, translation)) = found {
+                    out.translations.push(FoundOperation { rotation: IDENTITY, translation, mapping });
+                }
+            }
+        },
+    }
+
+    // The translations (scaled by their number) and the original lattice generate
+    // the primitive lattice (scaled likewise).
+    let n = out.translations.len() as i64;
+    let rows: Vec<[i64; 3]> = {
+        out.translations.iter()
+            .map(|op| arr_3![k => (n as f64 * op.translation[k]).round() as i64])
+            .chain((0..3).map(|k| arr_3![j => if j == k { n } else { 0 }]))
+            .collect()
+    };
+    let hnf = hermite_normal_form(&rows);
+    ensure!(
+        hnf[0][0] * hnf[1][1] * hnf[2][2] == n * n,
+        "the pure translations do not form a group (try a different tolerance)",
+    );
+    let basis = mat_3!((r, c) => hnf[r][c] as f64 / n as f64);
+    let primitive_reduction = delaunay_matrix(&mul_33_33(&basis, &reduced_lattice), 1e-5);
+    out.primitive_basis = mul_33_33(&mat_3!((r, c) => f64::from(primitive_reduction[r][c])), &basis);
+    out.primitive_lattice = mul_33_33(&out.primitive_basis, &reduced_lattice);
+    Ok(out)
+}
+
+pub(crate) fn search_operations(poscar: &Poscar, symprec: f64) -> Result<OperationSearch, failure::Error>
+{
+    let cell = search_translations(poscar, symprec)?;
+
+    // Work in the primitive cell; i.e. only with one site from each orbit of the
+    // pure translations, since the rest follow.
+    let to_primitive = inv_f64(&cell.primitive_basis);
+    let representatives: Vec<usize> = (0..cell.fracs.len()).filter(|&i| cell.is_representative(i)).collect();
+    let fracs: Vec<[f64; 3]> = representatives.iter().map(|&i| mul_3_33(&cell.fracs[i], &to_primitive)).collect();
+    let types: Vec<usize> = representatives.iter().map(|&i| cell.types[i]).collect();
+    let sites_of_type = sites_of_type(&types);
+    // index into `representatives` of the representative of each site
+    let representative_index: Vec<usize> = {
+        (0..cell.fracs.len()).map(|i| {
+            representatives.binary_search(&cell.representative(i)).expect("(BUG) not a representative")
+        }).collect()
+    };
+
+    let rotations = lattice_point_group(&cell.primitive_lattice, symprec);
+    let mut primitive_ops = vec![];
+    match sites_of_type.iter().min_by_key(|sites| sites.len()) {
+        None => {
+            primitive_ops.extend(rotations.into_iter().map(|rotation| {
+                FoundOperation { rotation, translation: [0.0; 3], mapping: vec![] }
+            }));
+        },
+        Some(reference_sites) => {
+            for rotation in rotations {
+                let image = apply_rotation(&rotation, &fracs[reference_sites[0]]);
+                let found = reference_sites.iter().find_map(|&target| {
+                    let translation = sub_3(&fracs[target], &image);
+                    try_operation(&rotation, &translation, &fracs, &types, &sites_of_type, &cell.primitive_lattice, symprec, false)
+                });
+                if let Some((mapping, translation)) = found {
+                    let mapping = representative_index.iter().map(|&k| representatives[mapping[k]]).collect();
+                    primitive_ops.push(FoundOperation { rotation, translation, mapping });
+                }
+            }
+        },
+    }
+    primitive_ops.sort_by(|a, b| {
+        (a.rotation != IDENTITY).cmp(&(b.rotation != IDENTITY))
+            .then_with(|| a.rotation.cmp(&b.rotation))
+    });
+    Ok(OperationSearch { cell, primitive_ops })
+}
+
+fn sites_of_type(types: &[usize]) -> Vec<Vec<usize>>
+{
+    let num_types = types.iter().max().map_or(0, |&t| t + 1);
+    let mut out = vec![vec![]; num_types];
+    for (i, &t) in types.iter().enumerate() {
+        out[t].push(i);
+    }
+    out
+}
+
+/// Find the integer matrices (acting on fractional column vectors) which preserve
+/// the metric of a reduced lattice, within tolerance.
+fn lattice_point_group(lattice: &[[f64; 3]; 3], symprec: f64) -> Vec<[[i32; 3]; 3]>
+{
+    let lengths = arr_3![k => norm_3(&lattice[k])];
+    let mut vectors = vec![];
+    for a in -2..=2 {
+        for b in -2..=2 {
+            for c in -2..=2 {
+                let v = [a, b, c];
+                let cart = mul_3_33(&arr_3![k => f64::from(v[k])], lattice);
+                vectors.push((v, cart));
+            }
+        }
+    }
+    let candidates = arr_3![k => {
+        vectors.iter()
+            .filter(|(_, cart)| (norm_3(cart) - lengths[k]).abs() <= symprec)
+            .collect::<Vec<_>>()
+    }];
+    let close = |i: usize, j: usize, u: &[f64; 3], v: &[f64; 3]| {
+        (dot_f64(u, v) - dot_f64(&lattice[i], &lattice[j])).abs() <= symprec * (lengths[i] + lengths[j])
+    };
+
+    let mut out = vec![];
+    for (ua, a) in &candidates[0] {
+        for (ub, b) in &candidates[1] {
+            if !close(0, 1, a, b) {
+                continue;
+            }
+            for (uc, c) in &candidates[2] {
+                if !close(0, 2, a, c) || !close(1, 2, b, c) {
+                    continue;
+                }
+                // the images of the lattice vectors are the columns
+                let columns = [ua, ub, uc];
+                let rotation = mat_3!((r, c) => columns[c][r]);
+                if det_i32(&rotation).abs() == 1 {
+                    out.push(rotation);
+                }
+            }
+        }
+    }
+    out
+}
+
+/// Check whether an operation maps every site onto a site of the same type.
+///
+/// On success, produces the mapping and the refined translation.  If `bijective`
+/// is true, the mapping must also be a permutation.
+#[allow(clippy::too_many_arguments)]
+fn try_operation(
+    rotation: &[[i32; 3]; 3],
+    translation: &[f64; 3],
+    fracs: &[[f64; 3]],
+    types: &[usize],
+    sites_of_type: &[Vec<usize>],
+    lattice: &[[f64; 3]; 3],
+    symprec: f64,
+    bijective: bool,
+) -> Option<(Vec<usize>, [f64; 3])>
+{
+    // A displacement within `symprec` has small fractional components.
+    // (this is only a quick test to avoid most of the Cartesian distance computations)
+    let inverse = inv_f64(lattice);
+    let bounds = arr_3![k => symprec * norm_3(&arr_3![j => inverse[j][k]]) * (1.0 + 1e-8)];
+
+    let mut mapping = Vec::with_capacity(fracs.len());
+    let mut used = vec![false; fracs.len()];
+    let mut total_residual = [0.0; 3];
+    for (frac, &t) in zip!(fracs, types) {
+        let image = add_3(&apply_rotation(rotation, frac), translation);
+
+        let mut best: Option<(usize, [f64; 3], f64)> = None;
+        for &k in &sites_of_type[t] {
+            let diff = sub_3(&fracs[k], &image);
+            let diff = arr_3![c => diff[c] - diff[c].round()];
+            if (0..3).any(|c| diff[c].abs() > bounds[c]) {
+                continue;
+            }
+            let distance = norm_3(&mul_3_33(&diff, lattice));
+            if distance <= symprec && best.is_none_or(|(_, _, d)| distance < d) {
+                best = Some((k, diff, distance));
+            }
+        }
+        let (k, residual, _) = best?;
+        if bijective && used[k] {
+            return None;
+        }
+        used[k] = true;
+        mapping.push(k);
+        total_residual = add_3(&total_residual, &residual);
+    }
+
+    let n = fracs.len().max(1) as f64;
+    let refined = arr_3![k => translation[k] + total_residual[k] / n];
+    Some((mapping, reduce_translation(&refined)))
+}
+
+#[cfg(test)]
+#[deny(unused)]
+mod tests {
+    use super::*;
+    use super::hall::standard_groups;
+    use crate::util::test_matrices::*;
+    use crate::math::det_f64;
+
+    fn build(lattice: &[[f64; 3]; 3], symbols: &[&str], fracs: &[[f64; 3]]) -> Poscar {
+        Builder::new()
+            .lattice_vectors(lattice)
+            .site_symbols(symbols.iter().cloned())
+            .positions(Coords::Frac(fracs.to_vec()))
+            .build().unwrap()
+    }
+
+    fn cubic(a: f64) -> [[f64; 3]; 3] {
+        [[a, 0.0, 0.0], [0.0, a, 0.0], [0.0, 0.0, a]]
+    }
+
+    fn hexagonal(a: f64, c: f64) -> [[f64; 3]; 3] {
+        [[a, 0.0, 0.0], [-0.5 * a, 0.5 * 3f64.sqrt() * a, 0.0], [0.0, 0.0, c]]
+    }
+
+    fn fcc(a: f64) -> [[f64; 3]; 3] {
+        let h = 0.5 * a;
+        [[0.0, h, h], [h, 0.0, h], [h, h, 0.0]]
+    }
+
+    #[test]
+    fn known_structures() {
+        let cases = vec![
+            // rocksalt
+            (build(&fcc(5.6), &["Na", "Cl"], &[[0.0; 3], [0.5; 3]]), 225, 8),
+            // diamond
+            (build(&fcc(3.57), &["C", "C"], &[[0.0; 3], [0.25; 3]]), 227, 8),
+            // bcc, in the conventional cell
+            (build(&cubic(2.87), &["Fe", "Fe"], &[[0.0; 3], [0.5; 3]]), 229, 2),
+            // CsCl
+            (build(&cubic(4.1), &["Cs", "Cl"], &[[0.0; 3], [0.5; 3]]), 221, 2),
+            // perovskite
+            (build(&cubic(3.9), &["Sr", "Ti", "O", "O", "O"], &[
+                [0.0; 3], [0.5; 3], [0.5, 0.5, 0.0], [0.5, 0.0, 0.5], [0.0, 0.5, 0.5],
+            ]), 221, 5),
+            // hcp
+            (build(&hexagonal(3.2, 5.2), &["Mg", "Mg"], &[
+                [1.0 / 3.0, 2.0 / 3.0, 0.25], [2.0 / 3.0, 1.0 / 3.0, 0.75],
+            ]), 194, 2),
+            // wurtzite
+            (build(&hexagonal(3.25, 5.2), &["Zn", "Zn", "O", "O"], &[
+                [1.0 / 3.0, 2.0 / 3.0, 0.0], [2.0 / 3.0, 1.0 / 3.0, 0.5],
+                [1.0 / 3.0, 2.0 / 3.0, 0.38], [2.0 / 3.0, 1.0 / 3.0, 0.88],
+            ]), 186, 4),
+            // graphite (AB)
+            (build(&hexagonal(2.46, 6.7), &["C", "C", "C", "C"], &[
+                [0.0, 0.0, 0.25], [0.0, 0.0, 0.75], [1.0 / 3.0, 2.0 / 3.0, 0.25], [2.0 / 3.0, 1.0 / 3.0, 0.75],
+            ]), 194, 4),
+            // rutile
+            (build(&[[4.6, 0.0, 0.0], [0.0, 4.6, 0.0], [0.0, 0.0, 2.96]], &["Ti", "Ti", "O", "O", "O", "O"], &[
+                [0.0; 3], [0.5; 3],
+                [0.3, 0.3, 0.0], [0.7, 0.7, 0.0], [0.8, 0.2, 0.5], [0.2, 0.8, 0.5],
+            ]), 136, 6),
+        ];
+        for (poscar, number, conventional_sites) in cases {
+            let dataset = poscar.symmetry_dataset(1e-5).unwrap();
+            assert_eq!(dataset.number, number);
+            assert_eq!(dataset.international, SPACE_GROUP_TYPES[number as usize - 1].1);
+            assert_eq!(dataset.conventional.num_sites(), conventional_sites);
+            assert_eq!(
+                dataset.conventional.site_symbols().unwrap().collect::<Vec<_>>().len(),
+                conventional_sites,
+            );
+        }
+    }
+
+    #[test]
+    fn operations_and_orbits() {
+        // simple cubic, as a 2x1x1 supercell with a vacancy-free decoration
+        let poscar = build(
+            &[[6.0, 0.0, 0.0], [0.0, 3.0, 0.0], [0.0, 0.0, 3.0]],
+            &["Po", "Po"],
+            &[[0.0; 3], [0.5, 0.0, 0.0]],
+        );
+        let ops = poscar.symmetry_operations(1e-5).unwrap();
+        assert_eq!(ops.len(), 32);
+        assert_eq!(ops[0], SymmetryOperation { rotation: IDENTITY, translation: [0.0; 3] });
+        assert_eq!(ops[1].rotation, IDENTITY);
+        assert!((ops[1].translation[0] - 0.5).abs() < 1e-12);
+
+        let dataset = poscar.symmetry_dataset(1e-5).unwrap();
+        assert_eq!(dataset.number, 221);
+        assert_eq!(dataset.equivalent_atoms, vec![0, 0]);
+        assert_eq!(dataset.conventional.num_sites(), 1);
+        assert!((dataset.conventional.scaled_volume() - 27.0).abs() < 1e-9);
+
+        // every operation maps the structure onto itself
+        for op in &ops {
+            for frac in poscar.frac_positions().iter() {
+                let image = op.apply(frac);
+                assert!(poscar.frac_positions().iter().any(|other| {
+                    (0..3).all(|k| {
+                        let d = image[k] - other[k];
+                        (d - d.round()).abs() < 1e-9
+                    })
+                }));
+            }
+        }
+
+        // species are distinguished by symbol...
+        let poscar = build(&cubic(3.0), &["Cs", "Cl"], &[[0.0; 3], [0.5; 3]]);
+        let dataset = poscar.symmetry_dataset(1e-5).unwrap();
+        assert_eq!(dataset.equivalent_atoms, vec![0, 1]);
+
+        // ...or by group when there are no symbols
+        let mut raw = poscar.into_raw();
+        raw.group_symbols = None;
+        let dataset = raw.clone().validate().unwrap().symmetry_dataset(1e-5).unwrap();
+        assert_eq!(dataset.number, 221);
+        raw.group_counts = vec![2];
+        let dataset = raw.validate().unwrap().symmetry_dataset(1e-5).unwrap();
+        assert_eq!(dataset.number, 229);
+    }
+
+    #[test]
+    fn tolerance() {
+        let ideal = [[0.0; 3], [0.25; 3]];
+        let noisy = [[1e-4, -2e-4, 0.0], [0.25, 0.2502, 0.2499]];
+        let poscar = build(&fcc(3.57), &["C", "C"], &noisy);
+        assert_eq!(poscar.symmetry_dataset(1e-2).unwrap().number, 227);
+        assert_eq!(poscar.symmetry_operations(1e-2).unwrap().len(), 48);
+        assert!(poscar.symmetry_operations(1e-5).unwrap().len() < 48);
+
+        let poscar = build(&fcc(3.57), &["C", "C"], &ideal);
+        assert_eq!(poscar.symmetry_operations(1e-5).unwrap().len(), 48);
+    }
+
+    // A lattice for each crystal system, with no additional symmetry.
+    fn generic_lattice(number: usize) -> [[f64; 3]; 3] {
+        match number {
+            1..=2 => [[4.0, 0.0, 0.0], [0.5, 4.8, 0.0], [-0.7, 0.9, 5.6]],
+            3..=15 => [[4.0, 0.0, 0.0], [0.0, 5.0, 0.0], [-1.2, 0.0, 5.8]],
+            16..=74 => [[4.0, 0.0, 0.0], [0.0, 5.0, 0.0], [0.0, 0.0, 6.0]],
+            75..=142 => [[4.0, 0.0, 0.0], [0.0, 4.0, 0.0], [0.0, 0.0, 6.0]],
+            143..=194 => hexagonal(4.0, 6.0),
+            195..=230 => cubic(5.0),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn every_space_group() {
+        // Two species, so that no orbit has accidental extra symmetry. (e.g. the orbit
+        // of a point under P2 is just a pair of points, which is centrosymmetric)
+        let points = [("X", [0.1234, 0.2345, 0.3456]), ("Y", [0.3712, 0.0921, 0.2613])];
+        let contains = |fracs: &[[f64; 3]], x: &[f64; 3]| {
+            fracs.iter().any(|y| hall::translations_equal(x, y))
+        };
+
+        for (index, group) in standard_groups().iter().enumerate() {
+            let number = index + 1;
+
+            // orbits of general positions in the standard setting
+            let mut symbols = vec![];
+            let mut fracs: Vec<[f64; 3]> = vec![];
+            for (symbol, point) in &points {
+                for (rotation, translation) in &group.ops {
+                    for centering in &group.centering {
+                        let image = add_3(&add_3(&apply_rotation(rotation, point), translation), centering);
+                        symbols.push(*symbol);
+                        fracs.push(reduce_translation(&image));
+                    }
+                }
+            }
+
+            // move the origin and use a skewed basis
+            let shift = [0.17, 0.31, 0.05];
+            let shifted: Vec<_> = fracs.iter().map(|x| add_3(x, &shift)).collect();
+            let mut poscar = build(&generic_lattice(number), &symbols, &shifted);
+            let skew = mat_3!((r, c) => EXAMPLE_UNIMODULAR[r][c] as i32);
+            poscar.change_basis(&skew).unwrap();
+
+            let dataset = poscar.symmetry_dataset(1e-5).unwrap();
+            let name = group_name(index);
+            assert_eq!(dataset.number as usize, number, "{}", name);
+            assert_eq!(dataset.hall_symbol, SPACE_GROUP_TYPES[index].0);
+            assert_eq!(dataset.operations.len(), fracs.len() / 2, "{}", name);
+            let half = fracs.len() / 2;
+            assert_eq!(dataset.equivalent_atoms, [vec![0; half], vec![half; half]].concat());
+
+            // the conventional cell is the one we started with, up to the choice of axes
+            let conventional = &dataset.conventional;
+            assert_eq!(conventional.num_sites(), fracs.len());
+            let volume = det_f64(&conventional.scaled_lattice());
+            assert!((volume - det_f64(&generic_lattice(number))).abs() < 1e-6);
+
+            // ...and has the symmetry of the standard setting
+            let conventional_fracs = conventional.frac_positions();
+            for x in conventional_fracs.iter() {
+                for (rotation, translation) in &group.ops {
+                    for centering in &group.centering {
+                        let image = add_3(&add_3(&apply_rotation(rotation, x), translation), centering);
+                        assert!(contains(&conventional_fracs, &image), "{}", name);
+                    }
+                }
+            }
+
+            // the documented relationship between the input and the conventional cell
+            let inverse = inv_f64(&dataset.transformation);
+            for x in poscar.frac_positions().iter() {
+                let y = mul_3_33(&sub_3(x, &dataset.origin_shift), &inverse);
+                assert!(contains(&conventional_fracs, &y), "{}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn monoclinic_setting() {
+        // Cm, with a reduced C-centered cell and an obtuse angle
+        let conventional = [[6.0, 0.0, 0.0], [0.0, 4.0, 0.0], [-1.0, 0.0, 5.5]];
+        let mut symbols = vec![];
+        let mut fracs = vec![];
+        for (symbol, [x, y, z]) in [("X", [0.1234, 0.2345, 0.3456]), ("Y", [0.3712, 0.0921, 0.2613])] {
+            for &[x, y, z] in &[[x, y, z], [x, -y, z], [x + 0.5, y + 0.5, z], [x + 0.5, 0.5 - y, z]] {
+                symbols.push(symbol);
+                fracs.push(reduce_translation(&[x, y, z]));
+            }
+        }
+        let expected = build(&conventional, &symbols, &fracs);
+
+        // other centered cells, an acute angle, a skewed basis, and the primitive cell
+        let mut inputs = vec![];
+        for basis in &[
+            IDENTITY,
+            [[0, 0, 1], [0, -1, 0], [1, 0, 0]],
+            [[1, 0, 1], [0, 1, 0], [0, 0, 1]],
+            [[1, 0, 0], [0, 1, 0], [1, 0, 1]],
+            [[-1, 0, 0], [0, -1, 0], [0, 0, 1]],
+            mat_3!((r, c) => EXAMPLE_UNIMODULAR[r][c] as i32),
+        ] {
+            let mut poscar = expected.clone();
+            poscar.change_basis(basis).unwrap();
+            inputs.push(poscar.find_primitive(1e-5).unwrap().0);
+            inputs.push(poscar);
+        }
+        for poscar in inputs {
+            let dataset = poscar.symmetry_dataset(1e-5).unwrap();
+            assert_eq!(dataset.number, 8);
+            let [a, b, c] = dataset.conventional.scaled_lattice();
+            let cos_beta = crate::math::dot_f64(&a, &c) / (norm_3(&a) * norm_3(&c));
+            assert!(cos_beta < 0.0, "{:?}", dataset.conventional.scaled_lattice());
+            assert!((norm_3(&a) - 6.0).abs() < 1e-9);
+            assert!((norm_3(&b) - 4.0).abs() < 1e-9);
+            assert!((norm_3(&c) - 5.5f64.hypot(1.0)).abs() < 1e-9);
+        }
+    }
+
+    fn group_name(index: usize) -> String {
+        format!("{} ({})", index + 1, SPACE_GROUP_TYPES[index].1)
+    }
+}
@@ -0,0 +1,254 @@
+// Copyright 2018 Michael Lamparski
+// Part of the vasp-poscar crate.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use crate::{Poscar, Coords, ScaleLine};
+use crate::math::{inv_f64, mul_3_33, mul_33_33, sub_3};
+use crate::types::FRAC;
+
+use super::search_translations;
+
+/// # Primitive cell
+impl Poscar {
+    /// Find a primitive cell of the structure, in case it is a supercell.
+    ///
+    /// This searches for pure translations that map every site onto a site of the
+    /// same species within a Cartesian distance of `tol` (as in [`symmetry_operations`]).
+    /// If there are none besides the identity, the structure is already primitive and
+    /// an unmodified copy is returned.  Otherwise, the translations and the lattice
+    /// generate a finer lattice, whose Delaunay-reduced basis becomes the new lattice.
+    ///
+    /// Also returned is a list which, for each site of the input, gives the index of
+    /// the corresponding site in the primitive cell.
+    ///
+    /// Each site of the output is the first site of the input that maps onto it; its data
+    /// is copied unchanged (not averaged), and sites are not wrapped into the new cell.
+    /// The comment, the kind of scale line, and the choice of Cartesian or direct
+    /// coordinates are preserved.  The remaining data is kept where it is consistent:
+    ///
+    /// * The groups are kept (with smaller counts) if no group contains only some of the
+    ///   images of a site.  Otherwise, sites are regrouped by species.
+    /// * Velocities are kept if all images of each site have the same Cartesian velocity
+    ///   (within `1e-8`).
+    /// * Selective dynamics are kept if all images of each site have the same flags,
+    ///   and those flags can be expressed in the new coordinates (see [`niggli_reduce`]).
+    ///
+    /// ```rust
+    /// # fn main() -> Result<(), failure::Error> {Ok({
+    /// use vasp_poscar::{Builder, Coords};
+    ///
+    /// // CsCl, doubled along the first axis
+    /// let poscar =
+    ///     Builder::new()
+    ///     .lattice_vectors(&[[8.0, 0.0, 0.0], [0.0, 4.0, 0.0], [0.0, 0.0, 4.0]])
+    ///     .site_symbols(vec!["Cs", "Cs", "Cl", "Cl"])
+    ///     .positions(Coords::Frac(vec![
+    ///         [0.0, 0.0, 0.0], [0.5, 0.0, 0.0],
+    ///         [0.25, 0.5, 0.5], [0.75, 0.5, 0.5],
+    ///     ]))
+    ///     .build()?;
+    ///
+    /// let (primitive, mapping) = poscar.find_primitive(1e-5)?;
+    /// assert_eq!(primitive.group_counts().collect::<Vec<_>>(), vec![1, 1]);
+    /// assert_eq!(primitive.scaled_volume(), 64.0);
+    /// assert_eq!(mapping, vec![0, 0, 1, 1]);
+    /// # })}
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Fails if the translations found do not form a group, which can happen when
+    /// `tol` is too large.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `tol` is not positive.
+    ///
+    /// [`symmetry_operations`]: #method.symmetry_operations
+    /// [`niggli_reduce`]: #method.niggli_reduce
+    pub fn find_primitive(&self, tol: f64) -> Result<(Poscar, Vec<usize>), failure::Error>
+    {
+        let search = search_translations(self, tol)?;
+        let num_sites = self.num_sites();
+        if search.translations.len() == 1 {
+            return Ok((self.clone(), (0..num_sites).collect()));
+        }
+
+        let representatives: Vec<usize> = (0..num_sites).filter(|&i| search.is_representative(i)).collect();
+        let mapping: Vec<usize> = {
+            (0..num_sites)
+                .map(|i| representatives.binary_search(&search.representative(i)).expect("(BUG) not a representative"))
+                .collect()
+        };
+        let pick = |xs: &[[f64; 3]]| representatives.iter().map(|&i| xs[i]).collect::<Vec<_>>();
+
+        // new_lattice = matrix * lattice;  new_frac = old_frac * inv(matrix)
+        let reduction = mat_3!((r, c) => f64::from(search.reduction[r][c]));
+        let matrix = mul_33_33(&search.primitive_basis, &reduction);
+        let inverse = inv_f64(&matrix);
+        let to_new = |coords: &Coords| match coords {
+            Coords::Frac(v) => Coords::Frac(pick(v).iter().map(|x| mul_3_33(x, &inverse)).collect()),
+            Coords::Cart(v) => Coords::Cart(pick(v)),
+        };
+
+        let velocities = self.0.velocities.as_ref().filter(|_| {
+            let cart = self.cart_velocities().expect("(BUG) velocities disappeared");
+            zip!(cart.iter(), &mapping).all(|(v, &k)| {
+                let difference = sub_3(v, &cart[representatives[k]]);
+                difference.iter().all(|x| x.abs() <= 1e-8)
+            })
+        }).map(to_new);
+
+        let dynamics = match self.0.dynamics {
+            Some(ref flags) if zip!(flags, &mapping).all(|(f, &k)| *f == flags[representatives[k]]) => {
+                self.transformed_dynamics(FRAC, &inverse).ok().and_then(|x| x).map(|flags| pick_flags(&flags, &representatives))
+            },
+            _ => None,
+        };
+
+        let types = self.site_type_indices();
+        let site_groups: Vec<usize> = {
+            zip!(0.., self.group_counts())
+                .flat_map(|(g, count)| (0..count).map(move |_| g))
+                .collect()
+        };
+        let groups_are_consistent = zip!(&site_groups, &mapping).all(|(&g, &k)| g == site_groups[representatives[k]]);
+        let (group_counts, group_symbols) = if groups_are_consistent {
+            let mut counts = vec![0; self.0.group_counts.len()];
+            for &i in &representatives {
+                counts[site_groups[i]] += 1;
+            }
+            (counts, self.0.group_symbols.clone())
+        } else {
+            let new_types = representatives.iter().map(|&i| types[i]);
+            let (counts, group_types) = crate::builder::get_run_length_encoding(new_types);
+            let symbols = self.type_symbols().map(|symbols| {
+                group_types.iter().map(|&t| symbols[t].clone()).collect()
+            });
+            (counts, symbols)
+        };
+
+        let mut raw = self.clone().into_raw();
+        raw.lattice_vectors = mul_33_33(&matrix, &self.0.lattice_vectors);
+        if let ScaleLine::Volume(volume) = raw.scale {
+            raw.scale = ScaleLine::Volume(volume / search.translations.len() as f64);
+        }
+        raw.positions = to_new(&self.0.positions);
+        raw.velocities = velocities;
+        raw.dynamics = dynamics;
+        raw.group_counts = group_counts;
+        raw.group_symbols = group_symbols;
+        Ok((raw.validate()?, mapping))
+    }
+}
+
+fn pick_flags(flags: &[[bool; 3]], indices: &[usize]) -> Vec<[bool; 3]>
+{ indices.iter().map(|&i| flags[i]).collect() }
+
+#[cfg(test)]
+#[deny(unused)]
+mod tests {
+    use super::*;
+    use crate::Builder;
+    use crate::math::det_f64;
+
+    fn rocksalt() -> Poscar {
+        Builder::new()
+            .lattice_vectors(&[[0.0, 2.8, 2.8], [2.8, 0.0, 2.8], [2.8, 2.8, 0.0]])
+            .site_symbols(vec!["Na", "Cl"])
+            .positions(Coords::Frac(vec![[0.0; 3], [0.5; 3]]))
+            .velocities(Coords::Cart(vec![[0.1, 0.2, 0.3], [-0.3, 0.0, 0.1]]))
+            .build().unwrap()
+    }
+
+    #[test]
+    fn recovers_primitive_cell() {
+        let original = rocksalt();
+        let matrix = [[1, 1, -1], [-1, 1, 1], [1, -1, 1]];
+        let (supercell, supercell_mapping) = original.supercell(&matrix).unwrap();
+        assert_eq!(supercell.num_sites(), 8);
+
+        for storage in [supercell.clone(), supercell.to_cart_storage()] {
+            let (primitive, mapping) = storage.find_primitive(1e-5).unwrap();
+            assert_eq!(primitive.num_sites(), 2);
+            assert_eq!(primitive.group_counts().collect::<Vec<_>>(), vec![1, 1]);
+            assert!((primitive.scaled_volume() - original.scaled_volume()).abs() < 1e-9);
+            assert!(det_f64(&primitive.scaled_lattice()) > 0.0);
+            assert_eq!(primitive.0.positions.tag(), storage.0.positions.tag());
+
+            let expected: Vec<usize> = supercell_mapping.iter().map(|&(i, _)| i).collect();
+            assert_eq!(mapping, expected);
+
+            // the sites are the same as those of the supercell
+            for (i, &k) in mapping.iter().enumerate() {
+                let displacement = crate::geometry::MinImage::new(&primitive.scaled_lattice())
+                    .cart_displacement(&primitive.frac_positions()[k], &mul_3_33(
+                        &storage.scaled_cart_positions()[i],
+                        &inv_f64(&primitive.scaled_lattice()),
+                    ));
+                assert!(displacement.iter().all(|x| x.abs() < 1e-9));
+            }
+            assert_eq!(primitive.cart_velocities().unwrap().to_vec(), original.cart_velocities().unwrap().to_vec());
+        }
+    }
+
+    #[test]
+    fn already_primitive() {
+        let poscar = rocksalt();
+        let (primitive, mapping) = poscar.find_primitive(1e-5).unwrap();
+        assert_eq!(format!("{:?}", primitive), format!("{:?}", poscar));
+        assert_eq!(mapping, vec![0, 1]);
+
+        // species are distinguished by group when there are no symbols
+        let mut raw = poscar.into_raw();
+        raw.group_symbols = None;
+        let poscar = raw.validate().unwrap();
+        assert_eq!(poscar.find_primitive(1e-5).unwrap().0.num_sites(), 2);
+    }
+
+    #[test]
+    fn inconsistent_data() {
+        let (supercell, _) = rocksalt().supercell(&[[2, 0, 0], [0, 1, 0], [0, 0, 1]]).unwrap();
+        let mut raw = supercell.into_raw();
+        raw.scale = ScaleLine::Volume(100.0);
+        // groups that split the images of a site
+        raw.group_counts = vec![1, 1, 2];
+        raw.group_symbols = Some(vec!["Na".into(), "Na".into(), "Cl".into()]);
+        raw.dynamics = Some(vec![[true; 3], [true; 3], [false; 3], [false; 3]]);
+        let poscar = raw.validate().unwrap();
+
+        let (primitive, mapping) = poscar.find_primitive(1e-5).unwrap();
+        assert_eq!(mapping, vec![0, 0, 1, 1]);
+        assert_eq!(primitive.group_counts().collect::<Vec<_>>(), vec![1, 1]);
+        assert_eq!(primitive.group_symbols().unwrap().collect::<Vec<_>>(), vec!["Na", "Cl"]);
+        assert_eq!(primitive.0.scale, ScaleLine::Volume(50.0));
+        assert_eq!(primitive.0.dynamics, Some(vec![[true; 3], [false; 3]]));
+        assert!(primitive.0.velocities.is_some());
+
+        // inconsistent velocities and flags are dropped
+        let mut raw = poscar.into_raw();
+        raw.dynamics = Some(vec![[true; 3], [false; 3], [false; 3], [false; 3]]);
+        raw.velocities = Some(Coords::Cart(vec![[0.0; 3], [1.0, 0.0, 0.0], [0.0; 3], [0.0; 3]]));
+        let (primitive, _) = raw.validate().unwrap().find_primitive(1e-5).unwrap();
+        assert_eq!(primitive.num_sites(), 2);
+        assert_eq!(primitive.0.dynamics, None);
+        assert_eq!(primitive.0.velocities, None);
+    }
+
+    #[test]
+    fn tolerance() {
+        let (supercell, _) = rocksalt().supercell(&[[2, 0, 0], [0, 1, 0], [0, 0, 1]]).unwrap();
+        let mut raw = supercell.into_raw();
+        if let Coords::Frac(ref mut fracs) = raw.positions {
+            fracs[1][0] += 1e-4;
+        }
+        let poscar = raw.validate().unwrap();
+        assert_eq!(poscar.find_primitive(1e-5).unwrap().0.num_sites(), 4);
+        assert_eq!(poscar.find_primitive(1e-2).unwrap().0.num_sites(), 2);
+    }
+}
@@ -0,0 +1,572 @@
+// Copyright 2018 Michael Lamparski
+// Part of the vasp-poscar crate.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Identification of the space group type and its standard setting.
+//!
+//! Starting from the operations in a reduced primitive basis, this:
+//!
+//! 1. determines the crystal system from the rotations,
+//! 2. builds a conventional cell from the symmetry axes,
+//! 3. tries small changes of basis until the rotations and centering match
+//!    those of a standard setting from the table of Hall symbols, and
+//! 4. solves for an origin shift that brings the translations into agreement.
+
+use super::hall::{self, HallGroup, apply_rotation, close_centering, reduce_translation};
+use super::{OperationSearch, TranslationSearch, IDENTITY};
+use crate::math::{adj_i32, det_i32, dot_f64, hermite_normal_form, inv_f64, mul_3_33, mul_33_33, mul_i32_33, norm_3, sub_3};
+use crate::math::transpose_i32_33;
+use crate::reduction::niggli_matrix;
+
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+type Rotation = [[i32; 3]; 3];
+
+#[derive(Debug, Clone)]
+pub(crate) struct Standardization {
+    /// The space group number.
+    pub(crate) number: usize,
+    /// Change of basis from the primitive cell of the search to the standard
+    /// conventional cell.  (`conventional = to_conventional * primitive`)
+    pub(crate) to_conventional: [[i32; 3]; 3],
+    /// The standard origin, in fractional coordinates of the standard conventional cell.
+    pub(crate) origin: [f64; 3],
+}
+
+impl Standardization {
+    /// The matrix `T` such that the conventional lattice is `T * input_lattice`.
+    pub(crate) fn transformation(&self, cell: &TranslationSearch) -> [[f64; 3]; 3]
+    {
+        let to_conventional = mat_3!((r, c) => f64::from(self.to_conventional[r][c]));
+        let reduction = mat_3!((r, c) => f64::from(cell.reduction[r][c]));
+        mul_33_33(&to_conventional, &mul_33_33(&cell.primitive_basis, &reduction))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CrystalSystem { Triclinic, Monoclinic, Orthorhombic, Tetragonal, Trigonal, Hexagonal, Cubic }
+
+pub(crate) fn identify(search: &OperationSearch, symprec: f64) -> Result<Standardization, failure::Error>
+{
+    let ops: Vec<(Rotation, [f64; 3])> = {
+        search.primitive_ops.iter()
+            .map(|op| (op.rotation, op.translation))
+            .collect()
+    };
+    for (a, _) in &ops {
+        for (b, _) in &ops {
+            let product = mul_i32_33(a, b);
+            ensure!(
+                ops.iter().any(|(r, _)| *r == product),
+                "the symmetry operations do not form a group (try a different tolerance)",
+            );
+        }
+    }
+
+    let proper_parts: Vec<(Rotation, u32)> = {
+        ops.iter().map(|(r, _)| {
+            let proper = match det_i32(r) {
+                1 => *r,
+                _ => mat_3!((i, j) => -r[i][j]),
+            };
+            let order = match proper[0][0] + proper[1][1] + proper[2][2] {
+                3 => 1,
+                -1 => 2,
+                0 => 3,
+                1 => 4,
+                2 => 6,
+                _ => bail!("non-crystallographic rotation {:?} (try a different tolerance)", r),
+            };
+            Ok((proper, order))
+        }).collect::<Result<_, failure::Error>>()?
+    };
+    let system = crystal_system(&proper_parts);
+
+    let p = conventional_basis(system, &proper_parts, &search.cell.primitive_lattice)?;
+    let conventional_lattice = mul_33_33(&mat_3!((r, c) => f64::from(p[r][c])), &search.cell.primitive_lattice);
+    let (conventional_ops, centering) = transform_operations(&ops, &p)?;
+
+    // Monoclinic cells may need a different pair of axes in the plane to obtain the
+    // standard centering, so the best of all matching settings is used.
+    let mut best_monoclinic: Option<(MonoclinicCell, Standardization)> = None;
+    for s in alternative_settings() {
+        let (candidate_ops, candidate_centering) = transform_operations(&conventional_ops, s)?;
+        let mut rotations: Vec<Rotation> = candidate_ops.iter().map(|&(r, _)| r).collect();
+        rotations.sort();
+        let candidates = match groups_by_rotations().get(&rotations) {
+            Some(candidates) => candidates,
+            None => continue,
+        };
+
+        // include the centering of the conventional cell
+        let generators: Vec<[f64; 3]> = {
+            candidate_centering.iter().cloned()
+                .chain(centering.iter().map(|c| mul_3_33(c, &inv_int(s))))
+                .collect()
+        };
+        let candidate_centering = close_centering(&generators);
+        let lattice = mul_33_33(&mat_3!((r, c) => f64::from(s[r][c])), &conventional_lattice);
+        for &index in candidates {
+            let group = &hall::standard_groups()[index];
+            if !same_translations(&group.centering, &candidate_centering) {
+                continue;
+            }
+            if let Some(origin) = solve_origin(&candidate_ops, group, &lattice, symprec) {
+                let standardization = Standardization {
+                    number: index + 1,
+                    to_conventional: mul_i32_33(s, &p),
+                    origin,
+                };
+                if system != CrystalSystem::Monoclinic {
+                    return Ok(standardization);
+                }
+                let cell = MonoclinicCell::new(&lattice, symprec);
+                if best_monoclinic.as_ref().is_none_or(|(best, _)| cell.is_better_than(best, symprec)) {
+                    best_monoclinic = Some((cell, standardization));
+                }
+                break;
+            }
+        }
+    }
+    if let Some((_, standardization)) = best_monoclinic {
+        return Ok(standardization);
+    }
+    bail!("could not identify the space group (try a different tolerance)")
+}
+
+/// The properties of a monoclinic cell (unique axis b) that decide between settings.
+struct MonoclinicCell {
+    acute: bool,
+    length: f64,
+}
+
+impl MonoclinicCell {
+    fn new(lattice: &[[f64; 3]; 3], symprec: f64) -> Self
+    {
+        let length = norm_3(&lattice[0]) + norm_3(&lattice[2]);
+        MonoclinicCell {
+            acute: dot_f64(&lattice[0], &lattice[2]) > symprec * length,
+            length,
+        }
+    }
+
+    /// Prefer an obtuse (or right) beta, then the shortest a and c.
+    fn is_better_than(&self, other: &MonoclinicCell, symprec: f64) -> bool
+    {
+        match (self.acute, other.acute) {
+            (false, true) => true,
+            (true, false) => false,
+            _ => self.length < other.length - symprec,
+        }
+    }
+}
+
+fn crystal_system(proper_parts: &[(Rotation, u32)]) -> CrystalSystem
+{
+    let count = |order| proper_parts.iter().filter(|&&(_, n)| n == order).count();
+    let mut two_fold: Vec<Rotation> = proper_parts.iter().filter(|&&(_, n)| n == 2).map(|&(r, _)| r).collect();
+    two_fold.sort();
+    two_fold.dedup();
+
+    if count(3) >= 8 {
+        CrystalSystem::Cubic
+    } else if count(6) > 0 {
+        CrystalSystem::Hexagonal
+    } else if count(3) > 0 {
+        CrystalSystem::Trigonal
+    } else if count(4) > 0 {
+        CrystalSystem::Tetragonal
+    } else if two_fold.len() >= 3 {
+        CrystalSystem::Orthorhombic
+    } else if !two_fold.is_empty() {
+        CrystalSystem::Monoclinic
+    } else {
+        CrystalSystem::Triclinic
+    }
+}
+
+/// Choose the conventional cell from the symmetry axes.
+///
+/// The output is `p` such that `conventional = p * primitive`, with `det(p) > 0`.
+fn conventional_basis(
+    system: CrystalSystem,
+    proper_parts: &[(Rotation, u32)],
+    lattice: &[[f64; 3]; 3],
+) -> Result<[[i32; 3]; 3], failure::Error>
+{
+    let to_cart = |v: &[i32; 3]| mul_3_33(&arr_3![k => f64::from(v[k])], lattice);
+    let length = |v: &[i32; 3]| norm_3(&to_cart(v));
+    let rotation_of_order = |order| {
+        proper_parts.iter()
+            .find(|&&(_, n)| n == order)
+            .map(|&(r, _)| r)
+            .expect("(BUG) missing rotation")
+    };
+    let axes_of_order = |order| {
+        let mut axes: Vec<[i32; 3]> = {
+            proper_parts.iter()
+                .filter(|&&(_, n)| n == order)
+                .map(|&(r, n)| rotation_axis(&r, n))
+                .collect()
+        };
+        axes.sort();
+        axes.dedup();
+        axes
+    };
+    // Lattice vectors perpendicular to an axis, sorted by length.
+    let in_plane = |rotation: &Rotation, order: u32| {
+        let sum = power_sum(rotation, order);
+        let mut vectors = vec![];
+        for a in -3..=3 {
+            for b in -3..=3 {
+                for c in -3..=3 {
+                    let v = [a, b, c];
+                    if v != [0; 3] && apply_int(&sum, &v) == [0; 3] {
+                        vectors.push(v);
+                    }
+                }
+            }
+        }
+        vectors.sort_by(|a, b| length(a).partial_cmp(&length(b)).expect("(BUG) NaN").then(b.cmp(a)));
+        vectors
+    };
+    let det = |m: &[[i32; 3]; 3]| det_i32(m);
+    let negate = |v: [i32; 3]| arr_3![k => -v[k]];
+
+    let p = match system {
+        CrystalSystem::Triclinic => niggli_matrix(lattice, 1e-5),
+        CrystalSystem::Monoclinic => {
+            let rotation = rotation_of_order(2);
+            let b = rotation_axis(&rotation, 2);
+            let plane = in_plane(&rotation, 2);
+            let a = plane[0];
+            let c = {
+                *plane.iter()
+                    .find(|w| gcd_3(&cross_int(&a, w)) == 1)
+                    .ok_or_else(|| format_err!("(BUG) no basis for monoclinic plane"))?
+            };
+            let c = if det(&[a, b, c]) < 0 { negate(c) } else { c };
+            // make beta obtuse (a and c are a reduced basis of the plane, so this
+            // choice of signs is all that remains)
+            let dot = dot_f64(&to_cart(&a), &to_cart(&c));
+            if dot > 0.0 { [negate(a), negate(b), c] } else { [a, b, c] }
+        },
+        CrystalSystem::Orthorhombic | CrystalSystem::Cubic => {
+            let mut axes = axes_of_order(4);
+            if system == CrystalSystem::Orthorhombic || axes.is_empty() {
+                axes = axes_of_order(2);
+            }
+            ensure!(axes.len() == 3, "(BUG) expected three axes, found {:?}", axes);
+            axes.sort_by(|a, b| length(a).partial_cmp(&length(b)).expect("(BUG) NaN"));
+            let p = [axes[0], axes[1], axes[2]];
+            if det(&p) < 0 { [axes[0], axes[1], negate(axes[2])] } else { p }
+        },
+        CrystalSystem::Tetragonal => {
+            let rotation = rotation_of_order(4);
+            let c = rotation_axis(&rotation, 4);
+            let a = in_plane(&rotation, 4)[0];
+            let b = apply_int(&rotation, &a);
+            let p = [a, b, c];
+            if det(&p) < 0 { [a, negate(b), c] } else { p }
+        },
+        CrystalSystem::Trigonal | CrystalSystem::Hexagonal => {
+            let rotation = rotation_of_order(3);
+            let c = rotation_axis(&rotation, 3);
+            let a = in_plane(&rotation, 3)[0];
+            let b = apply_int(&rotation, &a);
+            let p = [a, b, c];
+            if det(&p) < 0 { [a, apply_int(&rotation, &b), c] } else { p }
+        },
+    };
+    assert!(det(&p) > 0, "(BUG) bad conventional basis {:?}", p);
+    Ok(p)
+}
+
+/// Re-express operations and the lattice translations in the basis `m * old`, where
+/// `m` is an integer matrix with positive determinant.
+///
+/// Produces the new operations, and the translations of the old lattice reduced into
+/// the new cell (i.e. the centering vectors, if `m` is not unimodular).
+#[allow(clippy::type_complexity)]
+fn transform_operations(
+    ops: &[(Rotation, [f64; 3])],
+    m: &[[i32; 3]; 3],
+) -> Result<(Vec<(Rotation, [f64; 3])>, Vec<[f64; 3]>), failure::Error>
+{
+    // new_frac = inv(m)^T * old_frac  (as columns)
+    let det = det_i32(m);
+    let adj = adj_i32(m);
+    let m_t = transpose_i32_33(m);
+    let new_ops = ops.iter().map(|(rotation, translation)| {
+        // inv(m)^T * rotation * m^T, computed exactly
+        let product = mul_i64_33(&transpose_i64_33(&adj), &mul_i64_33(&to_i64_33(rotation), &to_i64_33(&m_t)));
+        ensure!(
+            product.iter().flatten().all(|x| x % det == 0),
+            "the symmetry operations are inconsistent with the conventional cell (try a different tolerance)",
+        );
+        let rotation = mat_3!((r, c) => (product[r][c] / det) as i32);
+        let translation = reduce_translation(&mul_3_33(translation, &inv_int(m)));
+        Ok((rotation, translation))
+    }).collect::<Result<_, failure::Error>>()?;
+
+    let inv = inv_int(m);
+    let centering = close_centering(&inv);
+    Ok((new_ops, centering))
+}
+
+/// Solve for the origin shift `p` (in fractional coordinates of `lattice`) that brings
+/// the operations into agreement with those of `group`; i.e. such that
+/// `t + (R - I) p ≡ t_std` modulo the centering lattice, for every operation.
+fn solve_origin(
+    ops: &[(Rotation, [f64; 3])],
+    group: &HallGroup,
+    lattice: &[[f64; 3]; 3],
+    symprec: f64,
+) -> Option<[f64; 3]>
+{
+    // Basis of the lattice of translations (as columns), which contains Z^3.
+    let rows: Vec<[i64; 3]> = {
+        group.centering.iter()
+            .map(|c| arr_3![k => (6.0 * c[k]).round() as i64])
+            .chain((0..3).map(|k| arr_3![j => if j == k { 6 } else { 0 }]))
+            .collect()
+    };
+    let hnf = hermite_normal_form(&rows);
+    let q = mat_3!((r, c) => hnf[c][r] as f64 / 6.0);
+    let q_inv = inv_f64(&q);
+
+    // In the basis q, the equations are an integer system modulo Z^3.
+    let std_translation = |rotation: &Rotation| {
+        group.ops.iter().find(|(r, _)| r == rotation).map(|&(_, t)| t)
+    };
+    let mut a = vec![];
+    let mut b = vec![];
+    for (rotation, translation) in ops {
+        let diff = sub_3(&std_translation(rotation)?, translation);
+        let r_minus_i = mat_3!((r, c) => f64::from(rotation[r][c] - IDENTITY[r][c]));
+        let coeffs = mul_33_33(&q_inv, &mul_33_33(&r_minus_i, &q));
+        for (row, q_inv_row) in zip!(&coeffs, &q_inv) {
+            a.push(arr_3![c => row[c].round() as i64]);
+            b.push((0..3).map(|k| q_inv_row[k] * diff[k]).sum::<f64>());
+        }
+    }
+
+    let v = diagonalize(&mut a, &mut b);
+    let solution = arr_3![k => if a[k][k] != 0 { b[k] / a[k][k] as f64 } else { 0.0 }];
+    let solution = arr_3![r => (0..3).map(|c| v[r][c] as f64 * solution[c]).sum::<f64>()];
+    let origin = arr_3![r => (0..3).map(|c| q[r][c] * solution[c]).sum::<f64>()];
+
+    // verify
+    let tol = 3.0 * symprec;
+    for (rotation, translation) in ops {
+        let shifted = apply_rotation(rotation, &origin);
+        let residual = arr_3![k => translation[k] + shifted[k] - origin[k] - std_translation(rotation)?[k]];
+        let ok = group.centering.iter().any(|c| {
+            let diff = sub_3(&residual, c);
+            let diff = arr_3![k => diff[k] - diff[k].round()];
+            norm_3(&mul_3_33(&diff, lattice)) <= tol
+        });
+        if !ok {
+            return None;
+        }
+    }
+    Some(reduce_translation(&origin))
+}
+
+/// Reduce an integer matrix to diagonal form using row and column operations,
+/// applying the row operations to `b`.  Returns the accumulated column operations `v`,
+/// such that the output `a` is `u * input_a * v` for some unimodular `u`.
+fn diagonalize(a: &mut [[i64; 3]], b: &mut [f64]) -> [[i64; 3]; 3]
+{
+    let mut v = [[1, 0, 0], [0, 1, 0], [0, 0, 1]];
+    for k in 0..3 {
+        loop {
+            let pivot = {
+                (k..a.len())
+                    .flat_map(|r| (k..3).map(move |c| (r, c)))
+                    .filter(|&(r, c)| a[r][c] != 0)
+                    .min_by_key(|&(r, c)| a[r][c].abs())
+            };
+            let (pivot_row, pivot_col) = match pivot {
+                Some(pivot) => pivot,
+                None => return v,
+            };
+            a.swap(k, pivot_row);
+            b.swap(k, pivot_row);
+            for row in a.iter_mut().chain(v.iter_mut()) {
+                row.swap(k, pivot_col);
+            }
+
+            let mut done = true;
+            let (above, below) = a.split_at_mut(k + 1);
+            let (b_above, b_below) = b.split_at_mut(k + 1);
+            for (row, b_row) in zip!(below, b_below) {
+                let q = row[k] / above[k][k];
+                for (x, p) in zip!(row.iter_mut(), &above[k]) {
+                    *x -= q * p;
+                }
+                *b_row -= q as f64 * b_above[k];
+                done &= row[k] == 0;
+            }
+            for c in k + 1..3 {
+                let q = a[k][c] / a[k][k];
+                for row in a.iter_mut().chain(v.iter_mut()) {
+                    row[c] -= q * row[k];
+                }
+                done &= a[k][c] == 0;
+            }
+            if done {
+                break;
+            }
+        }
+    }
+    v
+}
+
+/// Unimodular changes of basis with entries in `{-1, 0, 1}`, simplest first.
+pub(crate) fn alternative_settings() -> &'static [[[i32; 3]; 3]]
+{
+    static SETTINGS: OnceLock<Vec<[[i32; 3]; 3]>> = OnceLock::new();
+    SETTINGS.get_or_init(|| {
+        let mut out = vec![];
+        for code in 0..3i32.pow(9) {
+            let m = mat_3!((r, c) => (code / 3i32.pow(3 * r as u32 + c as u32)) % 3 - 1);
+            if det_i32(&m) == 1 {
+                out.push(m);
+            }
+        }
+        let count = |m: &[[i32; 3]; 3], pred: fn(i32) -> bool| m.iter().flatten().filter(|&&x| pred(x)).count();
+        out.sort_by_key(|m| (count(m, |x| x != 0), count(m, |x| x < 0), std::cmp::Reverse(*m)));
+        out
+    })
+}
+
+/// Indices of the standard groups, keyed by their sorted rotations.
+fn groups_by_rotations() -> &'static HashMap<Vec<Rotation>, Vec<usize>>
+{
+    static MAP: OnceLock<HashMap<Vec<Rotation>, Vec<usize>>> = OnceLock::new();
+    MAP.get_or_init(|| {
+        let mut map = HashMap::new();
+        for (index, group) in hall::standard_groups().iter().enumerate() {
+            let rotations = group.ops.iter().map(|&(r, _)| r).collect::<Vec<_>>();
+            map.entry(rotations).or_insert_with(Vec::new).push(index);
+        }
+        map
+    })
+}
+
+fn same_translations(a: &[[f64; 3]], b: &[[f64; 3]]) -> bool
+{
+    a.len() == b.len() && a.iter().all(|x| b.iter().any(|y| hall::translations_equal(x, y)))
+}
+
+/// Direction of the axis of a proper rotation, as the shortest lattice vector along it.
+fn rotation_axis(rotation: &Rotation, order: u32) -> [i32; 3]
+{
+    // the sum of all powers projects onto the axis (up to a factor)
+    let sum = power_sum(rotation, order);
+    let column = {
+        (0..3)
+            .map(|c| arr_3![r => sum[r][c]])
+            .find(|v| *v != [0; 3])
+            .expect("(BUG) rotation has no axis")
+    };
+    let g = gcd_3(&column);
+    let axis = arr_3![k => column[k] / g];
+    match axis.iter().find(|&&x| x != 0) {
+        Some(&x) if x < 0 => arr_3![k => -axis[k]],
+        _ => axis,
+    }
+}
+
+fn power_sum(rotation: &Rotation, order: u32) -> Rotation
+{
+    let mut sum = [[0; 3]; 3];
+    let mut power = IDENTITY;
+    for _ in 0..order {
+        sum = mat_3!((r, c) => sum[r][c] + power[r][c]);
+        power = mul_i32_33(rotation, &power);
+    }
+    sum
+}
+
+fn apply_int(m: &Rotation, v: &[i32; 3]) -> [i32; 3]
+{ arr_3![r => (0..3).map(|c| m[r][c] * v[c]).sum()] }
+
+fn cross_int(a: &[i32; 3], b: &[i32; 3]) -> [i32; 3]
+{ arr_3![k => a[(k + 1) % 3] * b[(k + 2) % 3] - a[(k + 2) % 3] * b[(k + 1) % 3]] }
+
+fn gcd_3(v: &[i32; 3]) -> i32
+{
+    fn gcd(a: i32, b: i32) -> i32 { if b == 0 { a.abs() } else { gcd(b, a % b) } }
+    gcd(gcd(v[0], v[1]), v[2])
+}
+
+fn inv_int(m: &[[i32; 3]; 3]) -> [[f64; 3]; 3]
+{ inv_f64(&mat_3!((r, c) => f64::from(m[r][c]))) }
+
+fn to_i64_33(m: &[[i32; 3]; 3]) -> [[i64; 3]; 3]
+{ mat_3!((r, c) => i64::from(m[r][c])) }
+
+fn transpose_i64_33(m: &[[i64; 3]; 3]) -> [[i64; 3]; 3]
+{ mat_3!((r, c) => m[c][r]) }
+
+fn mul_i64_33(a: &[[i64; 3]; 3], b: &[[i64; 3]; 3]) -> [[i64; 3]; 3]
+{ mat_3!((r, c) => (0..3).map(|k| a[r][k] * b[k][c]).sum()) }
+
+#[cfg(test)]
+#[deny(unused)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings() {
+        let settings = alternative_settings();
+        assert_eq!(settings[0], IDENTITY);
+        assert!(settings.iter().all(|m| det_i32(m) == 1));
+    }
+
+    #[test]
+    fn axes() {
+        let three_fold = [[0, -1, 0], [1, -1, 0], [0, 0, 1]];
+        assert_eq!(rotation_axis(&three_fold, 3), [0, 0, 1]);
+        let body_diagonal = [[0, 0, 1], [1, 0, 0], [0, 1, 0]];
+        assert_eq!(rotation_axis(&body_diagonal, 3), [1, 1, 1]);
+        let two_fold = [[0, -1, 0], [-1, 0, 0], [0, 0, -1]];
+        assert_eq!(rotation_axis(&two_fold, 2), [1, -1, 0]);
+    }
+
+    #[test]
+    fn origin() {
+        // P2_1/c with its origin moved by (0.1, 0.2, 0.3)
+        let group = hall::parse_hall("-P 2ybc").unwrap();
+        let shift = [0.1, 0.2, 0.3];
+        let ops: Vec<_> = group.ops.iter().map(|(rotation, translation)| {
+            // x' = x - shift
+            let moved = apply_rotation(rotation, &shift);
+            (*rotation, reduce_translation(&arr_3![k => translation[k] + moved[k] - shift[k]]))
+        }).collect();
+        let lattice = [[4.0, 0.0, 0.0], [0.0, 5.0, 0.0], [-1.0, 0.0, 6.0]];
+        let origin = solve_origin(&ops, &group, &lattice, 1e-5).unwrap();
+
+        // the solution is only unique up to the normalizer; check that it works
+        for (rotation, translation) in &ops {
+            let moved = apply_rotation(rotation, &origin);
+            let t = arr_3![k => translation[k] + moved[k] - origin[k]];
+            let &(_, expected) = group.ops.iter().find(|(r, _)| r == rotation).unwrap();
+            assert!(hall::translations_equal(&t, &expected));
+        }
+
+        // a shift along the screw axis doesn't turn P2_1 into P2
+        let screw = hall::parse_hall("P 2yb").unwrap();
+        let plain = hall::parse_hall("P 2y").unwrap();
+        assert!(solve_origin(&screw.ops, &plain, &lattice, 1e-5).is_none());
+
+    }
+}
@@ -9,6 +9,7 @@
 
 use crate::math::{inv_f64, det_f64};
 use std::borrow::{Cow};
+use crate::LatticeParams;
 
 /// Represents a POSCAR file.
 ///
@@ -237,7 +238,7 @@ impl Poscar {
     // by to properly account for the scale line.
     //
     // This quantity is non-negative, but may be infinite.
-    fn effective_scale_factor(&self) -> f64
+    pub(crate) fn effective_scale_factor(&self) -> f64
     { match self.0.scale {
         ScaleLine::Factor(f) => f,
         ScaleLine::Volume(v) => (v / self.unscaled_determinant().abs()).cbrt(),
@@ -307,6 +308,53 @@ impl Poscar {
     })}
 }
 
+/// # Changing the coordinate system
+impl Poscar {
+    /// Get a copy of this Poscar with positions and velocities written in
+    /// direct (fractional) coordinates.
+    ///
+    /// The scale line and lattice are left untouched, so the structure
+    /// described by the output is the same.
+    ///
+    /// ```rust
+    /// # fn main() -> Result<(), failure::Error> {Ok({
+    /// use vasp_poscar::{Builder, Coords, ScaleLine};
+    ///
+    /// let poscar =
+    ///     Builder::new()
+    ///     .scale(ScaleLine::Factor(3.0))
+    ///     .lattice_vectors(&[[2.0, 0.0, 0.0], [0.0, 2.0, 0.0], [0.0, 0.0, 2.0]])
+    ///     .positions(Coords::Cart(vec![[1.0, 0.5, 0.0]]))
+    ///     .build()?;
+    ///
+    /// let poscar = poscar.to_frac_storage();
+    /// assert_eq!(
+    ///     poscar.into_raw().positions,
+    ///     Coords::Frac(vec![[0.5, 0.25, 0.0]]),
+    /// );
+    /// # })}
+    /// ```
+    pub fn to_frac_storage(&self) -> Poscar
+    { self.to_storage(FRAC) }
+
+    /// Get a copy of this Poscar with positions and velocities written in
+    /// Cartesian coordinates.
+    ///
+    /// The scale line and lattice are left untouched, so the Cartesian data
+    /// is written unscaled (as is required by the format).
+    pub fn to_cart_storage(&self) -> Poscar
+    { self.to_storage(CART) }
+
+    fn to_storage(&self, tag: CoordsTag) -> Poscar
+    {
+        let lattice = self.unscaled_lattice();
+        let mut raw = self.0.clone();
+        raw.positions = raw.positions.into_tag(&lattice, tag);
+        raw.velocities = raw.velocities.map(|v| v.into_tag(&lattice, tag));
+        Poscar(raw)
+    }
+}
+
 // Accessing the lattice matrix.
 //
 // NOTE: These are not exposed because the crate deliberately tries to
@@ -319,16 +367,150 @@ impl Poscar {
 // * our formalism is row-based (nearly all vectors are formally row vectors)
 // * our storage is row-major (`matrix[i]` conceptually yields a row vector)
 impl Poscar {
-    fn unscaled_lattice(&self) -> [[f64; 3]; 3]
+    pub(crate) fn unscaled_lattice(&self) -> [[f64; 3]; 3]
     { self.0.lattice_vectors }
 
-    fn scaled_lattice(&self) -> [[f64; 3]; 3]
+    pub(crate) fn scaled_lattice(&self) -> [[f64; 3]; 3]
     {
         let f = self.effective_scale_factor();
         crate::math::scale_33(&self.0.lattice_vectors, f).0
     }
 }
 
+// Helpers for operations that modify the structure.
+//
+// These are phrased in terms of the scaled lattice and fractional positions,
+// and take care of writing the data back in whatever form was originally
+// stored (i.e. they preserve the kind of scale line and the Cart/Frac choice).
+impl Poscar {
+    /// Replace the positions, keeping the currently stored representation.
+    pub(crate) fn set_frac_positions(&mut self, fracs: Vec<[f64; 3]>)
+    {
+        let tag = self.0.positions.tag();
+        self.0.positions = Coords::Frac(fracs).into_tag(&self.unscaled_lattice(), tag);
+    }
+
+    /// Reorder the sites so that site `i` of the output is site `perm[i]` of
+    /// the input, recomputing the groups from the site types.
+    ///
+    /// Consecutive sites with the same type are merged into a single group.
+    pub(crate) fn permute_sites(&mut self, perm: &[usize])
+    {
+        assert_eq!(perm.len(), self.num_sites(), "(BUG) wrong permutation length");
+
+        let types = self.site_type_indices();
+        let type_symbols = self.type_symbols();
+        let new_types = perm.iter().map(|&i| types[i]);
+        let (counts, groups) = crate::builder::get_run_length_encoding(new_types);
+
+        let permute = |xs: &[[f64; 3]]| perm.iter().map(|&i| xs[i]).collect::<Vec<_>>();
+        self.0.positions = self.0.positions.as_ref().map(|v| permute(v));
+        self.0.velocities = self.0.velocities.as_ref().map(|c| c.as_ref().map(|v| permute(v)));
+        self.0.dynamics = self.0.dynamics.as_ref().map(|d| perm.iter().map(|&i| d[i]).collect());
+        self.0.group_symbols = type_symbols.map(|syms| {
+            groups.iter().map(|&t| syms[t].clone()).collect()
+        });
+        self.0.group_counts = counts;
+    }
+
+    /// Get an index for each site identifying its type.
+    ///
+    /// When symbols are present, sites with the same symbol have the same type
+    /// even if they belong to different groups.  Otherwise, each group is
+    /// its own type.  Types are numbered in order of first appearance.
+    pub(crate) fn site_type_indices(&self) -> Vec<usize>
+    {
+        let group_types: Vec<usize> = match self.0.group_symbols {
+            None => (0..self.0.group_counts.len()).collect(),
+            Some(ref syms) => {
+                let mut unique: Vec<&str> = vec![];
+                syms.iter().map(|sym| {
+                    match unique.iter().position(|&u| u == sym) {
+                        Some(t) => t,
+                        None => { unique.push(sym); unique.len() - 1 },
+                    }
+                }).collect()
+            },
+        };
+        zip!(&self.0.group_counts, group_types)
+            .flat_map(|(&count, t)| (0..count).map(move |_| t))
+            .collect()
+    }
+
+    /// Get a label for the species of each site.
+    ///
+    /// This is the symbol when symbols are present, and otherwise the (zero-based)
+    /// index of the group, written in decimal.  Since symbols may not begin with
+    /// a digit, the two kinds of labels can never be confused.
+    pub(crate) fn site_species_labels(&self) -> Vec<String>
+    {
+        let group_labels: Vec<String> = match self.0.group_symbols {
+            Some(ref syms) => syms.clone(),
+            None => (0..self.0.group_counts.len()).map(|g| g.to_string()).collect(),
+        };
+        zip!(&self.0.group_counts, group_labels)
+            .flat_map(|(&count, label)| (0..count).map(move |_| label.clone()))
+            .collect()
+    }
+
+    /// Get the symbol for each type in `site_type_indices`, if symbols are present.
+    pub(crate) fn type_symbols(&self) -> Option<Vec<String>>
+    {
+        self.0.group_symbols.as_ref().map(|syms| {
+            let mut unique: Vec<String> = vec![];
+            for sym in syms {
+                if !unique.contains(sym) {
+                    unique.push(sym.clone());
+                }
+            }
+            unique
+        })
+    }
+
+    /// Get the selective dynamics flags as they should be after the stored data
+    /// of kind `tag` undergoes the linear transformation `new = old * matrix`.
+    ///
+    /// Flags constrain individual components of the stored positions, so they can
+    /// only be carried over if the transformation is a signed permutation, or if
+    /// each site's flags are all the same.  Otherwise, this fails.
+    pub(crate) fn transformed_dynamics(
+        &self,
+        tag: CoordsTag,
+        matrix: &[[f64; 3]; 3],
+    ) -> Result<Option<Vec<[bool; 3]>>, failure::Error>
+    {
+        let dynamics = match self.0.dynamics {
+            Some(ref dynamics) => dynamics,
+            None => return Ok(None),
+        };
+        if self.0.positions.tag() != tag {
+            return Ok(Some(dynamics.clone()));
+        }
+
+        // For a signed permutation, new axis `k` comes from the old axis `j` with `matrix[j][k] != 0`.
+        let is_nonzero = |x: f64| x.abs() > 1e-12;
+        let source_axes: Option<Vec<usize>> = {
+            (0..3).map(|k| {
+                let nonzero: Vec<_> = (0..3).filter(|&j| is_nonzero(matrix[j][k])).collect();
+                match nonzero[..] {
+                    [j] => Some(j),
+                    _ => None,
+                }
+            }).collect()
+        };
+
+        dynamics.iter().map(|&flags| {
+            if let Some(ref axes) = source_axes {
+                Ok(arr_3![k => flags[axes[k]]])
+            } else if flags[0] == flags[1] && flags[1] == flags[2] {
+                Ok(flags)
+            } else {
+                bail!("selective dynamics {:?} cannot be expressed in the new coordinates", flags)
+            }
+        }).collect::<Result<_, _>>().map(Some)
+    }
+}
+
 /// Unencumbered `struct` form of a Poscar with public data members.
 ///
 /// This is basically the [`Poscar`] type, minus all the type-protected
@@ -496,6 +678,12 @@ pub enum ValidationError {
     #[fail(display = "member '{}' is wrong length (should be {})", _0, _1)]
     WrongLength(&'static str, usize),
 
+    /// The lattice parameters given to [`Builder::lattice_parameters`] do not describe a valid cell.
+    ///
+    /// [`Builder::lattice_parameters`]: builder/struct.Builder.html#method.lattice_parameters
+    #[fail(display = "lattice parameters do not describe a valid cell: {:?}", _0)]
+    BadLatticeParams(LatticeParams),
+
     /// INIT in predictor corrector is zero. (you should use `None` instead)
     #[allow(unused)] // FIXME
     #[doc(hidden)]
@@ -617,23 +805,59 @@ pub(crate) const CART: CoordsTag = Coords::Cart(());
 pub(crate) const FRAC: CoordsTag = Coords::Frac(());
 
 impl<V> Coords<V> {
+    /// Get a `Coords<()>` that only records which variant this is.
     #[inline(always)]
-    pub(crate) fn tag(&self) -> CoordsTag
+    pub fn tag(&self) -> Coords<()>
     { self.as_ref().map(|_| ()) }
 
+    /// Wrap a value in the same variant as a tag produced by [`tag`].
+    ///
+    /// [`tag`]: #method.tag
     #[inline(always)]
-    pub(crate) fn of_tag(tag: CoordsTag, value: V) -> Coords<V>
+    pub fn of_tag(tag: Coords<()>, value: V) -> Coords<V>
     { tag.map(|()| value) }
 }
 
 impl Coords {
-    /// Convert into a specific Coord representations on demand.
+    /// Convert into a specific Coord representation on demand.
+    ///
+    /// `lattice` is the lattice that the data is expressed against, and `tag`
+    /// selects the desired output representation (`Coords::Cart(())` or
+    /// `Coords::Frac(())`).
     ///
     /// May return a borrow if that data is immediately available.
     ///
     /// This may compute a lattice inverse; don't use it in a tight loop.
+    ///
+    /// # Which lattice?
+    ///
+    /// Cartesian data in a POSCAR file is written *unscaled*, so to convert
+    /// between the positions and velocities stored in a [`Poscar`] you should
+    /// use [`Poscar::unscaled_lattice_vectors`].  To obtain true Cartesian
+    /// coordinates from fractional coordinates, use [`Poscar::scaled_lattice_vectors`].
+    ///
+    /// ```rust
+    /// # fn main() -> Result<(), failure::Error> {Ok({
+    /// use vasp_poscar::{Builder, Coords, ScaleLine};
+    ///
+    /// let poscar =
+    ///     Builder::new()
+    ///     .scale(ScaleLine::Factor(2.0))
+    ///     .lattice_vectors(&[[2.0, 0.0, 0.0], [0.0, 4.0, 0.0], [0.0, 0.0, 8.0]])
+    ///     .positions(Coords::Frac(vec![[0.5, 0.5, 0.5]]))
+    ///     .build()?;
+    ///
+    /// let carts = Coords::Cart(vec![[2.0, 2.0, 2.0]]);
+    /// let fracs = carts.to_tag(&poscar.scaled_lattice_vectors(), Coords::Frac(()));
+    /// assert_eq!(&fracs[..], &[[0.5, 0.25, 0.125]]);
+    /// # })}
+    /// ```
+    ///
+    /// [`Poscar`]: struct.Poscar.html
+    /// [`Poscar::unscaled_lattice_vectors`]: struct.Poscar.html#method.unscaled_lattice_vectors
+    /// [`Poscar::scaled_lattice_vectors`]: struct.Poscar.html#method.scaled_lattice_vectors
     #[inline(always)]
-    pub(crate) fn to_tag(&self, lattice: &[[f64; 3]; 3], tag: Coords<()>) -> Cow<'_, [[f64; 3]]>
+    pub fn to_tag(&self, lattice: &[[f64; 3]; 3], tag: Coords<()>) -> Cow<'_, [[f64; 3]]>
     {
         use self::Coords::{Cart, Frac};
         match (self.as_ref(), tag) {
@@ -646,36 +870,47 @@ impl Coords {
             (Cart(v), FRAC) => crate::math::mul_n3_33(v, &inv_f64(lattice)).into(),
         }
     }
+
+    /// Like [`to_tag`], but produces an owned `Coords` of the requested variant.
+    ///
+    /// [`to_tag`]: #method.to_tag
+    pub fn into_tag(self, lattice: &[[f64; 3]; 3], tag: Coords<()>) -> Coords
+    {
+        if self.tag() == tag {
+            return self;
+        }
+        Coords::of_tag(tag, self.to_tag(lattice, tag).into_owned())
+    }
 }
 
 // --------------------------------
 // Helpers
 
 impl<A> Coords<A> {
-    #[allow(unused)]
-    pub(crate) fn map<B, F>(self, f: F) -> Coords<B>
+    /// Apply a function to the contained value, preserving the variant.
+    pub fn map<B, F>(self, f: F) -> Coords<B>
     where F: FnOnce(A) -> B,
     { match self {
         Coords::Cart(x) => Coords::Cart(f(x)),
         Coords::Frac(x) => Coords::Frac(f(x)),
     }}
 
-    #[allow(unused)]
-    pub(crate) fn as_ref(&self) -> Coords<&A>
+    /// Borrow the contained value, preserving the variant.
+    pub fn as_ref(&self) -> Coords<&A>
     { match *self {
         Coords::Cart(ref x) => Coords::Cart(x),
         Coords::Frac(ref x) => Coords::Frac(x),
     }}
 
-    #[allow(unused)]
-    pub(crate) fn as_mut(&mut self) -> Coords<&mut A>
+    /// Mutably borrow the contained value, preserving the variant.
+    pub fn as_mut(&mut self) -> Coords<&mut A>
     { match *self {
         Coords::Cart(ref mut x) => Coords::Cart(x),
         Coords::Frac(ref mut x) => Coords::Frac(x),
     }}
 
-    #[allow(unused)]
-    pub(crate) fn raw(self) -> A
+    /// Get the contained value, regardless of variant.
+    pub fn raw(self) -> A
     { match self {
         Coords::Cart(x) => x,
         Coords::Frac(x) => x,
@@ -878,4 +1113,46 @@ mod accessor_tests {
             }
         }
     }
+
+    #[test]
+    fn storage_conversion() {
+        const LATTICE: [[f64; 3]; 3] = [
+            [-4.0,  2.0, -4.0],
+            [ 2.0, -6.0,  6.0],
+            [-2.0, -2.0,  0.0],
+        ];
+        const FRACS: &[[f64; 3]] = &[
+            [ 0.0 ,  0.25, 0.75 ],
+            [ 0.25, -2.25, 3.125],
+        ];
+        const CARTS: &[[f64; 3]] = &[
+            [ -1.0 , -3.0 ,   1.5],
+            [-11.75,  7.75, -14.5],
+        ];
+
+        for &scale in &[ScaleLine::Factor(2.0), ScaleLine::Volume(64.0)] {
+            let frac =
+                Builder::new()
+                .scale(scale)
+                .lattice_vectors(&LATTICE)
+                .positions(Coords::Frac(FRACS))
+                .velocities(Coords::Frac(FRACS))
+                .build().unwrap();
+
+            let cart = frac.to_cart_storage();
+            assert_eq!(cart.scaled_volume(), frac.scaled_volume());
+            assert_eq!(cart.0.scale, scale);
+            assert_eq!(cart.0.positions, Coords::Cart(CARTS.to_vec()));
+            assert_eq!(cart.0.velocities, Some(Coords::Cart(CARTS.to_vec())));
+            assert_eq!(cart.scaled_cart_positions(), frac.scaled_cart_positions());
+
+            let back = cart.to_frac_storage();
+            assert_eq!(back.0.positions, Coords::Frac(FRACS.to_vec()));
+            assert_eq!(back.0.velocities, Some(Coords::Frac(FRACS.to_vec())));
+
+            // no-op conversions
+            assert_eq!(frac.to_frac_storage().0.positions, frac.0.positions);
+            assert_eq!(cart.to_cart_storage().0.positions, cart.0.positions);
+        }
+    }
 }
@@ -8,12 +8,12 @@
 // except according to those terms.
 
 // general bail! and ensure! macros that don't constrain the type to failure::Error
-macro_rules! g_bail { ($e:expr $(,)*) => { return Err($e.into()); }; }
+macro_rules! g_bail { ($e:expr $(,)*) => { return Err($e.into()) }; }
 macro_rules! g_ensure { ($cond:expr, $e:expr $(,)*) => { if !$cond { g_bail!($e); } }; }
 
 /// Macro that generates a `[T; 3]` from a function on index.
 ///
-/// ```rust
+/// ```rust,ignore
 /// assert_eq!(
 ///     arr_3![i => 2*i],
 ///     [0, 2, 4],
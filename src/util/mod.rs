@@ -11,3 +11,5 @@
 mod macros;
 #[cfg(test)]
 pub(crate) mod test_matrices;
+#[cfg(test)]
+pub(crate) mod test_structures;
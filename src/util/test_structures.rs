@@ -0,0 +1,49 @@
+// Copyright 2018 Michael Lamparski
+// Part of the vasp-poscar crate.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use crate::{Builder, Coords, Poscar, ScaleLine};
+
+/// A builder with a scale factor and a lattice that is neither orthogonal nor
+/// symmetric; the caller supplies the sites.
+///
+/// Mixing up rows and columns of the lattice, or forgetting the scale, will
+/// generally give the wrong answer on this cell.
+pub(crate) fn skewed_builder() -> Builder {
+    let mut builder = Builder::new();
+    builder
+        .scale(ScaleLine::Factor(2.0))
+        .lattice_vectors(&[[2.0, 0.0, 0.0], [1.0, 2.0, 0.0], [0.0, 0.5, 3.0]]);
+    builder
+}
+
+/// Two sites of one group in an oblique cell, stored in Cartesian coordinates,
+/// with velocities in direct coordinates.
+pub(crate) fn oblique_pair() -> Poscar {
+    Builder::new()
+        .lattice_vectors(&[[1.0, 2.0, 0.5], [-1.5, 0.25, 2.0], [0.5, -2.0, 3.0]])
+        .group_counts(vec![2])
+        .positions(Coords::Cart(vec![[0.5, 0.5, 0.5], [1.0, -1.0, 2.0]]))
+        .velocities(Coords::Frac(vec![[0.1, 0.2, 0.3], [-0.1, 0.0, 0.2]]))
+        .build().unwrap()
+}
+
+/// Five sites of three species in a triclinic cell, with no symmetry at all.
+pub(crate) fn low_symmetry() -> Poscar {
+    Builder::new()
+        .lattice_vectors(&[[4.0, 0.0, 0.0], [0.7, 4.5, 0.0], [-0.4, 0.9, 5.2]])
+        .site_symbols(vec!["Ti", "O", "O", "Sr", "O"])
+        .positions(Coords::Frac(vec![
+            [0.03, 0.11, 0.07],
+            [0.52, 0.08, 0.13],
+            [0.09, 0.57, 0.21],
+            [0.48, 0.51, 0.55],
+            [0.12, 0.16, 0.61],
+        ]))
+        .build().unwrap()
+}
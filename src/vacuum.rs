@@ -0,0 +1,284 @@
+// Copyright 2018 Michael Lamparski
+// Part of the vasp-poscar crate.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use crate::{Poscar, Coords, ScaleLine};
+use crate::math::{cross_f64, det_f64, norm_3};
+
+/// # Vacuum
+///
+/// These methods treat the structure as a finite block along one lattice vector
+/// (e.g. a slab along `c`, or a molecule along all three), surrounded by vacuum.
+/// The block is found by looking for the largest gap between the sites along that
+/// lattice vector, which is taken to be the vacuum, and periodic images are chosen
+/// so that the block is contiguous.
+///
+/// Gaps are measured perpendicular to the other two lattice vectors, so these work
+/// for cells where the vector is not perpendicular to the others.  Note that if the
+/// vacuum is made thinner than some gap inside the block, that gap will be taken as
+/// the vacuum from then on.
+impl Poscar {
+    /// Get the thickness of the vacuum along lattice vector `axis`.
+    ///
+    /// This is the distance between the planes (spanned by the other two lattice
+    /// vectors) that bound the block of sites, in scaled units.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `axis` is not `0`, `1`, or `2`.
+    pub fn vacuum(&self, axis: usize) -> f64
+    {
+        let block = Block::find(self, axis);
+        (1.0 - block.extent) * self.plane_spacing(axis)
+    }
+
+    /// Change the length of lattice vector `axis` so that the vacuum along it
+    /// becomes `thickness` (in scaled units), without moving the sites.
+    ///
+    /// The lattice vector keeps its direction.  Sites keep their Cartesian positions,
+    /// except that periodic images are first chosen so that the block of sites is
+    /// contiguous.  Sites are not wrapped afterwards, so some may lie outside the cell;
+    /// use [`center`] to bring them back.
+    ///
+    /// Direct positions and velocities are rescaled along `axis` as needed.  For a
+    /// `ScaleLine::Volume`, the volume is changed rather than the scale factor.
+    ///
+    /// ```rust
+    /// # fn main() -> Result<(), failure::Error> {Ok({
+    /// use vasp_poscar::{Builder, Coords};
+    ///
+    /// // a sheet of atoms near z = 0, with periodic images on both sides of the boundary
+    /// let mut poscar =
+    ///     Builder::new()
+    ///     .lattice_vectors(&[[3.0, 0.0, 0.0], [0.0, 3.0, 0.0], [0.0, 0.0, 10.0]])
+    ///     .positions(Coords::Frac(vec![[0.0, 0.0, 0.05], [0.5, 0.5, 0.95]]))
+    ///     .build()?;
+    /// assert!((poscar.vacuum(2) - 9.0).abs() < 1e-12);
+    ///
+    /// poscar.set_vacuum(2, 19.0)?;
+    /// poscar.center(2);
+    /// assert!((poscar.scaled_lattice_vectors()[2][2] - 20.0).abs() < 1e-12);
+    /// assert!((poscar.vacuum(2) - 19.0).abs() < 1e-12);
+    /// assert!((poscar.frac_positions()[0][2] - 0.525).abs() < 1e-12);
+    /// # })}
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Fails (leaving the Poscar unchanged) if `thickness` is negative, or if the new
+    /// lattice vector would have zero length.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `axis` is not `0`, `1`, or `2`.
+    ///
+    /// [`center`]: #method.center
+    pub fn set_vacuum(&mut self, axis: usize, thickness: f64) -> Result<(), failure::Error>
+    {
+        ensure!(thickness >= 0.0, "vacuum thickness must be non-negative: {}", thickness);
+
+        let block = Block::find(self, axis);
+        let spacing = self.plane_spacing(axis);
+        let new_spacing = block.extent * spacing + thickness;
+        ensure!(new_spacing > 0.0, "cannot set zero vacuum around a single plane of sites");
+
+        let fracs = block.unwrapped_fracs(self, 0.0);
+        self.set_frac_positions(fracs);
+
+        // Stretch the lattice vector.  Cartesian data stays fixed, so the
+        // corresponding direct coordinates shrink.
+        let ratio = new_spacing / spacing;
+        let rescale = |coords: &mut Coords| {
+            if let Coords::Frac(v) = coords {
+                for x in v {
+                    x[axis] /= ratio;
+                }
+            }
+        };
+        rescale(&mut self.0.positions);
+        if let Some(velocities) = self.0.velocities.as_mut() {
+            rescale(velocities);
+        }
+        for x in &mut self.0.lattice_vectors[axis] {
+            *x *= ratio;
+        }
+        if let ScaleLine::Volume(ref mut volume) = self.0.scale {
+            *volume *= ratio;
+        }
+        Ok(())
+    }
+
+    /// Translate all sites along lattice vector `axis` so that the block of
+    /// sites is centered in the cell.
+    ///
+    /// Periodic images are chosen so that the block is contiguous, after which
+    /// the midpoint between its lowest and highest sites is moved to a direct
+    /// coordinate of `0.5`.  Thus, all sites end up inside the cell along `axis`.
+    /// Other coordinates and the velocities are not modified.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `axis` is not `0`, `1`, or `2`.
+    pub fn center(&mut self, axis: usize)
+    {
+        let block = Block::find(self, axis);
+        let shift = 0.5 - (block.start + 0.5 * block.extent);
+        let fracs = block.unwrapped_fracs(self, shift);
+        self.set_frac_positions(fracs);
+    }
+
+    // Distance between adjacent lattice planes spanned by the other two vectors.
+    fn plane_spacing(&self, axis: usize) -> f64
+    {
+        let lattice = self.scaled_lattice();
+        let cross = cross_f64(&lattice[(axis + 1) % 3], &lattice[(axis + 2) % 3]);
+        det_f64(&lattice).abs() / norm_3(&cross)
+    }
+}
+
+// The sites, viewed as a contiguous interval of direct coordinates along one axis.
+struct Block {
+    axis: usize,
+    // direct coordinate of the lowest site, in [0, 1]
+    start: f64,
+    // direct coordinate of the highest site minus that of the lowest
+    extent: f64,
+}
+
+impl Block {
+    fn find(poscar: &Poscar, axis: usize) -> Block
+    {
+        assert!(axis < 3, "axis out of range: {}", axis);
+
+        let mut values: Vec<f64> = poscar.frac_positions().iter().map(|x| x[axis].rem_euclid(1.0)).collect();
+        values.sort_by(|a, b| a.partial_cmp(b).expect("NaN position"));
+
+        let (first, last) = match (values.first(), values.last()) {
+            (Some(&first), Some(&last)) => (first, last),
+            _ => return Block { axis, start: 0.0, extent: 0.0 },
+        };
+        // The gap that wraps around the boundary is considered first,
+        // so that a block which doesn't cross the boundary is left alone.
+        let mut best = (first + 1.0 - last, first);
+        for pair in values.windows(2) {
+            let gap = pair[1] - pair[0];
+            if gap > best.0 {
+                best = (gap, pair[1]);
+            }
+        }
+        let (gap, start) = best;
+        Block { axis, start, extent: 1.0 - gap }
+    }
+
+    // Direct positions with images chosen so that the block starts at `start + shift`.
+    fn unwrapped_fracs(&self, poscar: &Poscar, shift: f64) -> Vec<[f64; 3]>
+    {
+        let mut fracs = poscar.frac_positions().into_owned();
+        for x in &mut fracs {
+            let value = x[self.axis].rem_euclid(1.0);
+            let offset = (value - self.start).rem_euclid(1.0);
+            // (guard against roundoff near either end of the block)
+            let offset = if offset > 0.5 * (1.0 + self.extent) { offset - 1.0 } else { offset };
+            x[self.axis] = self.start + offset + shift;
+        }
+        fracs
+    }
+}
+
+#[cfg(test)]
+#[deny(unused)]
+mod tests {
+    use super::*;
+    use crate::Builder;
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-10, "{} != {}", a, b);
+    }
+
+    // a skewed cell with a sheet crossing the boundary of the third axis
+    fn sheet() -> Poscar {
+        Builder::new()
+            .lattice_vectors(&[[3.0, 0.0, 0.0], [1.0, 3.0, 0.0], [1.0, 2.0, 10.0]])
+            .group_counts(vec![3])
+            .positions(Coords::Frac(vec![[0.0, 0.0, 0.9], [0.5, 0.0, 0.0], [0.0, 0.5, 0.1]]))
+            .velocities(Coords::Frac(vec![[0.0, 0.0, 0.1], [0.0; 3], [0.0; 3]]))
+            .build().unwrap()
+    }
+
+    #[test]
+    fn skewed_cell() {
+        for &storage in &[Coords::Frac(()), Coords::Cart(())] {
+            for &volume_scale in &[false, true] {
+                let mut raw = sheet().into_raw();
+                if storage == Coords::Cart(()) {
+                    let lattice = raw.lattice_vectors;
+                    raw.positions = raw.positions.into_tag(&lattice, storage);
+                    raw.velocities = raw.velocities.map(|v| v.into_tag(&lattice, storage));
+                }
+                if volume_scale {
+                    raw.scale = ScaleLine::Volume(2.0 * det_f64(&raw.lattice_vectors));
+                }
+                let mut poscar = raw.validate().unwrap();
+                let spacing = poscar.scaled_lattice()[2][2];
+                assert_close(poscar.vacuum(2), 0.8 * spacing);
+
+                let before = poscar.clone();
+                poscar.set_vacuum(2, 20.0).unwrap();
+                assert_close(poscar.vacuum(2), 20.0);
+                assert_close(poscar.scaled_lattice()[2][2], 0.2 * spacing + 20.0);
+                assert_eq!(poscar.0.positions.tag(), storage);
+
+                // the sites above the boundary were unwrapped, but nothing else moved
+                let old = before.scaled_cart_positions();
+                let new = poscar.scaled_cart_positions();
+                let old_c = before.scaled_lattice()[2];
+                for k in 0..3 {
+                    assert_close(new[0][k], old[0][k]);
+                    assert_close(new[1][k], old[1][k] + old_c[k]);
+                    assert_close(new[2][k], old[2][k] + old_c[k]);
+                }
+                let old_v = before.cart_velocities().unwrap();
+                let new_v = poscar.cart_velocities().unwrap();
+                for (a, b) in zip!(old_v.iter(), new_v.iter()) {
+                    for k in 0..3 {
+                        assert_close(a[k], b[k]);
+                    }
+                }
+
+                poscar.center(2);
+                let fracs = poscar.frac_positions();
+                let spread = 0.2 * spacing / (0.2 * spacing + 20.0);
+                assert_close(fracs[0][2], 0.5 - 0.5 * spread);
+                assert_close(fracs[1][2], 0.5);
+                assert_close(fracs[2][2], 0.5 + 0.5 * spread);
+                assert_close(poscar.vacuum(2), 20.0);
+            }
+        }
+    }
+
+    #[test]
+    fn shrink() {
+        let mut poscar = sheet();
+        poscar.center(2);
+        poscar.set_vacuum(2, 1.5).unwrap();
+        assert_close(poscar.vacuum(2), 1.5);
+        assert!(poscar.set_vacuum(2, -1.0).is_err());
+
+        // a single plane needs some vacuum
+        let mut poscar = {
+            Builder::new()
+                .dummy_lattice_vectors()
+                .positions(Coords::Frac(vec![[0.0, 0.0, 0.5], [0.5, 0.5, 0.5]]))
+                .build().unwrap()
+        };
+        assert_close(poscar.vacuum(2), 1.0);
+        assert!(poscar.set_vacuum(2, 0.0).is_err());
+        poscar.set_vacuum(2, 2.0).unwrap();
+        assert_close(poscar.scaled_lattice()[2][2], 2.0);
+    }
+}